@@ -15,13 +15,14 @@
 //! Configuration file management
 
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
 use std::path::PathBuf;
 
 use core::config::MinerConfig;
 use core::MinerError;
 use cuckoo::PluginConfig;
+use hardware::Hardware;
 use toml;
 use types::{ConfigError, ConfigMembers, GlobalConfig};
 use util::{LoggingConfig, LOGGER};
@@ -55,8 +56,11 @@ impl Default for GlobalConfig {
 }
 
 impl GlobalConfig {
-	/// Copy the epic-miner.toml from the default locations to the current folder
-	pub fn copy_config_file(&mut self) -> Result<(), ConfigError> {
+	/// Create an epic-miner.toml in the current folder from the one in the
+	/// default locations, with the settings that depend on the hardware
+	/// (RandomX threads and large pages, CPU plugin, GPUs) set for this
+	/// machine
+	pub fn create_config_file(&mut self) -> Result<(), ConfigError> {
 		let mut config_path_new = env::current_dir().unwrap();
 		config_path_new.push(CONFIG_FILE_NAME);
 		if config_path_new.exists() {
@@ -67,24 +71,57 @@ impl GlobalConfig {
 			.config_file_path
 			.clone()
 			.unwrap_or(PathBuf::from("".to_owned()));
-		std::fs::copy(&config_path_original, &config_path_new).map_err(|e| {
+		let template = fs::read_to_string(&config_path_original).map_err(|e| {
+			ConfigError::FileIOError(
+				config_path_original.display().to_string(),
+				format!("Unable to read the file! {:?}:", e),
+			)
+		})?;
+		let members: ConfigMembers = toml::from_str(&template).map_err(|e| {
+			ConfigError::ParseError(
+				config_path_original.display().to_string(),
+				format!("{}", e),
+			)
+		})?;
+
+		let plugin_dir = members
+			.mining
+			.miner_plugin_dir
+			.clone()
+			.unwrap_or_else(|| {
+				let mut dir = env::current_exe().unwrap();
+				dir.pop();
+				dir.push("plugins");
+				dir
+			});
+		let hardware = Hardware::detect(&plugin_dir);
+		fs::write(
+			&config_path_new,
+			hardware.configure(&template, &members.mining),
+		)
+		.map_err(|e| {
 			ConfigError::FileIOError(
 				format!(
-					"Unable to copy the file {} to {}! :",
-					config_path_original.display(),
+					"Unable to write the file {}! :",
 					config_path_new.display()
 				),
 				format!("{:?}", e),
 			)
 		})?;
 		println!(
-			"Successfully copied the file {} to {}",
-			config_path_original.display(),
-			config_path_new.display()
+			"Successfully created the file {} from {} for this machine",
+			config_path_new.display(),
+			config_path_original.display()
 		);
 		Ok(())
 	}
 
+	/// Former name of `create_config_file`
+	#[deprecated(note = "use create_config_file")]
+	pub fn copy_config_file(&mut self) -> Result<(), ConfigError> {
+		self.create_config_file()
+	}
+
 	fn derive_config_location(&mut self) -> Result<(), ConfigError> {
 		// First, check working directory
 		let mut config_path = env::current_dir().unwrap();
@@ -0,0 +1,433 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Detects the hardware `new_config` runs on and fills in the settings of
+//! the template epic-miner.toml that depend on it
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use core::config::{EpicMinerPluginConfig, MinerConfig};
+use util;
+
+/// Memory a RandomX miner needs for its dataset and cache, in MiB
+const RANDOMX_MEMORY: u64 = 2080 + 256;
+
+/// L3 cache each RandomX thread wants for its scratchpad, in KiB
+const RANDOMX_SCRATCHPAD: u64 = 2048;
+
+/// A GPU found through OpenCL
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpuDevice {
+	/// Name of the OpenCL platform, i.e. the driver
+	pub platform: String,
+	/// Index of the device among the GPUs of its platform
+	pub index: u32,
+	/// Device name
+	pub name: String,
+	/// Global memory in MiB
+	pub memory: u64,
+}
+
+/// What was found out about the machine
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Hardware {
+	/// Hardware threads
+	pub cpu_threads: u32,
+	/// AES-NI support
+	pub aes: bool,
+	/// AVX2 support
+	pub avx2: bool,
+	/// Available memory in MiB, where it could be read
+	pub memory_available: Option<u64>,
+	/// Free huge pages in MiB, where they could be read
+	pub hugepages_free: Option<u64>,
+	/// L3 cache in KiB, where it could be read
+	pub l3_cache: Option<u64>,
+	/// OpenCL GPUs, none when built without OpenCL
+	pub gpus: Option<Vec<GpuDevice>>,
+	/// Names of the cuckoo plugins installed in the plugin dir
+	pub plugins: Vec<String>,
+}
+
+impl Hardware {
+	/// Looks at the machine, plugins are looked for in `plugin_dir`
+	pub fn detect(plugin_dir: &Path) -> Hardware {
+		let meminfo = fs::read_to_string("/proc/meminfo").unwrap_or_default();
+		let meminfo_kib = |key: &str| {
+			meminfo
+				.lines()
+				.find(|l| l.starts_with(key))
+				.and_then(|l| l[key.len()..].split_whitespace().next())
+				.and_then(|v| v.parse::<u64>().ok())
+		};
+		let hugepages_free = match (meminfo_kib("HugePages_Free:"), meminfo_kib("Hugepagesize:")) {
+			(Some(pages), Some(size)) => Some(pages * size / 1024),
+			_ => None,
+		};
+		Hardware {
+			cpu_threads: util::cpu_threads(),
+			aes: cpu_feature_aes(),
+			avx2: cpu_feature_avx2(),
+			memory_available: meminfo_kib("MemAvailable:").map(|kib| kib / 1024),
+			hugepages_free: hugepages_free,
+			l3_cache: fs::read_to_string("/sys/devices/system/cpu/cpu0/cache/index3/size")
+				.ok()
+				.and_then(|s| s.trim().trim_end_matches('K').parse::<u64>().ok()),
+			gpus: opencl_gpus(),
+			plugins: installed_plugins(plugin_dir),
+		}
+	}
+
+	/// RandomX threads, one per hardware thread as far as the L3 cache
+	/// holds their scratchpads
+	pub fn randomx_threads(&self) -> u32 {
+		let threads = match self.l3_cache {
+			Some(l3) => self.cpu_threads.min((l3 / RANDOMX_SCRATCHPAD) as u32),
+			None => self.cpu_threads,
+		};
+		threads.max(1)
+	}
+
+	/// Whether the RandomX dataset fits in the free huge pages
+	pub fn randomx_large_pages(&self) -> bool {
+		self.hugepages_free
+			.map_or(false, |free| free >= RANDOMX_MEMORY)
+	}
+
+	/// The installed variant of `configured` this CPU can run, avx2 when
+	/// supported, compat otherwise. Falls back to another installed CPU
+	/// plugin, `None` when there's none this CPU can run.
+	pub fn cpu_plugin(&self, configured: &str) -> Option<String> {
+		let (wanted, other) = if self.avx2 {
+			("_avx2_", "_compat_")
+		} else {
+			("_compat_", "_avx2_")
+		};
+		let preferred = configured.replace(other, wanted);
+		let installed = |name: &str| self.plugins.iter().any(|p| p == name);
+		let usable = |name: &str| name.contains("_compat_") || self.avx2 && name.contains("_avx2_");
+		if installed(&preferred) {
+			Some(preferred)
+		} else if installed(configured) && usable(configured) {
+			Some(configured.to_owned())
+		} else if let Some(p) = self
+			.plugins
+			.iter()
+			.find(|p| usable(p) && p.contains(wanted))
+		{
+			Some(p.clone())
+		} else if let Some(p) = self.plugins.iter().find(|p| usable(p)) {
+			Some(p.clone())
+		} else if installed("cpu_cuckatoo") {
+			Some("cpu_cuckatoo".to_owned())
+		} else {
+			None
+		}
+	}
+
+	/// One line summary for the top of the generated file
+	fn summary(&self) -> String {
+		let mut parts = vec![format!("{} CPU threads", self.cpu_threads)];
+		parts.push(format!("AES-NI {}", if self.aes { "yes" } else { "no" }));
+		parts.push(format!("AVX2 {}", if self.avx2 { "yes" } else { "no" }));
+		if let Some(m) = self.memory_available {
+			parts.push(format!("{} MiB memory available", m));
+		}
+		if let Some(h) = self.hugepages_free {
+			parts.push(format!("{} MiB in free huge pages", h));
+		}
+		if let Some(l3) = self.l3_cache {
+			parts.push(format!("{} KiB L3 cache", l3));
+		}
+		if let Some(ref gpus) = self.gpus {
+			parts.push(format!("{} OpenCL GPUs", gpus.len()));
+		}
+		parts.join(", ")
+	}
+
+	/// Fills the settings of `template` that depend on the machine in,
+	/// leaving its comments alone. `config` is the parsed template.
+	pub fn configure(&self, template: &str, config: &MinerConfig) -> String {
+		let mut lines: Vec<String> = template.lines().map(|l| l.to_owned()).collect();
+
+		let rx = &config.randomx_config;
+		replace_table(
+			&mut lines,
+			"[mining.randomx_config]",
+			vec![
+				format!("threads = {}", self.randomx_threads()),
+				format!("jit = {}", rx.jit),
+				format!("large_pages = {}", self.randomx_large_pages()),
+				format!("hard_aes = {}", self.aes),
+			],
+		);
+
+		if let Some(ref gpus) = self.gpus {
+			let mut gpu_lines = vec![];
+			for (i, gpu) in gpus.iter().enumerate() {
+				if i > 0 {
+					gpu_lines.push(String::new());
+					gpu_lines.push("[[mining.gpu_config]]".to_owned());
+				}
+				gpu_lines.push(format!(
+					"# {} on {}, {} MiB",
+					gpu.name, gpu.platform, gpu.memory
+				));
+				gpu_lines.push(format!("device = {}", gpu.index));
+				gpu_lines.push("driver = 2".to_owned());
+			}
+			if !gpu_lines.is_empty() {
+				replace_table(&mut lines, "[[mining.gpu_config]]", gpu_lines);
+			}
+		}
+
+		if let Some(plugin) = config.miner_plugin_config.first() {
+			let header = "[[mining.miner_plugin_config]]";
+			let name = if plugin.plugin_name.contains("_cpu_") {
+				self.cpu_plugin(&plugin.plugin_name)
+			} else {
+				Some(plugin.plugin_name.clone())
+			};
+			match name {
+				Some(name) => replace_table(&mut lines, header, self.plugin_lines(&name, plugin)),
+				// written out anyway, for when the plugin gets installed
+				None => {
+					let mut table = vec![
+						format!(
+							"# WARNING: no CPU plugin this machine can run was found in the plugin \
+							 dir, install {} and uncomment it",
+							plugin.plugin_name
+						),
+						format!("#{}", header),
+					];
+					for line in self.plugin_lines(&plugin.plugin_name, plugin) {
+						table.push(format!("#{}", line));
+					}
+					replace_table_and_header(&mut lines, header, table);
+				}
+			}
+		}
+
+		let mut out = format!(
+			"# Generated by epic-miner new_config for this machine:\n# {}\n\n",
+			self.summary()
+		);
+		if self.memory_available.map_or(false, |m| m < RANDOMX_MEMORY) {
+			out.push_str(&format!(
+				"# Less than the {} MiB RandomX needs are available, expect it to swap\n\n",
+				RANDOMX_MEMORY
+			));
+		}
+		out.push_str(&lines.join("\n"));
+		out.push('\n');
+		out
+	}
+
+	fn plugin_lines(&self, name: &str, plugin: &EpicMinerPluginConfig) -> Vec<String> {
+		let mut params: BTreeMap<String, u32> = plugin
+			.parameters
+			.clone()
+			.map(|p| p.into_iter().collect())
+			.unwrap_or_default();
+		if params.contains_key("nthreads") {
+			params.insert("nthreads".to_owned(), self.cpu_threads);
+		}
+		let mut lines = vec![format!("plugin_name = \"{}\"", name)];
+		if !params.is_empty() {
+			lines.push("[mining.miner_plugin_config.parameters]".to_owned());
+			for (k, v) in params {
+				lines.push(format!("{} = {}", k, v));
+			}
+		}
+		lines
+	}
+}
+
+/// Replaces the lines after the first uncommented `header` up to the next
+/// blank line
+fn replace_table(lines: &mut Vec<String>, header: &str, body: Vec<String>) {
+	if let Some(start) = lines.iter().position(|l| l.trim() == header) {
+		let end = lines[start + 1..]
+			.iter()
+			.position(|l| l.trim().is_empty())
+			.map_or(lines.len(), |n| start + 1 + n);
+		lines.splice(start + 1..end, body);
+	}
+}
+
+/// Same as `replace_table`, the header is replaced as well
+fn replace_table_and_header(lines: &mut Vec<String>, header: &str, table: Vec<String>) {
+	if let Some(start) = lines.iter().position(|l| l.trim() == header) {
+		replace_table(lines, header, vec![]);
+		lines.splice(start..start + 1, table);
+	}
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn cpu_feature_aes() -> bool {
+	is_x86_feature_detected!("aes")
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn cpu_feature_avx2() -> bool {
+	is_x86_feature_detected!("avx2")
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn cpu_feature_aes() -> bool {
+	false
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn cpu_feature_avx2() -> bool {
+	false
+}
+
+#[cfg(feature = "opencl")]
+fn opencl_gpus() -> Option<Vec<GpuDevice>> {
+	use ocl::flags::DEVICE_TYPE_GPU;
+	use ocl::{Device, Platform};
+
+	let mut gpus = vec![];
+	for platform in Platform::list() {
+		let platform_name = platform.name().unwrap_or_default();
+		let devices = Device::list(&platform, Some(DEVICE_TYPE_GPU)).unwrap_or_default();
+		for (index, device) in devices.iter().enumerate() {
+			let memory = match device.info(ocl::enums::DeviceInfo::GlobalMemSize) {
+				Ok(ocl::enums::DeviceInfoResult::GlobalMemSize(bytes)) => bytes / 1024 / 1024,
+				_ => 0,
+			};
+			gpus.push(GpuDevice {
+				platform: platform_name.clone(),
+				index: index as u32,
+				name: device.name().unwrap_or_default(),
+				memory: memory,
+			});
+		}
+	}
+	Some(gpus)
+}
+
+#[cfg(not(feature = "opencl"))]
+fn opencl_gpus() -> Option<Vec<GpuDevice>> {
+	None
+}
+
+fn installed_plugins(plugin_dir: &Path) -> Vec<String> {
+	let mut plugins: Vec<String> = fs::read_dir(plugin_dir)
+		.map(|entries| {
+			entries
+				.filter_map(|e| e.ok())
+				.filter_map(|e| e.file_name().into_string().ok())
+				.filter_map(|f| f.rfind(".cuckooplugin").map(|i| f[..i].to_owned()))
+				.collect()
+		})
+		.unwrap_or_default();
+	plugins.sort();
+	plugins
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use toml;
+	use types::ConfigMembers;
+
+	const TEMPLATE: &str = include_str!("../../epic-miner.toml");
+
+	fn machine() -> Hardware {
+		Hardware {
+			cpu_threads: 16,
+			aes: true,
+			avx2: true,
+			memory_available: Some(30000),
+			hugepages_free: Some(4096),
+			l3_cache: Some(16384),
+			gpus: Some(vec![
+				GpuDevice {
+					platform: "AMD Accelerated Parallel Processing".to_owned(),
+					index: 0,
+					name: "gfx1030".to_owned(),
+					memory: 16368,
+				},
+				GpuDevice {
+					platform: "AMD Accelerated Parallel Processing".to_owned(),
+					index: 1,
+					name: "gfx1030".to_owned(),
+					memory: 16368,
+				},
+				GpuDevice {
+					platform: "NVIDIA CUDA".to_owned(),
+					index: 0,
+					name: "GeForce GTX 1080".to_owned(),
+					memory: 8119,
+				},
+			]),
+			plugins: vec![
+				"cuckatoo_lean_cpu_avx2_19".to_owned(),
+				"cuckatoo_lean_cpu_compat_19".to_owned(),
+			],
+		}
+	}
+
+	fn configure(hw: &Hardware) -> ConfigMembers {
+		let template: ConfigMembers = toml::from_str(TEMPLATE).unwrap();
+		toml::from_str(&hw.configure(TEMPLATE, &template.mining)).unwrap()
+	}
+
+	#[test]
+	fn configure_template() {
+		let config = configure(&machine()).mining;
+		assert_eq!(config.randomx_config.threads, 8);
+		assert!(config.randomx_config.large_pages);
+		assert!(config.randomx_config.hard_aes);
+		// device indexes count per platform
+		let devices: Vec<u32> = config.gpu_config.iter().map(|g| g.device).collect();
+		assert_eq!(devices, vec![0, 1, 0]);
+		assert_eq!(config.miner_plugin_config.len(), 1);
+		let plugin = &config.miner_plugin_config[0];
+		assert_eq!(plugin.plugin_name, "cuckatoo_lean_cpu_avx2_19");
+		assert_eq!(plugin.parameters.as_ref().unwrap()["nthreads"], 16);
+	}
+
+	#[test]
+	fn configure_older_machine() {
+		let mut hw = machine();
+		hw.aes = false;
+		hw.avx2 = false;
+		hw.hugepages_free = Some(0);
+		hw.l3_cache = None;
+		hw.gpus = None;
+		hw.plugins = vec!["cuckatoo_lean_cpu_avx2_19".to_owned()];
+		let template: ConfigMembers = toml::from_str(TEMPLATE).unwrap();
+		let generated = hw.configure(TEMPLATE, &template.mining);
+		let config = toml::from_str::<ConfigMembers>(&generated).unwrap().mining;
+		assert_eq!(config.randomx_config.threads, 16);
+		assert!(!config.randomx_config.large_pages);
+		assert!(!config.randomx_config.hard_aes);
+		assert_eq!(config.gpu_config.len(), 1);
+		// the avx2 plugin can't run here, the compat one is only suggested
+		assert_eq!(hw.cpu_plugin("cuckatoo_lean_cpu_compat_19"), None);
+		assert!(config.miner_plugin_config.is_empty());
+		assert!(generated.contains("# WARNING: no CPU plugin"));
+		assert!(generated.contains("#plugin_name = \"cuckatoo_lean_cpu_compat_19\""));
+
+		// a compat plugin is installed too
+		hw.plugins.push("cuckatoo_lean_cpu_compat_19".to_owned());
+		let plugin = &configure(&hw).mining.miner_plugin_config[0];
+		assert_eq!(plugin.plugin_name, "cuckatoo_lean_cpu_compat_19");
+	}
+}
@@ -31,10 +31,14 @@ extern crate slog;
 extern crate epic_miner_core as core;
 extern crate epic_miner_util as util;
 extern crate cuckoo_miner as cuckoo;
+#[cfg(feature = "opencl")]
+extern crate ocl;
 
 mod config;
+mod hardware;
 mod types;
 
+pub use hardware::{GpuDevice, Hardware};
 pub use types::{ConfigError, ConfigMembers, GlobalConfig};
 // pub use config::read_configs;
 
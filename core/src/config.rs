@@ -2,13 +2,13 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use types::Algorithm;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct GpuConfig {
 	pub device: u32,
 	pub driver: u8,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct RxConfig {
 	#[serde(default = "default_threads")]
 	pub threads: u64,
@@ -28,6 +28,58 @@ fn default_rx_bool() -> bool {
 	false
 }
 
+fn default_failover_retries() -> u32 {
+	3
+}
+
+fn default_stall_timeout() -> u64 {
+	300
+}
+
+fn default_primary_retry_interval() -> u64 {
+	60
+}
+
+fn default_request_timeout() -> u64 {
+	60
+}
+
+fn default_keepalive_interval() -> u64 {
+	60
+}
+
+fn default_idle_timeout() -> u64 {
+	180
+}
+
+fn default_retry_min() -> u64 {
+	1
+}
+
+fn default_retry_max() -> u64 {
+	60
+}
+
+fn default_share_queue_size() -> usize {
+	32
+}
+
+fn default_node_poll_interval() -> u64 {
+	2
+}
+
+fn default_proxy_listen_addr() -> String {
+	"0.0.0.0:3416".to_owned()
+}
+
+fn default_remote_dns() -> bool {
+	true
+}
+
+fn default_config_reload_interval() -> u64 {
+	5
+}
+
 impl Default for RxConfig {
 	fn default() -> Self {
 		RxConfig {
@@ -40,7 +92,7 @@ impl Default for RxConfig {
 }
 
 /// CuckooMinerPlugin configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct EpicMinerPluginConfig {
 	/// The type of plugin to load (i.e. filters on filename)
 	pub plugin_name: String,
@@ -58,12 +110,113 @@ impl Default for EpicMinerPluginConfig {
 	}
 }
 
+/// Wire protocol spoken with the stratum server
+#[derive(Copy, Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum StratumProtocol {
+	/// the Epic node's own protocol (login, getjobtemplate, job, submit)
+	Epic,
+	/// mining.subscribe, mining.authorize, mining.notify, mining.submit
+	Stratum,
+}
+
+impl Default for StratumProtocol {
+	fn default() -> StratumProtocol {
+		StratumProtocol::Epic
+	}
+}
+
+/// TLS settings used when connecting to a stratum server
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct TlsConfig {
+	/// name used for SNI and certificate validation, defaults to the
+	/// host part of the server address
+	pub server_name: Option<String>,
+
+	/// PEM file with additional CA certificates to trust
+	pub ca_file: Option<String>,
+
+	/// PEM client certificate presented to the server
+	pub client_cert_file: Option<String>,
+
+	/// PEM private key for the client certificate
+	pub client_key_file: Option<String>,
+
+	/// SHA-256 fingerprint (hex) the server certificate must match, the
+	/// usual chain validation is skipped when set
+	pub cert_fingerprint: Option<String>,
+
+	/// accept any server certificate, only meant for test pools
+	#[serde(default)]
+	pub accept_invalid_certs: bool,
+}
+
+/// Kind of proxy outbound stratum connections go through
+#[derive(Copy, Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ProxyKind {
+	Socks5,
+	/// an HTTP proxy supporting the CONNECT method
+	Http,
+}
+
+/// Proxy to reach the stratum servers through
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProxyConfig {
+	pub kind: ProxyKind,
+
+	/// address of the proxy (host:port)
+	pub addr: String,
+
+	/// credentials, if the proxy asks for them
+	pub username: Option<String>,
+	pub password: Option<String>,
+
+	/// let the proxy resolve the stratum server's host name instead of
+	/// resolving it locally, needed for Tor
+	#[serde(default = "default_remote_dns")]
+	pub remote_dns: bool,
+}
+
+/// A stratum server the client can connect to
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StratumServerConfig {
+	/// address of the stratum server (host:port), or a ws:// or wss:// URL
+	pub addr: String,
+
+	/// login for the stratum server
+	pub login: Option<String>,
+
+	/// password for the stratum server
+	pub password: Option<String>,
+
+	/// whether tls is enabled for the stratum server
+	pub tls_enabled: Option<bool>,
+
+	/// TLS settings for the stratum server
+	pub tls: Option<TlsConfig>,
+
+	/// protocol spoken by the stratum server
+	pub protocol: Option<StratumProtocol>,
+
+	/// proxy the stratum server is reached through
+	pub proxy: Option<ProxyConfig>,
+}
+
 /// basic mining configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct MinerConfig {
 	/// Algorithm will be use to miner
 	pub algorithm: Option<Algorithm>,
 
+	/// algorithms to mine at the same time, each with its own stratum
+	/// connection, replaces `algorithm` when not empty
+	#[serde(default)]
+	pub algorithms: Vec<Algorithm>,
+
+	/// keep the miners of all `algorithms` ready but only mine the one
+	/// the jobs of a single stratum connection ask for
+	#[serde(default)]
+	pub follow_algorithm: bool,
+
 	pub randomx_config: RxConfig,
 
 	/// Whether to run the tui
@@ -81,10 +234,107 @@ pub struct MinerConfig {
 	/// whether tls is enabled for the stratum server
 	pub stratum_server_tls_enabled: Option<bool>,
 
+	/// TLS settings for the stratum server
+	#[serde(default)]
+	pub stratum_tls: TlsConfig,
+
+	/// protocol spoken by the stratum server
+	#[serde(default)]
+	pub stratum_protocol: StratumProtocol,
+
+	/// proxy the stratum servers are reached through, connections are
+	/// direct when unset
+	#[serde(default)]
+	pub stratum_proxy: Option<ProxyConfig>,
+
+	/// backup stratum servers, tried in order when the primary one fails
+	#[serde(default)]
+	pub stratum_backup_servers: Vec<StratumServerConfig>,
+
+	/// failed reconnects before switching to the next stratum server
+	#[serde(default = "default_failover_retries")]
+	pub stratum_failover_retries: u32,
+
+	/// seconds without a new job before a server is considered stalled,
+	/// 0 disables the check
+	#[serde(default = "default_stall_timeout")]
+	pub stratum_stall_timeout: u64,
+
+	/// seconds between checks whether the primary server is reachable
+	/// again while mining on a backup server
+	#[serde(default = "default_primary_retry_interval")]
+	pub stratum_primary_retry_interval: u64,
+
+	/// seconds to wait for the answer to a stratum request
+	#[serde(default = "default_request_timeout")]
+	pub stratum_request_timeout: u64,
+
+	/// seconds between keepalive requests, 0 disables them
+	#[serde(default = "default_keepalive_interval")]
+	pub stratum_keepalive_interval: u64,
+
+	/// reconnect if nothing was received from the server for this many
+	/// seconds, 0 disables the check
+	#[serde(default = "default_idle_timeout")]
+	pub stratum_idle_timeout: u64,
+
+	/// delay in seconds before the first reconnect attempt, doubled on
+	/// each further failure
+	#[serde(default = "default_retry_min")]
+	pub stratum_retry_min: u64,
+
+	/// upper bound in seconds for the reconnect delay
+	#[serde(default = "default_retry_max")]
+	pub stratum_retry_max: u64,
+
+	/// number of shares kept while the server can't be reached, 0
+	/// disables the queue
+	#[serde(default = "default_share_queue_size")]
+	pub stratum_share_queue_size: usize,
+
+	/// file every line sent to and received from the stratum server is
+	/// appended to, for `epic-miner replay`
+	#[serde(default)]
+	pub stratum_capture_file: Option<String>,
+
+	/// JSON-RPC endpoint of an Epic node's mining API to solo mine
+	/// against instead of the stratum server. Released nodes don't have
+	/// one yet, it takes a node-side change.
+	#[serde(default)]
+	pub node_api_url: Option<String>,
+
+	/// secret of the node API, from the node's .api_secret file
+	#[serde(default)]
+	pub node_api_secret: Option<String>,
+
+	/// seconds between asking the node for a new block template
+	#[serde(default = "default_node_poll_interval")]
+	pub node_poll_interval: u64,
+
+	/// address (host:port) `epic-miner proxy` accepts miners on
+	#[serde(default = "default_proxy_listen_addr")]
+	pub proxy_listen_addr: String,
+
+	/// address (host:port) the HTTP monitoring API listens on, the API is
+	/// disabled when unset
+	#[serde(default)]
+	pub api_listen_addr: Option<String>,
+
+	/// token control requests have to send as `Authorization: Bearer`,
+	/// the control endpoints are disabled when unset
+	#[serde(default)]
+	pub api_token: Option<String>,
+
+	/// seconds between checks whether the config file changed, 0 only
+	/// reloads on SIGHUP or through the control API
+	#[serde(default = "default_config_reload_interval")]
+	pub config_reload_interval: u64,
+
 	/// plugin dir
 	pub miner_plugin_dir: Option<PathBuf>,
 
 	/// Cuckoo miner plugin configuration, one for each plugin
+	#[serde(default)]
 	pub miner_plugin_config: Vec<EpicMinerPluginConfig>,
 
 	// gpu devices
@@ -95,6 +345,8 @@ impl Default for MinerConfig {
 	fn default() -> MinerConfig {
 		MinerConfig {
 			algorithm: Some(Algorithm::RandomX),
+			algorithms: vec![],
+			follow_algorithm: false,
 			randomx_config: RxConfig::default(),
 			run_tui: false,
 			miner_plugin_dir: None,
@@ -103,7 +355,75 @@ impl Default for MinerConfig {
 			stratum_server_login: None,
 			stratum_server_password: None,
 			stratum_server_tls_enabled: None,
+			stratum_tls: TlsConfig::default(),
+			stratum_protocol: StratumProtocol::default(),
+			stratum_proxy: None,
+			stratum_backup_servers: vec![],
+			stratum_failover_retries: default_failover_retries(),
+			stratum_stall_timeout: default_stall_timeout(),
+			stratum_primary_retry_interval: default_primary_retry_interval(),
+			stratum_request_timeout: default_request_timeout(),
+			stratum_keepalive_interval: default_keepalive_interval(),
+			stratum_idle_timeout: default_idle_timeout(),
+			stratum_retry_min: default_retry_min(),
+			stratum_retry_max: default_retry_max(),
+			stratum_share_queue_size: default_share_queue_size(),
+			stratum_capture_file: None,
+			node_api_url: None,
+			node_api_secret: None,
+			node_poll_interval: default_node_poll_interval(),
+			proxy_listen_addr: default_proxy_listen_addr(),
+			api_listen_addr: None,
+			api_token: None,
+			config_reload_interval: default_config_reload_interval(),
 			gpu_config: vec![],
 		}
 	}
 }
+
+impl MinerConfig {
+	/// Algorithms this configuration mines, `algorithms` if any are given
+	/// or else `algorithm`
+	pub fn mined_algorithms(&self) -> Vec<Algorithm> {
+		if self.algorithms.is_empty() {
+			self.algorithm.into_iter().collect()
+		} else {
+			self.algorithms.clone()
+		}
+	}
+
+	/// All configured stratum servers in priority order, the primary
+	/// server first followed by the backups. Settings missing on a backup
+	/// server are taken from the primary one.
+	pub fn stratum_servers(&self) -> Vec<StratumServerConfig> {
+		let primary = StratumServerConfig {
+			addr: self.stratum_server_addr.clone(),
+			login: self.stratum_server_login.clone(),
+			password: self.stratum_server_password.clone(),
+			tls_enabled: self.stratum_server_tls_enabled.clone(),
+			tls: Some(self.stratum_tls.clone()),
+			protocol: Some(self.stratum_protocol),
+			proxy: self.stratum_proxy.clone(),
+		};
+		let mut servers = vec![primary.clone()];
+		for s in &self.stratum_backup_servers {
+			servers.push(StratumServerConfig {
+				addr: s.addr.clone(),
+				login: s.login.clone().or(primary.login.clone()),
+				password: s.password.clone().or(primary.password.clone()),
+				tls_enabled: s.tls_enabled.or(primary.tls_enabled),
+				tls: s.tls.clone().or_else(|| {
+					// server name and pin belong to the primary server only
+					Some(TlsConfig {
+						server_name: None,
+						cert_fingerprint: None,
+						..self.stratum_tls.clone()
+					})
+				}),
+				protocol: s.protocol.or(primary.protocol),
+				proxy: s.proxy.clone().or_else(|| primary.proxy.clone()),
+			});
+		}
+		servers
+	}
+}
@@ -31,6 +31,9 @@ pub enum MinerError {
 
 	/// Error getting stats or stats not implemented
 	StatsError(String),
+
+	/// A solution couldn't be verified
+	VerifyError(String),
 }
 
 impl From<io::Error> for MinerError {
@@ -21,5 +21,6 @@ pub use types::{
     Algorithm,
     AlgorithmParams,
     ControlMessage,
+    DatasetStatus,
     JobSharedData,
     JobSharedDataType};
\ No newline at end of file
@@ -1,6 +1,6 @@
 use config::MinerConfig;
 use errors::MinerError;
-use types::{Solution, Stats};
+use types::{DatasetStatus, Solution, Stats};
 
 pub trait Miner: Send + Sync {
 	/// Creates a new instance of a CuckooMiner with the given configuration.
@@ -54,4 +54,26 @@ pub trait Miner: Send + Sync {
 	fn wait_for_solver_shutdown(&self);
 
 	fn add_epoch(&mut self, start_height: u64, end_height: u64, seed: [u8; 32]) {}
+
+	/// Load state of the datasets the miner mines with, empty for
+	/// algorithms that don't need any
+	fn dataset_status(&self) -> Vec<DatasetStatus> {
+		vec![]
+	}
+
+	/// Sets the leading nonce bytes assigned by the pool, every nonce
+	/// tried from now on starts with them
+	fn set_extranonce(&mut self, extranonce: &[u8]);
+
+	/// Recomputes the proof of work of a solution found for the given job
+	/// and checks it meets the job difficulty. Returns an error when the
+	/// solution can't be checked, e.g. while the data it needs is loading
+	fn verify_solution(
+		&self,
+		height: u64,
+		pre_nonce: &str,
+		post_nonce: &str,
+		difficulty: u64,
+		solution: &Solution,
+	) -> Result<bool, MinerError>;
 }
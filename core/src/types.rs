@@ -118,6 +118,15 @@ impl Stats {
 	}
 }
 
+/// Load state of the dataset for one epoch, only RandomX uses datasets
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DatasetStatus {
+	pub start_height: u64,
+	pub end_height: u64,
+	/// waiting, loading, loaded, running or failed
+	pub state: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Solution(u64, u64, AlgorithmParams);
 
@@ -158,6 +167,9 @@ pub struct JobSharedData {
 	/// target will be put into the output queue
 	pub difficulty: u64,
 
+	/// Leading nonce bytes assigned by the pool, empty if none
+	pub extranonce: Vec<u8>,
+
 	/// Output solutions
 	pub solutions: Vec<Solution>,
 
@@ -173,6 +185,7 @@ impl Default for JobSharedData {
 			pre_nonce: String::from(""),
 			post_nonce: String::from(""),
 			difficulty: 0,
+			extranonce: vec![],
 			solutions: Vec::new(),
 			stats: vec![],
 		}
@@ -187,6 +200,7 @@ impl JobSharedData {
 			pre_nonce: String::from(""),
 			post_nonce: String::from(""),
 			difficulty: 1,
+			extranonce: vec![],
 			solutions: Vec::new(),
 			stats: vec![Stats::default(); num_solvers],
 		}
@@ -36,8 +36,12 @@ pub fn header_data(pre_nonce: &str, post_nonce: &str, nonce: u64) -> (Vec<u8>, u
 	(pre_vec, sec_scaling)
 }
 
-pub fn get_next_header_data(pre_nonce: &str, post_nonce: &str) -> (u64, Vec<u8>, u32) {
-	let nonce: u64 = rand::OsRng::new().unwrap().gen();
+pub fn get_next_header_data(
+	pre_nonce: &str,
+	post_nonce: &str,
+	extranonce: &[u8],
+) -> (u64, Vec<u8>, u32) {
+	let nonce = apply_extranonce(extranonce, rand::OsRng::new().unwrap().gen());
 	let (hd, sec_scaling) = header_data(pre_nonce, post_nonce, nonce);
 	(nonce, hd, sec_scaling)
 }
@@ -58,3 +62,18 @@ pub fn from_hex_string(in_str: &str) -> Vec<u8> {
 pub fn get_random_nonce() -> u64 {
 	rand::OsRng::new().unwrap().gen()
 }
+
+/// Puts the extranonce assigned by the pool into the most significant bytes
+/// of the nonce. The highest free bit is cleared so solvers counting up from
+/// the nonce don't run into the extranonce.
+pub fn apply_extranonce(extranonce: &[u8], nonce: u64) -> u64 {
+	if extranonce.is_empty() {
+		return nonce;
+	}
+	let len = extranonce.len().min(7);
+	let free_bits = 64 - 8 * len as u32;
+	let mut prefix = [0u8; 8];
+	prefix[..len].copy_from_slice(&extranonce[..len]);
+	let prefix = BigEndian::read_u64(&prefix);
+	prefix | (nonce & ((1u64 << (free_bits - 1)) - 1))
+}
@@ -0,0 +1,264 @@
+//! Pure Rust cuckatoo CPU solver, loaded like any other .cuckooplugin so
+//! cuckatoo can be mined without the C++ plugin build.
+
+extern crate blake2_rfc;
+extern crate byteorder;
+extern crate epic_miner_plugin as plugin;
+extern crate epic_miner_util as util;
+extern crate hashbrown;
+extern crate libc;
+
+use blake2_rfc::blake2b::blake2b;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use libc::*;
+use plugin::*;
+use std::io::Cursor;
+use std::io::Error;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime};
+
+pub use self::finder::Graph;
+pub use self::trimmer::{Mode, Trimmer};
+
+mod finder;
+mod trimmer;
+
+const MIN_EDGE_BITS: u8 = 10;
+const MAX_EDGE_BITS: u8 = 31;
+const DEFAULT_EDGE_BITS: u8 = 31;
+
+#[repr(C)]
+struct Solver {
+	trimmer: Trimmer,
+	edge_bits: u8,
+	mutate_nonce: bool,
+}
+
+/// # Safety
+///
+/// `params` must point to valid solver parameters.
+#[no_mangle]
+pub unsafe extern "C" fn create_solver_ctx(params: *mut SolverParams) -> *mut SolverCtx {
+	let mut edge_bits = (*params).edge_bits as u8;
+	if !(MIN_EDGE_BITS..=MAX_EDGE_BITS).contains(&edge_bits) {
+		edge_bits = DEFAULT_EDGE_BITS;
+	}
+	// expand = 0 is lean, anything else mean
+	let mode = match (*params).expand {
+		0 => Mode::Lean,
+		_ => Mode::Mean,
+	};
+	let nthreads = match (*params).nthreads {
+		0 => util::cpu_threads() as usize,
+		n => n as usize,
+	};
+	let trimmer = Trimmer::build(edge_bits, mode, nthreads, (*params).ntrims);
+	let solver = Solver {
+		trimmer,
+		edge_bits,
+		mutate_nonce: (*params).mutate_nonce,
+	};
+	let solver_box = Box::new(solver);
+	let solver_ref = Box::leak(solver_box);
+	solver_ref as *mut Solver as *mut SolverCtx
+}
+
+/// # Safety
+///
+/// `solver_ctx_ptr` must come from `create_solver_ctx` and not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn destroy_solver_ctx(solver_ctx_ptr: *mut SolverCtx) {
+	// create box to clear memory
+	let solver_ptr = mem::transmute::<*mut SolverCtx, *mut Solver>(solver_ctx_ptr);
+	let _solver_box = Box::from_raw(solver_ptr);
+}
+
+/// # Safety
+///
+/// `solver_ctx_ptr` must come from `create_solver_ctx`.
+#[no_mangle]
+pub unsafe extern "C" fn stop_solver(solver_ctx_ptr: *mut SolverCtx) {
+	let solver_ptr = mem::transmute::<*mut SolverCtx, *mut Solver>(solver_ctx_ptr);
+	let solver = &*solver_ptr;
+	solver.trimmer.stop.store(true, Ordering::Relaxed);
+}
+
+/// # Safety
+///
+/// `params` must point to valid solver parameters.
+#[no_mangle]
+pub unsafe extern "C" fn fill_default_params(params: *mut SolverParams) {
+	(*params).nthreads = 0;
+	(*params).ntrims = 0;
+	(*params).expand = 0;
+	(*params).edge_bits = DEFAULT_EDGE_BITS as u32;
+}
+
+/// # Safety
+///
+/// `ctx` must come from `create_solver_ctx`, `header_ptr` must point to
+/// `header_length` bytes and `solutions` and `stats` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn run_solver(
+	ctx: *mut SolverCtx,
+	header_ptr: *const c_uchar,
+	header_length: u32,
+	nonce: u64,
+	_range: u32,
+	solutions: *mut SolverSolutions,
+	stats: *mut SolverStats,
+) -> u32 {
+	let start = SystemTime::now();
+	let solver_ptr = mem::transmute::<*mut SolverCtx, *mut Solver>(ctx);
+	let solver = &*solver_ptr;
+	solver.trimmer.stop.store(false, Ordering::Relaxed);
+	let mut header = Vec::with_capacity(header_length as usize);
+	let r_ptr = header.as_mut_ptr();
+	ptr::copy_nonoverlapping(header_ptr, r_ptr, header_length as usize);
+	header.set_len(header_length as usize);
+	let n = nonce as u32;
+	let k = match set_header_nonce(&header, Some(n), solver.mutate_nonce) {
+		Err(_e) => {
+			return 2;
+		}
+		Ok(v) => v,
+	};
+	let res = match solver.trimmer.run(&k) {
+		Err(_e) => {
+			return 3;
+		}
+		Ok(v) => v,
+	};
+
+	let sols = match Graph::search(&res) {
+		Err(_e) => {
+			return 4;
+		}
+		Ok(v) => v,
+	};
+	let end = SystemTime::now();
+	let elapsed = end.duration_since(start).unwrap();
+	let solutions = &mut *solutions;
+	let stats = &mut *stats;
+	let mut i = 0;
+	solutions.edge_bits = solver.edge_bits as u32;
+	for sol in sols.iter().take(MAX_SOLS) {
+		solutions.sols[i].nonce = nonce;
+		solutions.sols[i]
+			.proof
+			.copy_from_slice(&sol.nonces[..sol.nonces.len()]);
+		i += 1;
+	}
+	solutions.num_sols = i as u32;
+	stats.edge_bits = solver.edge_bits as u32;
+	stats.device_id = 0;
+	let name_bytes = solver.trimmer.device_name.as_bytes();
+	let n = std::cmp::min(stats.device_name.len(), name_bytes.len());
+	stats.device_name[..n].copy_from_slice(&name_bytes[..n]);
+	stats.last_solution_time = duration_to_u64(elapsed);
+	stats.last_start_time = duration_to_u64(start.duration_since(SystemTime::UNIX_EPOCH).unwrap());
+	stats.last_end_time = duration_to_u64(end.duration_since(SystemTime::UNIX_EPOCH).unwrap());
+	0
+}
+
+fn duration_to_u64(elapsed: Duration) -> u64 {
+	elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64
+}
+
+pub fn set_header_nonce(
+	header: &[u8],
+	nonce: Option<u32>,
+	mutate_nonce: bool,
+) -> Result<[u64; 4], Error> {
+	if let Some(n) = nonce {
+		let len = header.len();
+		let mut header = header.to_owned();
+		if mutate_nonce {
+			header.truncate(len - 4);
+			header.write_u32::<LittleEndian>(n)?;
+		}
+		create_siphash_keys(&header)
+	} else {
+		create_siphash_keys(header)
+	}
+}
+
+pub fn create_siphash_keys(header: &[u8]) -> Result<[u64; 4], Error> {
+	let h = blake2b(32, &[], header);
+	let hb = h.as_bytes();
+	let mut rdr = Cursor::new(hb);
+	Ok([
+		rdr.read_u64::<LittleEndian>()?,
+		rdr.read_u64::<LittleEndian>()?,
+		rdr.read_u64::<LittleEndian>()?,
+		rdr.read_u64::<LittleEndian>()?,
+	])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Solution for an 80 byte zero header with nonce 68
+	const CUCKATOO_19_SOL: [u64; 42] = [
+		0x6330, 0x6fc9, 0xa191, 0xa9f2, 0xb847, 0xe22a, 0xed86, 0x11ee8, 0x12c42, 0x1a7da, 0x1b479,
+		0x1c032, 0x27a88, 0x29dee, 0x2b5c1, 0x2c03c, 0x2ea3a, 0x30440, 0x31827, 0x397a1, 0x3f561,
+		0x40979, 0x42995, 0x42e05, 0x42fd5, 0x47e43, 0x4dbec, 0x5108e, 0x512de, 0x5203b, 0x56793,
+		0x5d269, 0x5fd3c, 0x60a06, 0x65942, 0x6bdea, 0x6d92a, 0x6f1d6, 0x71797, 0x757c5, 0x782e3,
+		0x7edb4,
+	];
+
+	/// Solution for an 80 byte zero header with nonce 20
+	const CUCKATOO_29_SOL: [u64; 42] = [
+		0x48a9e2, 0x9cf043, 0x155ca30, 0x18f4783, 0x248f86c, 0x2629a64, 0x5bad752, 0x72e3569,
+		0x93db760, 0x97d3b37, 0x9e05670, 0xa315d5a, 0xa3571a1, 0xa48db46, 0xa7796b6, 0xac43611,
+		0xb64912f, 0xbb6c71e, 0xbcc8be1, 0xc38a43a, 0xd4faa99, 0xe018a66, 0xe37e49c, 0xfa975fa,
+		0x11786035, 0x1243b60a, 0x12892da0, 0x141b5453, 0x1483c3a0, 0x1505525e, 0x1607352c,
+		0x16181fe3, 0x17e3a1da, 0x180b651e, 0x1899d678, 0x1931b0bb, 0x19606448, 0x1b041655,
+		0x1b2c20ad, 0x1bd7a83c, 0x1c05d5b0, 0x1c0b9caa,
+	];
+
+	fn solve(edge_bits: u8, mode: Mode, header: &[u8], nonce: u32) -> Vec<Vec<u64>> {
+		let trimmer = Trimmer::build(edge_bits, mode, 4, 0);
+		let k = set_header_nonce(header, Some(nonce), true).unwrap();
+		let res = trimmer.run(&k).unwrap();
+		Graph::search(&res)
+			.unwrap()
+			.into_iter()
+			.map(|s| s.nonces)
+			.collect()
+	}
+
+	#[test]
+	fn solve_19() {
+		for mode in [Mode::Lean, Mode::Mean].iter() {
+			let sols = solve(19, *mode, &[0u8; 80], 68);
+			assert_eq!(sols, vec![CUCKATOO_19_SOL.to_vec()]);
+			// no cycle for the nonce before
+			assert!(solve(19, *mode, &[0u8; 80], 67).is_empty());
+		}
+	}
+
+	/// Solving the full graph takes minutes at 29 edge bits, so this trims
+	/// and searches one of only the solution's edges and 64k others. A
+	/// cycle survives trimming in every graph it's part of.
+	#[test]
+	fn solve_29() {
+		let mut nonces: Vec<u64> = (0..1 << 16).collect();
+		nonces.extend_from_slice(&CUCKATOO_29_SOL);
+		let k = set_header_nonce(&[0u8; 80], Some(20), true).unwrap();
+		for mode in [Mode::Lean, Mode::Mean].iter() {
+			let trimmer = Trimmer::build(29, *mode, 4, 8);
+			let res = trimmer.run_edges(&k, &nonces).unwrap();
+			let sols: Vec<_> = Graph::search(&res)
+				.unwrap()
+				.into_iter()
+				.map(|s| s.nonces)
+				.collect();
+			assert_eq!(sols, vec![CUCKATOO_29_SOL.to_vec()]);
+		}
+	}
+}
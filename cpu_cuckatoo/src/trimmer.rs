@@ -0,0 +1,251 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use util::siphash24;
+
+/// Once fewer than 1/MEAN_THRESHOLD of the edges are alive, the mean trimmer
+/// stops rehashing and keeps the survivors with their endpoints in memory
+const MEAN_THRESHOLD: u64 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+	/// Only bitmaps of alive edges and nodes, endpoints are rehashed every
+	/// round
+	Lean,
+	/// Like lean for the first rounds, then trims a list of the remaining
+	/// edges, trading memory for siphash work
+	Mean,
+}
+
+pub struct Trimmer {
+	edge_bits: u8,
+	mode: Mode,
+	nthreads: usize,
+	ntrims: u32,
+	// one bit per edge
+	alive: Arc<Vec<AtomicU64>>,
+	// one bit per node of the side being trimmed
+	nodes: Arc<Vec<AtomicU64>>,
+	pub stop: Arc<AtomicBool>,
+	pub device_name: String,
+}
+
+impl Trimmer {
+	pub fn build(edge_bits: u8, mode: Mode, nthreads: usize, ntrims: u32) -> Trimmer {
+		let words = bitmap_words(edge_bits);
+		let ntrims = if ntrims > 0 {
+			ntrims
+		} else if edge_bits >= 29 {
+			128
+		} else {
+			256
+		};
+		Trimmer {
+			edge_bits,
+			mode,
+			nthreads: if nthreads > 0 { nthreads } else { 1 },
+			ntrims,
+			alive: Arc::new((0..words).map(|_| AtomicU64::new(0)).collect()),
+			nodes: Arc::new((0..words).map(|_| AtomicU64::new(0)).collect()),
+			stop: Arc::new(AtomicBool::new(false)),
+			device_name: "CPU".to_owned(),
+		}
+	}
+
+	/// Trims the graph for the given siphash keys, returns the remaining
+	/// edges in the layout `Graph::search` expects: 4 u32 per entry, the
+	/// first entry holds the edge count, every following one u, v, nonce.
+	/// v nodes have bit `edge_bits` set to keep them apart from u nodes.
+	pub fn run(&self, k: &[u64; 4]) -> Result<Vec<u32>, String> {
+		for w in self.alive.iter() {
+			w.store(!0, Ordering::Relaxed);
+		}
+		self.trim(k)
+	}
+
+	/// Like `run`, on a graph of only the given edges
+	#[cfg(test)]
+	pub fn run_edges(&self, k: &[u64; 4], nonces: &[u64]) -> Result<Vec<u32>, String> {
+		for w in self.alive.iter() {
+			w.store(0, Ordering::Relaxed);
+		}
+		for n in nonces {
+			self.alive[(n / 64) as usize].fetch_or(1 << (n % 64), Ordering::Relaxed);
+		}
+		self.trim(k)
+	}
+
+	fn trim(&self, k: &[u64; 4]) -> Result<Vec<u32>, String> {
+		let edge_mask = (1u64 << self.edge_bits) - 1;
+		let mut list: Option<Vec<(u32, u32, u32)>> = None;
+		for round in 0..self.ntrims {
+			if self.stop.load(Ordering::Relaxed) {
+				return Ok(vec![0; 4]);
+			}
+			let uorv = (round & 1) as u64;
+			if let Some(ref mut edges) = list {
+				self.trim_list(edges, uorv);
+				continue;
+			}
+			let alive_count = self.trim_round(k, uorv);
+			if self.mode == Mode::Mean && alive_count < (edge_mask + 1) / MEAN_THRESHOLD {
+				list = Some(self.alive_edges(k));
+			}
+		}
+
+		let edges = match list {
+			Some(edges) => edges,
+			None => self.alive_edges(k),
+		};
+		let mut res = vec![0u32; 4 * (edges.len() + 1)];
+		res[1] = edges.len() as u32;
+		let v_bit = 1u32 << self.edge_bits;
+		for (i, (nonce, u, v)) in edges.into_iter().enumerate() {
+			res[4 * (i + 1)] = u;
+			res[4 * (i + 1) + 1] = v | v_bit;
+			res[4 * (i + 1) + 2] = nonce;
+		}
+		Ok(res)
+	}
+
+	/// One lean round: marks the endpoints of all alive edges on one side,
+	/// then kills the edges whose endpoint has no partner node. Returns the
+	/// number of edges left.
+	fn trim_round(&self, k: &[u64; 4], uorv: u64) -> u64 {
+		for w in self.nodes.iter() {
+			w.store(0, Ordering::Relaxed);
+		}
+		let edge_mask = (1u64 << self.edge_bits) - 1;
+
+		self.for_each_chunk(k, move |k, alive, nodes, start, end| {
+			let mut batch = [0u64; 64];
+			for (word, w) in alive.iter().enumerate().take(end).skip(start) {
+				let mut bits = w.load(Ordering::Relaxed);
+				let mut n = 0;
+				while bits != 0 {
+					let nonce = (word as u64) * 64 + bits.trailing_zeros() as u64;
+					bits &= bits - 1;
+					batch[n] = sipnode(k, nonce, uorv, edge_mask);
+					n += 1;
+				}
+				// hashing first and touching the bitmap after lets the cache
+				// misses overlap
+				for node in &batch[..n] {
+					let nw = &nodes[(node / 64) as usize];
+					let mask = 1 << (node % 64);
+					if nw.load(Ordering::Relaxed) & mask == 0 {
+						nw.fetch_or(mask, Ordering::Relaxed);
+					}
+				}
+			}
+			0
+		});
+
+		self.for_each_chunk(k, move |k, alive, nodes, start, end| {
+			let mut batch = [(0u64, 0u64); 64];
+			let mut count = 0;
+			for (word, w) in alive.iter().enumerate().take(end).skip(start) {
+				let mut bits = w.load(Ordering::Relaxed);
+				let mut n = 0;
+				while bits != 0 {
+					let bit = bits.trailing_zeros() as u64;
+					bits &= bits - 1;
+					// cuckatoo cycles continue from the partner node
+					batch[n] = (
+						bit,
+						sipnode(k, (word as u64) * 64 + bit, uorv, edge_mask) ^ 1,
+					);
+					n += 1;
+				}
+				let mut kept = 0;
+				for &(bit, node) in &batch[..n] {
+					if nodes[(node / 64) as usize].load(Ordering::Relaxed) & (1 << (node % 64)) != 0
+					{
+						kept |= 1 << bit;
+					}
+				}
+				w.store(kept, Ordering::Relaxed);
+				count += kept.count_ones() as u64;
+			}
+			count
+		})
+	}
+
+	/// Same as `trim_round`, on the remaining edges of the mean trimmer
+	fn trim_list(&self, edges: &mut Vec<(u32, u32, u32)>, uorv: u64) {
+		let node_of = |e: &(u32, u32, u32)| if uorv == 0 { e.1 } else { e.2 };
+		for e in edges.iter() {
+			let node = node_of(e) as usize;
+			self.nodes[node / 64].fetch_or(1 << (node % 64), Ordering::Relaxed);
+		}
+		let nodes = &self.nodes;
+		let is_set =
+			|node: usize| nodes[node / 64].load(Ordering::Relaxed) & (1 << (node % 64)) != 0;
+		let kept: Vec<_> = edges
+			.iter()
+			.filter(|e| is_set(node_of(e) as usize ^ 1))
+			.cloned()
+			.collect();
+		// only clear what was set, the bitmap is much larger than the list
+		for e in edges.iter() {
+			let node = node_of(e) as usize;
+			self.nodes[node / 64].store(0, Ordering::Relaxed);
+		}
+		*edges = kept;
+	}
+
+	/// Nonce and endpoints of every alive edge
+	fn alive_edges(&self, k: &[u64; 4]) -> Vec<(u32, u32, u32)> {
+		let edge_mask = (1u64 << self.edge_bits) - 1;
+		let mut edges = vec![];
+		for (word, w) in self.alive.iter().enumerate() {
+			let mut bits = w.load(Ordering::Relaxed);
+			while bits != 0 {
+				let nonce = (word as u64) * 64 + bits.trailing_zeros() as u64;
+				bits &= bits - 1;
+				edges.push((
+					nonce as u32,
+					sipnode(k, nonce, 0, edge_mask) as u32,
+					sipnode(k, nonce, 1, edge_mask) as u32,
+				));
+			}
+		}
+		// trim_list expects a clear node bitmap
+		for w in self.nodes.iter() {
+			w.store(0, Ordering::Relaxed);
+		}
+		edges
+	}
+
+	/// Splits the edge bitmap between the solver threads and runs `f` on
+	/// every part, returns the sum of the results
+	fn for_each_chunk<F>(&self, k: &[u64; 4], f: F) -> u64
+	where
+		F: Fn(&[u64; 4], &[AtomicU64], &[AtomicU64], usize, usize) -> u64 + Send + Sync + 'static,
+	{
+		let words = self.alive.len();
+		let chunk = words.saturating_sub(1) / self.nthreads + 1;
+		let f = Arc::new(f);
+		let handles: Vec<_> = (0..self.nthreads)
+			.map(|i| {
+				let (alive, nodes, f, k) = (self.alive.clone(), self.nodes.clone(), f.clone(), *k);
+				thread::spawn(move || {
+					let start = (i * chunk).min(words);
+					let end = ((i + 1) * chunk).min(words);
+					f(&k, &alive, &nodes, start, end)
+				})
+			})
+			.collect();
+		handles.into_iter().map(|h| h.join().unwrap_or(0)).sum()
+	}
+}
+
+fn bitmap_words(edge_bits: u8) -> usize {
+	((1u64 << edge_bits) / 64).max(1) as usize
+}
+
+/// Endpoint of an edge on the u (0) or v (1) side of the graph
+#[inline]
+fn sipnode(k: &[u64; 4], nonce: u64, uorv: u64, edge_mask: u64) -> u64 {
+	siphash24(k, 2 * nonce + uorv) & edge_mask
+}
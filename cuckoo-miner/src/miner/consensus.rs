@@ -19,10 +19,13 @@ use std::cmp::{max, min};
 use std::fmt;
 use byteorder::{BigEndian, ByteOrder};
 
+use miner::siphash::{create_siphash_keys, siphash24, siphash_block};
+
 // constants from epic
 const DEFAULT_MIN_EDGE_BITS: u8 = 31;
 const SECOND_POW_EDGE_BITS: u8 = 29;
-const PROOF_SIZE: usize = 42;
+/// Number of edges in a cycle
+pub const PROOF_SIZE: usize = 42;
 const BLOCK_TIME_SEC: u64 = 60;
 
 const HOUR_HEIGHT: u64 = 3600 / BLOCK_TIME_SEC;
@@ -174,6 +177,112 @@ impl Proof {
 	pub fn to_difficulty_unscaled(&self) -> Difficulty {
 		Difficulty::from_proof_unscaled(&self)
 	}
+
+	/// Checks the nonces form a cycle of PROOF_SIZE edges in the cuckatoo
+	/// graph of the given size generated from the header
+	pub fn verify(&self, header: &[u8], edge_bits: u8) -> Result<(), String> {
+		self.verify_keys(&create_siphash_keys(header), edge_bits, CycleVariant::Cuckatoo)
+	}
+
+	/// Same as `verify`, for the cuckaroo graph
+	pub fn verify_cuckaroo(&self, header: &[u8], edge_bits: u8) -> Result<(), String> {
+		self.verify_keys(&create_siphash_keys(header), edge_bits, CycleVariant::Cuckaroo)
+	}
+
+	fn verify_keys(
+		&self,
+		keys: &[u64; 4],
+		edge_bits: u8,
+		variant: CycleVariant,
+	) -> Result<(), String> {
+		if self.edge_bits != edge_bits {
+			return Err(format!(
+				"proof is for {} edge bits, expected {}",
+				self.edge_bits, edge_bits
+			));
+		}
+		if self.nonces.len() != PROOF_SIZE {
+			return Err(format!("wrong cycle length {}", self.nonces.len()));
+		}
+		let edge_mask = (1u64 << edge_bits) - 1;
+
+		// endpoints of every edge, u at even and v at odd positions
+		let mut uvs = vec![0u64; 2 * PROOF_SIZE];
+		let (mut xor0, mut xor1) = match variant {
+			// cuckatoo cycles pair up nodes differing in the lowest bit only
+			CycleVariant::Cuckatoo => ((PROOF_SIZE as u64 / 2) & 1, (PROOF_SIZE as u64 / 2) & 1),
+			CycleVariant::Cuckaroo => (0, 0),
+		};
+		for n in 0..PROOF_SIZE {
+			let nonce = self.nonces[n];
+			if nonce > edge_mask {
+				return Err("edge too big".to_owned());
+			}
+			if n > 0 && nonce <= self.nonces[n - 1] {
+				return Err("edges not ascending".to_owned());
+			}
+			match variant {
+				CycleVariant::Cuckatoo => {
+					uvs[2 * n] = siphash24(keys, 2 * nonce) & edge_mask;
+					uvs[2 * n + 1] = siphash24(keys, 2 * nonce + 1) & edge_mask;
+				}
+				CycleVariant::Cuckaroo => {
+					let edge = siphash_block(keys, nonce);
+					uvs[2 * n] = edge & edge_mask;
+					uvs[2 * n + 1] = (edge >> 32) & edge_mask;
+				}
+			}
+			xor0 ^= uvs[2 * n];
+			xor1 ^= uvs[2 * n + 1];
+		}
+		if xor0 | xor1 != 0 {
+			return Err("endpoints don't match up".to_owned());
+		}
+
+		// follow the cycle from the first edge until it comes back
+		let mut n = 0;
+		let mut i = 0;
+		loop {
+			let mut j = i;
+			let mut k = i;
+			loop {
+				k = (k + 2) % (2 * PROOF_SIZE);
+				if k == i {
+					break;
+				}
+				let connected = match variant {
+					CycleVariant::Cuckatoo => uvs[k] >> 1 == uvs[i] >> 1,
+					CycleVariant::Cuckaroo => uvs[k] == uvs[i],
+				};
+				if connected {
+					// find other edge endpoint matching one at i
+					if j != i {
+						return Err("branch in cycle".to_owned());
+					}
+					j = k;
+				}
+			}
+			if j == i || (variant == CycleVariant::Cuckatoo && uvs[j] == uvs[i]) {
+				return Err("cycle dead ends".to_owned());
+			}
+			i = j ^ 1;
+			n += 1;
+			if i == 0 {
+				break;
+			}
+		}
+		if n != PROOF_SIZE {
+			return Err("cycle too short".to_owned());
+		}
+		Ok(())
+	}
+}
+
+/// Graph a proof's cycle was found in
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CycleVariant {
+	Cuckatoo,
+	Cuckaroo,
 }
 
 struct BitVec {
@@ -234,6 +343,98 @@ impl fmt::Display for Hash {
 mod tests {
 	use super::*;
 
+	/// Cuckatoo29 solution for an 80 byte zero header with nonce 20
+	const CUCKATOO_29_SOL: [u64; 42] = [
+		0x48a9e2, 0x9cf043, 0x155ca30, 0x18f4783, 0x248f86c, 0x2629a64, 0x5bad752, 0x72e3569,
+		0x93db760, 0x97d3b37, 0x9e05670, 0xa315d5a, 0xa3571a1, 0xa48db46, 0xa7796b6, 0xac43611,
+		0xb64912f, 0xbb6c71e, 0xbcc8be1, 0xc38a43a, 0xd4faa99, 0xe018a66, 0xe37e49c, 0xfa975fa,
+		0x11786035, 0x1243b60a, 0x12892da0, 0x141b5453, 0x1483c3a0, 0x1505525e, 0x1607352c, 0x16181fe3,
+		0x17e3a1da, 0x180b651e, 0x1899d678, 0x1931b0bb, 0x19606448, 0x1b041655, 0x1b2c20ad, 0x1bd7a83c,
+		0x1c05d5b0, 0x1c0b9caa,
+	];
+
+	/// Siphash keys and cuckaroo19 solution for them
+	const CUCKAROO_19_KEYS: [u64; 4] = [
+		0x23796193872092ea,
+		0xf1017d8a68c4b745,
+		0xd312bd53d2cd307b,
+		0x840acce5833ddc52,
+	];
+	const CUCKAROO_19_SOL: [u64; 42] = [
+		0x45e9, 0x6a59, 0xf1ad, 0x10ef7, 0x129e8, 0x13e58, 0x17936, 0x19f7f, 0x208df, 0x23704,
+		0x24564, 0x27e64, 0x2b828, 0x2bb41, 0x2ffc0, 0x304c5, 0x31f2a, 0x347de, 0x39686, 0x3ab6c,
+		0x429ad, 0x45254, 0x49200, 0x4f8f8, 0x5697f, 0x57ad1, 0x5dd47, 0x607f8, 0x66199, 0x686c7,
+		0x6d5f3, 0x6da7a, 0x6dbdf, 0x6f6bf, 0x6ffbb, 0x7580e, 0x78594, 0x785ac, 0x78b1d, 0x7b80d,
+		0x7c11c, 0x7da35,
+	];
+
+	fn cuckatoo_29_header() -> Vec<u8> {
+		let mut header = vec![0u8; 80];
+		header[76] = 20;
+		header
+	}
+
+	#[test]
+	fn verify_cuckatoo() {
+		let header = cuckatoo_29_header();
+		let proof = Proof::new(CUCKATOO_29_SOL.to_vec(), 29);
+		assert_eq!(proof.verify(&header, 29), Ok(()));
+
+		// same proof against another header or graph size
+		assert!(proof.verify(&[0u8; 80], 29).is_err());
+		assert!(proof.verify(&header, 31).is_err());
+
+		// one edge swapped for another
+		let mut nonces = CUCKATOO_29_SOL.to_vec();
+		nonces[10] += 1;
+		let proof = Proof::new(nonces, 29);
+		assert!(proof.verify(&header, 29).is_err());
+
+		// edges out of order
+		let mut nonces = CUCKATOO_29_SOL.to_vec();
+		nonces.swap(0, 1);
+		let proof = Proof { edge_bits: 29, nonces: nonces };
+		assert_eq!(proof.verify(&header, 29), Err("edges not ascending".to_owned()));
+
+		// edge outside of the graph
+		let mut nonces = CUCKATOO_29_SOL.to_vec();
+		nonces[41] = 1 << 29;
+		let proof = Proof::new(nonces, 29);
+		assert_eq!(proof.verify(&header, 29), Err("edge too big".to_owned()));
+
+		// missing edge
+		let proof = Proof::new(CUCKATOO_29_SOL[..41].to_vec(), 29);
+		assert!(proof.verify(&header, 29).is_err());
+
+		let proof = Proof::new([0u64; 42].to_vec(), 29);
+		assert!(proof.verify(&header, 29).is_err());
+	}
+
+	#[test]
+	fn verify_cuckaroo() {
+		let proof = Proof::new(CUCKAROO_19_SOL.to_vec(), 19);
+		assert_eq!(
+			proof.verify_keys(&CUCKAROO_19_KEYS, 19, CycleVariant::Cuckaroo),
+			Ok(())
+		);
+
+		// a cuckaroo cycle isn't a cuckatoo one
+		assert!(proof
+			.verify_keys(&CUCKAROO_19_KEYS, 19, CycleVariant::Cuckatoo)
+			.is_err());
+
+		let mut nonces = CUCKAROO_19_SOL.to_vec();
+		nonces[0] = 0x45ea;
+		let proof = Proof::new(nonces, 19);
+		assert!(proof
+			.verify_keys(&CUCKAROO_19_KEYS, 19, CycleVariant::Cuckaroo)
+			.is_err());
+
+		// and a cuckatoo cycle isn't a cuckaroo one
+		let proof = Proof::new(CUCKATOO_29_SOL.to_vec(), 29);
+		assert!(proof.verify_cuckaroo(&cuckatoo_29_header(), 29).is_err());
+	}
+
 	#[test]
 	fn proof_hash() {
 		let mut in_nonces: Vec<u64> = [0u64; 42].to_vec(); 
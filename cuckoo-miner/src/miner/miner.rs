@@ -27,7 +27,7 @@ use config::types::PluginConfig;
 use miner::types::{JobSharedData, JobSharedDataType, SolverInstance};
 
 use miner::util;
-use miner::consensus::Proof;
+use miner::consensus::{Proof, PROOF_SIZE};
 use plugin::{SolverCtxWrapper, SolverSolutions, Solution, SolverStats};
 use {PluginLibrary};
 
@@ -132,7 +132,8 @@ impl CuckooMiner {
 			let height = { shared_data.read().unwrap().height.clone() };
 			let job_id = { shared_data.read().unwrap().job_id.clone() };
 			let target_difficulty = { shared_data.read().unwrap().difficulty.clone() };
-			let header = util::get_next_header_data(&header_pre, &header_post);
+			let extranonce = { shared_data.read().unwrap().extranonce.clone() };
+			let header = util::get_next_header_data(&header_pre, &header_post, &extranonce);
 			let nonce = header.0;
 			//let sec_scaling = header.2;
 			solver.lib.run_solver(
@@ -388,4 +389,44 @@ impl Miner for CuckooMiner {
 			}
 		}
 	}
+
+	fn set_extranonce(&mut self, extranonce: &[u8]) {
+		self.shared_data.write().unwrap().extranonce = extranonce.to_vec();
+	}
+
+	/// Checks the proof is a cycle in the graph generated from the job's
+	/// header and that its hash meets the target difficulty
+	fn verify_solution(
+		&self,
+		_height: u64,
+		pre_nonce: &str,
+		post_nonce: &str,
+		difficulty: u64,
+		solution: &CrSolution,
+	) -> Result<bool, MinerError> {
+		// not Proof::new, which sorts the nonces: the unsorted ones are what
+		// gets submitted, so a plugin returning them out of order has to fail
+		let proof = match solution.get_algorithm_params() {
+			AlgorithmParams::Cuckoo(edge_bits, nonces) => Proof {
+				edge_bits: edge_bits as u8,
+				nonces: nonces,
+			},
+			_ => return Ok(false),
+		};
+		if proof.nonces.len() != PROOF_SIZE {
+			return Ok(false);
+		}
+		let (header, _) = util::header_data(pre_nonce, post_nonce, solution.get_nonce());
+		let edge_bits = proof.edge_bits;
+		// solutions don't say which plugin found them, so accept cycles in
+		// the graph of any plugin that's loaded
+		let is_cycle = self.configs.iter().any(|c| {
+			if c.name.contains("cuckaroo") {
+				proof.verify_cuckaroo(&header, edge_bits).is_ok()
+			} else {
+				proof.verify(&header, edge_bits).is_ok()
+			}
+		});
+		Ok(is_cycle && proof.to_difficulty_unscaled().to_num() >= difficulty)
+	}
 }
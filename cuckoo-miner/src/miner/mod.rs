@@ -25,3 +25,4 @@ pub mod miner;
 pub mod types;
 pub mod util;
 pub mod consensus;
+pub mod siphash;
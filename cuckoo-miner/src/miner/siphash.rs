@@ -0,0 +1,33 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Siphash keys of Cuck(at)oo Cycle graphs, the hashing itself is shared
+//! with the plugins through `util`.
+
+use blake2::blake2b::blake2b;
+use byteorder::{ByteOrder, LittleEndian};
+
+pub use util::{siphash24, siphash_block};
+
+/// Derives the four siphash keys from the blake2b hash of a header
+pub fn create_siphash_keys(header: &[u8]) -> [u64; 4] {
+	let h = blake2b(32, &[], header);
+	let hb = h.as_bytes();
+	[
+		LittleEndian::read_u64(&hb[0..8]),
+		LittleEndian::read_u64(&hb[8..16]),
+		LittleEndian::read_u64(&hb[16..24]),
+		LittleEndian::read_u64(&hb[24..32]),
+	]
+}
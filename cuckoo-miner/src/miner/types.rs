@@ -71,6 +71,9 @@ pub struct JobSharedData {
 	/// target will be put into the output queue
 	pub difficulty: u64,
 
+	/// Leading nonce bytes assigned by the pool, empty if none
+	pub extranonce: Vec<u8>,
+
 	/// Output solutions
 	pub solutions: Vec<SolverSolutions>,
 
@@ -86,6 +89,7 @@ impl Default for JobSharedData {
 			pre_nonce: String::from(""),
 			post_nonce: String::from(""),
 			difficulty: 0,
+			extranonce: vec![],
 			solutions: Vec::new(),
 			stats: vec![],
 		}
@@ -100,6 +104,7 @@ impl JobSharedData {
 			pre_nonce: String::from(""),
 			post_nonce: String::from(""),
 			difficulty: 1,
+			extranonce: vec![],
 			solutions: Vec::new(),
 			stats: vec![SolverStats::default(); num_solvers],
 		}
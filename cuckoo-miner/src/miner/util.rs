@@ -36,8 +36,12 @@ pub fn header_data(pre_nonce: &str, post_nonce: &str, nonce: u64) -> (Vec<u8>, u
 	(pre_vec, sec_scaling)
 }
 
-pub fn get_next_header_data(pre_nonce: &str, post_nonce: &str) -> (u64, Vec<u8>, u32) {
-	let nonce: u64 = rand::OsRng::new().unwrap().gen();
+pub fn get_next_header_data(
+	pre_nonce: &str,
+	post_nonce: &str,
+	extranonce: &[u8],
+) -> (u64, Vec<u8>, u32) {
+	let nonce = core::util::apply_extranonce(extranonce, rand::OsRng::new().unwrap().gen());
 	let (hd, sec_scaling) = header_data(pre_nonce, post_nonce, nonce);
 	(nonce, hd, sec_scaling)
 }
@@ -0,0 +1,141 @@
+Feature: Talk to a stratum server
+  Scenario: Log in and mine the job template
+    Given a mock stratum server
+    Given the mock server has a <randomx> job at height <5>
+    Given the client logs in as <worker1>
+    When a <randomx> stratum client connects
+    Then the server receives <1> <login> request
+    Then the server sees the login <worker1>
+    Then the server receives <1> <getjobtemplate> request
+    Then the miner receives a job at height <5>
+
+  Scenario: Mine jobs pushed by the server
+    Given a mock stratum server
+    When a <randomx> stratum client connects
+    Then the server receives <1> <getjobtemplate> request
+    When the server sends a <randomx> job at height <10>
+    Then the miner receives a job at height <10>
+    When the server sends a <randomx> job at height <11>
+    Then the miner receives a job at height <11>
+
+  Scenario: Hand new seeds to the miner
+    Given a mock stratum server
+    Given the mock server has a <randomx> job at height <5>
+    When a <randomx> stratum client connects
+    Then the miner receives a job at height <5>
+    When the server sends the seed <7> for heights <0> to <4096>
+    Then the miner receives the seed <7> for heights <0> to <4096>
+
+  Scenario: Stop mining a job for another algorithm
+    Given a mock stratum server
+    Given the mock server has a <randomx> job at height <5>
+    When a <randomx> stratum client connects
+    Then the miner receives a job at height <5>
+    When the server sends a <progpow> job at height <6>
+    Then the miner is told to stop
+
+  Scenario: Submit an accepted share
+    Given a mock stratum server
+    Given the mock server has a <randomx> job at height <5>
+    When a <randomx> stratum client connects
+    Then the miner receives a job at height <5>
+    When the miner finds a share at height <5>
+    Then the server receives a share for height <5>
+    Then the client counts <1> accepted, <0> rejected and <0> stale shares
+
+  Scenario: Resubmit a share once after the connection broke while sending it
+    Given a mock stratum server
+    Given the mock server has a <randomx> job at height <5>
+    Given the connection breaks while the first share is sent
+    When a <randomx> stratum client connects
+    Then the miner receives a job at height <5>
+    When the miner finds a share at height <5>
+    Then the client has connected <2> times
+    Then the server receives exactly <1> share for height <5>
+    Then the client counts <1> accepted, <0> rejected and <0> stale shares
+
+  Scenario: Count a found block
+    Given a mock stratum server
+    Given the mock server has a <randomx> job at height <5>
+    Given the mock server reports found blocks
+    When a <randomx> stratum client connects
+    Then the miner receives a job at height <5>
+    When the miner finds a share at height <5>
+    Then the client counts <1> blocks found
+
+  Scenario: Count a rejected share
+    Given a mock stratum server
+    Given the mock server has a <randomx> job at height <5>
+    Given the mock server rejects submits with <Failed to validate solution>
+    When a <randomx> stratum client connects
+    Then the miner receives a job at height <5>
+    When the miner finds a share at height <5>
+    Then the client counts <0> accepted, <1> rejected and <0> stale shares
+
+  Scenario: Count a stale share
+    Given a mock stratum server
+    Given the mock server has a <randomx> job at height <5>
+    Given the mock server rejects submits with <Solution submitted too late>
+    When a <randomx> stratum client connects
+    Then the miner receives a job at height <5>
+    When the miner finds a share at height <5>
+    Then the client counts <0> accepted, <0> rejected and <1> stale share
+
+  Scenario: Reconnect after the server dropped the connection
+    Given a mock stratum server
+    Given the mock server has a <randomx> job at height <5>
+    Given the client logs in as <worker1>
+    When a <randomx> stratum client connects
+    Then the miner receives a job at height <5>
+    When the server drops the connection
+    Then the miner is told to stop
+    Then the client has connected <2> times
+    Then the server receives <2> <login> requests
+    When the server sends a <randomx> job at height <6>
+    Then the miner receives a job at height <6>
+
+  Scenario: Reconnect to a server that went silent
+    Given a mock stratum server
+    Given the mock server has a <randomx> job at height <5>
+    Given the client gives up on a silent server after <2> seconds
+    When a <randomx> stratum client connects
+    Then the miner receives a job at height <5>
+    When the server stalls
+    Then the client has connected <2> times
+    Then the server receives <2> <getjobtemplate> requests
+
+  Scenario: Resubmit a share the server never answered
+    Given a mock stratum server
+    Given the mock server has a <randomx> job at height <5>
+    Given the client gives up on a request after <1> second
+    Given the client gives up on a silent server after <3> seconds
+    When a <randomx> stratum client connects
+    Then the miner receives a job at height <5>
+    When the server stalls
+    When the miner finds a share at height <5>
+    Then the client has connected <2> times
+    Then the server receives exactly <2> shares for height <5>
+    Then the client counts <1> accepted, <0> rejected and <0> stale shares
+
+  Scenario: Switch to a backup server after failed connects
+    Given a mock stratum server
+    Given the mock server has a <randomx> job at height <5>
+    Given the mock server is the backup of a primary server that is down
+    Given the client switches servers after <2> failed connects
+    When a <randomx> stratum client connects
+    Then the client has connected <1> times
+    Then the miner receives a job at height <5>
+    Then the client mines on server <1>
+
+  Scenario: Return to the primary server once it's back
+    Given a mock stratum server
+    Given the mock server has a <randomx> job at height <5>
+    Given the mock server is the backup of a primary server that is down
+    Given the client switches servers after <2> failed connects
+    Given the client checks for the primary server every <1> second
+    When a <randomx> stratum client connects
+    Then the client mines on server <1>
+    When the primary server comes back with a <randomx> job at height <6>
+    Then the client mines on server <0>
+    Then the primary server sees <1> <getjobtemplate> request
+    Then the miner receives a job at height <6>
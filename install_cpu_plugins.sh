@@ -0,0 +1,15 @@
+plugins_dir=$(egrep '^miner_plugin_dir' epic-miner.toml | awk '{ print $NF }' | xargs echo)
+if [ -z "$plugins_dir" ]; then
+	plugins_dir="target/release/plugins"
+fi
+mkdir -p "$plugins_dir";
+
+# Install cpu_cuckatoo
+cd cpu_cuckatoo
+cargo build --release
+cd ..
+if [ "$(uname)" = "Darwin" ]; then
+	cp target/release/libcpu_cuckatoo.dylib $plugins_dir/cpu_cuckatoo.cuckooplugin
+else
+	cp target/release/libcpu_cuckatoo.so $plugins_dir/cpu_cuckatoo.cuckooplugin
+fi
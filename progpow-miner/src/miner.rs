@@ -1,5 +1,5 @@
 use std::string;
-use std::sync::{mpsc, Arc, RwLock};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::thread;
 use std::time;
 use std::time::{Instant, SystemTime, UNIX_EPOCH};
@@ -47,6 +47,9 @@ pub struct PpMiner {
 
 	/// Solver has stopped and cleanly shutdown
 	solver_stopped_rxs: Vec<mpsc::Receiver<ControlMessage>>,
+
+	/// cpu implementation used to check solutions before they're submitted
+	verifier: Mutex<PpCPU>,
 }
 
 unsafe impl Send for PpMiner {}
@@ -100,6 +103,7 @@ impl PpMiner {
 			let height = { shared_data.read().unwrap().height.clone() };
 			let job_id = { shared_data.read().unwrap().job_id.clone() };
 			let target_difficulty = { shared_data.read().unwrap().difficulty.clone() };
+			let extranonce = { shared_data.read().unwrap().extranonce.clone() };
 
 			let boundary = U256::max_value() / U256::from(if target_difficulty > 0 { target_difficulty } else { 1 });
 
@@ -109,7 +113,7 @@ impl PpMiner {
 			keccak_256(&header_pre, &mut header);
 
 			let start = timestamp();
-			gpu.compute_with_startnonce(header, height, (height / 30000) as i32, target, util::get_random_nonce());
+			gpu.compute_with_startnonce(header, height, (height / 30000) as i32, target, util::apply_extranonce(&extranonce, util::get_random_nonce()));
 			let end = timestamp();
 
 			iter_count += WORK_PER_CALL;
@@ -165,6 +169,7 @@ impl Miner for PpMiner {
 			control_txs: vec![],
 			solver_loop_txs: vec![],
 			solver_stopped_rxs: vec![],
+			verifier: Mutex::new(PpCPU::new()),
 		}
 	}
 
@@ -291,4 +296,39 @@ impl Miner for PpMiner {
 			}
 		}
 	}
+
+	fn set_extranonce(&mut self, extranonce: &[u8]) {
+		self.shared_data.write().unwrap().extranonce = extranonce.to_vec();
+	}
+
+	fn verify_solution(
+		&self,
+		height: u64,
+		pre_nonce: &str,
+		_post_nonce: &str,
+		difficulty: u64,
+		solution: &Solution,
+	) -> Result<bool, MinerError> {
+		match solution.get_algorithm_params() {
+			AlgorithmParams::ProgPow(_) => {}
+			_ => return Ok(false),
+		}
+
+		let header_pre = util::from_hex_string(pre_nonce);
+		let mut header = [0u8; 32];
+		keccak_256(&header_pre, &mut header);
+
+		let (v, _) = self
+			.verifier
+			.lock()
+			.unwrap()
+			.verify(&header, height, solution.get_nonce())
+			.map_err(|e| MinerError::VerifyError(format!("{:?}", e)))?;
+		let digest: [u8; 32] = unsafe { ::std::mem::transmute(v) };
+		let h256_digest: U256 = digest.into();
+
+		let boundary = U256::max_value() / U256::from(if difficulty > 0 { difficulty } else { 1 });
+
+		Ok(h256_digest <= boundary)
+	}
 }
@@ -1,5 +1,5 @@
 use std::string;
-use std::sync::{mpsc, Arc, RwLock};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::thread;
 use std::time;
 use std::time::{Instant, SystemTime, UNIX_EPOCH};
@@ -9,10 +9,10 @@ use core::errors::MinerError;
 use core::miner::Miner;
 use core::types::AlgorithmParams;
 use core::util;
-use core::{ControlMessage, JobSharedData, JobSharedDataType, Solution, Stats};
+use core::{ControlMessage, DatasetStatus, JobSharedData, JobSharedDataType, Solution, Stats};
 
 use bigint::uint::U256;
-use randomx::{calculate, RxState, RxAction};
+use randomx::{calculate, RxAction, RxState, RxVM};
 use util::LOGGER;
 
 const MAX_HASHS: u64 = 100;
@@ -80,6 +80,9 @@ pub struct RxMiner {
 	epochs: Arc<RwLock<Vec<EpochSeed>>>,
 
 	config: RxConfig,
+
+	/// vm used to check solutions before they're submitted
+	verify_vm: Mutex<Option<Arc<RwLock<RxVM>>>>,
 }
 
 unsafe impl Send for RxMiner {}
@@ -271,7 +274,8 @@ impl RxMiner {
 
 			let job_id = { shared_data.read().unwrap().job_id.clone() };
 			let target_difficulty = { shared_data.read().unwrap().difficulty.clone() };
-			let header = util::get_next_header_data(&header_pre, &header_post);
+			let extranonce = { shared_data.read().unwrap().extranonce.clone() };
+			let header = util::get_next_header_data(&header_pre, &header_post, &extranonce);
 			let nonce = header.0;
 			let mut header = header.1;
 
@@ -338,6 +342,7 @@ impl Miner for RxMiner {
 			))),
 			current_seed: [u8::max_value(); 32],
 			epochs: Arc::new(RwLock::new(vec![])),
+			verify_vm: Mutex::new(None),
 		}
 	}
 
@@ -437,6 +442,26 @@ impl Miner for RxMiner {
 		}
 	}
 
+	fn dataset_status(&self) -> Vec<DatasetStatus> {
+		self.epochs
+			.read()
+			.unwrap()
+			.iter()
+			.map(|e| DatasetStatus {
+				start_height: e.start_height,
+				end_height: e.end_height,
+				state: match e.state {
+					EpochState::Waiting => "waiting",
+					EpochState::Loading => "loading",
+					EpochState::Loaded => "loaded",
+					EpochState::Running => "running",
+					EpochState::Failed(_) => "failed",
+				}
+				.to_owned(),
+			})
+			.collect()
+	}
+
 	/// #Description
 	///
 	/// Stops the current job, and signals for the loaded plugin to stop
@@ -491,4 +516,61 @@ impl Miner for RxMiner {
 			}
 		}
 	}
+
+	fn set_extranonce(&mut self, extranonce: &[u8]) {
+		self.shared_data.write().unwrap().extranonce = extranonce.to_vec();
+	}
+
+	fn verify_solution(
+		&self,
+		height: u64,
+		pre_nonce: &str,
+		post_nonce: &str,
+		difficulty: u64,
+		solution: &Solution,
+	) -> Result<bool, MinerError> {
+		let hash = match solution.get_algorithm_params() {
+			AlgorithmParams::RandomX(hash) => hash,
+			_ => return Ok(false),
+		};
+
+		// the loaded dataset has to be the one the solution was mined with
+		let dataset_loaded = {
+			self.epochs.read().unwrap().iter().any(|x| {
+				x.state == EpochState::Running && x.start_height < height && x.end_height >= height
+			})
+		};
+		if !dataset_loaded {
+			return Err(MinerError::VerifyError(format!(
+				"no dataset loaded for height {}",
+				height
+			)));
+		}
+
+		let mut verify_vm = self.verify_vm.lock().unwrap();
+		if verify_vm.is_none() {
+			// loading the next dataset holds the state for minutes, the
+			// solution goes out unchecked rather than stall mining for it
+			let mut rx = match self.state.try_write() {
+				Ok(rx) => rx,
+				Err(_) => return Err(MinerError::VerifyError("dataset is being loaded".to_owned())),
+			};
+			let vm = rx
+				.create_vm()
+				.map_err(|e| MinerError::VerifyError(format!("{:?}", e)))?;
+			*verify_vm = Some(vm);
+		}
+
+		let nonce = solution.get_nonce();
+		let (mut header, _) = util::header_data(pre_nonce, post_nonce, nonce);
+		let result = {
+			let vm_ref = verify_vm.as_ref().map(|x| x.write().unwrap()).unwrap();
+			calculate(&vm_ref, &mut header, nonce)
+		};
+
+		let boundary = U256::max_value() / U256::from(if difficulty > 0 { difficulty } else { 1 });
+		let reported: U256 = hash.into();
+
+		Ok(result == reported && result <= boundary)
+	}
 }
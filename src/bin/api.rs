@@ -0,0 +1,643 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Local HTTP API, lets dashboards and scripts read what the miner is
+//! doing and, with a token, control it. Just enough HTTP/1.1 for simple
+//! requests, each connection on its own thread and at most
+//! `MAX_CONNECTIONS` of them at once.
+
+use metrics;
+use reload;
+use serde_json::{self, Value};
+use stats;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use types;
+use util::LOGGER;
+
+use core::Stats as CrStats;
+
+/// Requests larger than this are refused
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// Time a client gets to send its request
+const READ_TIMEOUT_SECS: u64 = 5;
+
+/// Connections served at the same time, more are turned away
+const MAX_CONNECTIONS: usize = 16;
+
+/// How long a server change may take to connect before the request
+/// gives up waiting, the change itself still goes ahead
+const SERVER_CHANGE_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Debug)]
+pub struct Request {
+	pub method: String,
+	pub path: String,
+	/// header names are lowercased
+	pub headers: HashMap<String, String>,
+	pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct Response {
+	pub status: u16,
+	pub content_type: &'static str,
+	pub body: String,
+}
+
+impl Response {
+	pub fn json(status: u16, value: &Value) -> Response {
+		Response {
+			status: status,
+			content_type: "application/json",
+			body: serde_json::to_string_pretty(value).unwrap_or_default(),
+		}
+	}
+
+	pub fn error(status: u16, message: &str) -> Response {
+		Response::json(status, &json!({ "error": message }))
+	}
+}
+
+/// Lets the control endpoints steer the miner
+#[derive(Clone)]
+pub struct Control {
+	/// token control requests have to present
+	pub token: String,
+	/// stratum clients and miners of every mined algorithm
+	pub client_txs: Vec<mpsc::Sender<types::ClientMessage>>,
+	pub miner_txs: Vec<mpsc::Sender<types::MinerMessage>>,
+	/// asks the config watcher to reload the config file
+	pub reload_tx: mpsc::Sender<reload::ReloadReply>,
+}
+
+#[derive(Clone)]
+struct Api {
+	stats: Vec<Arc<RwLock<stats::Stats>>>,
+	control: Option<Control>,
+}
+
+/// Starts serving the API on `addr` in the background for the stats of
+/// all mined algorithms, the control endpoints are only available with
+/// `control`
+pub fn start(
+	addr: &str,
+	stats: Vec<Arc<RwLock<stats::Stats>>>,
+	control: Option<Control>,
+) -> io::Result<()> {
+	let listener = TcpListener::bind(addr)?;
+	info!(LOGGER, "Monitoring API listening on {}", addr);
+	let api = Api {
+		stats: stats,
+		control: control,
+	};
+	let connections = Arc::new(AtomicUsize::new(0));
+	thread::Builder::new()
+		.name("api".to_string())
+		.spawn(move || {
+			for stream in listener.incoming() {
+				match stream {
+					Ok(mut s) if connections.load(Ordering::SeqCst) >= MAX_CONNECTIONS => {
+						warn!(LOGGER, "API busy, turning a connection away");
+						let _ =
+							write_response(&mut s, &Response::error(503, "too many connections"));
+					}
+					// a server change can take a while to answer, the other
+					// requests don't wait for it
+					Ok(s) => {
+						let api = api.clone();
+						let active = connections.clone();
+						connections.fetch_add(1, Ordering::SeqCst);
+						let res = thread::Builder::new()
+							.name("api_connection".to_string())
+							.spawn(move || {
+								if let Err(e) = api.serve(s) {
+									debug!(LOGGER, "API connection error: {:?}", e);
+								}
+								active.fetch_sub(1, Ordering::SeqCst);
+							});
+						if let Err(e) = res {
+							connections.fetch_sub(1, Ordering::SeqCst);
+							warn!(LOGGER, "API can't serve connection: {:?}", e);
+						}
+					}
+					Err(e) => warn!(LOGGER, "API can't accept connection: {:?}", e),
+				}
+			}
+		})?;
+	Ok(())
+}
+
+impl Api {
+	fn serve(&self, stream: TcpStream) -> io::Result<()> {
+		stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SECS)))?;
+		let mut writer = stream.try_clone()?;
+		let response = match read_request(&mut BufReader::new(stream)) {
+			Ok(req) => self.handle(&req),
+			Err(e) => Response::error(400, &format!("{}", e)),
+		};
+		write_response(&mut writer, &response)
+	}
+
+	fn handle(&self, req: &Request) -> Response {
+		if req.path.starts_with("/control/") {
+			return self.control(req, &req.path["/control/".len()..]);
+		}
+		match (req.method.as_str(), req.path.as_str()) {
+			("GET", "/stats") => match self.snapshot() {
+				Some(ref s) if s.len() == 1 => Response::json(200, &stats_json(&s[0])),
+				Some(s) => Response::json(
+					200,
+					&json!({ "backends": s.iter().map(stats_json).collect::<Vec<_>>() }),
+				),
+				None => Response::error(500, "stats lock poisoned"),
+			},
+			("GET", "/metrics") => match self.snapshot() {
+				Some(s) => Response {
+					status: 200,
+					content_type: metrics::CONTENT_TYPE,
+					body: metrics::render(&s),
+				},
+				None => Response::error(500, "stats lock poisoned"),
+			},
+			(_, "/stats") | (_, "/metrics") => Response::error(405, "method not allowed"),
+			_ => Response::error(404, "not found"),
+		}
+	}
+
+	/// Copies the stats of every mined algorithm
+	fn snapshot(&self) -> Option<Vec<stats::Stats>> {
+		self.stats
+			.iter()
+			.map(|s| s.read().ok().map(|s| s.clone()))
+			.collect()
+	}
+
+	fn control(&self, req: &Request, action: &str) -> Response {
+		let control = match self.control {
+			Some(ref c) => c,
+			None => return Response::error(403, "control API disabled, no api_token configured"),
+		};
+		if !authorized(req, &control.token) {
+			warn!(LOGGER, "API control request without a valid token");
+			return Response::error(401, "missing or invalid token");
+		}
+		if req.method != "POST" {
+			return Response::error(405, "method not allowed");
+		}
+		info!(LOGGER, "API control request: {}", action);
+		let to_miners = |m: fn() -> types::MinerMessage| {
+			control.miner_txs.iter().all(|tx| tx.send(m()).is_ok())
+		};
+		let sent = match action {
+			"pause" => to_miners(|| types::MinerMessage::Pause),
+			"resume" => to_miners(|| types::MinerMessage::Resume),
+			"shutdown" => {
+				control
+					.client_txs
+					.iter()
+					.all(|tx| tx.send(types::ClientMessage::Shutdown).is_ok())
+					&& to_miners(|| types::MinerMessage::Shutdown)
+			}
+			"pool" => return change_server(control, &req.body),
+			"reload" => return reload_config(control),
+			_ => return Response::error(404, "not found"),
+		};
+		if sent {
+			Response::json(200, &json!({ "result": "ok" }))
+		} else {
+			Response::error(503, "miner is shutting down")
+		}
+	}
+}
+
+/// Hands new server settings to the stratum clients and waits for them
+/// to connect with them
+fn change_server(control: &Control, body: &[u8]) -> Response {
+	let change = match serde_json::from_slice::<types::ServerChange>(body) {
+		Ok(c) => c,
+		Err(e) => return Response::error(400, &format!("invalid server settings: {}", e)),
+	};
+	let (tx, rx) = mpsc::channel();
+	for client_tx in &control.client_txs {
+		if client_tx
+			.send(types::ClientMessage::ChangeServer(
+				change.clone(),
+				tx.clone(),
+			))
+			.is_err()
+		{
+			return Response::error(503, "miner is shutting down");
+		}
+	}
+	let deadline = Instant::now() + Duration::from_secs(SERVER_CHANGE_TIMEOUT_SECS);
+	let mut results = vec![];
+	for _ in &control.client_txs {
+		let timeout = deadline.saturating_duration_since(Instant::now());
+		match rx.recv_timeout(timeout) {
+			Ok(Ok(msg)) => results.push(msg),
+			Ok(Err(e)) => return Response::error(502, &e),
+			Err(_) => return Response::error(504, "no answer from the stratum client"),
+		}
+	}
+	Response::json(200, &json!({ "result": results.join("; ") }))
+}
+
+/// Reloads the config file and lists which settings changed
+fn reload_config(control: &Control) -> Response {
+	let (tx, rx) = mpsc::channel();
+	if control.reload_tx.send(tx).is_err() {
+		return Response::error(503, "miner is shutting down");
+	}
+	match rx.recv_timeout(Duration::from_secs(SERVER_CHANGE_TIMEOUT_SECS)) {
+		Ok(Ok(changes)) => Response::json(200, &json!(changes)),
+		Ok(Err(e)) => Response::error(422, &e),
+		Err(_) => Response::error(504, "no answer from the config watcher"),
+	}
+}
+
+/// Compares the bearer token without leaking how much of it matched
+fn authorized(req: &Request, token: &str) -> bool {
+	let expected = format!("Bearer {}", token);
+	match req.headers.get("authorization") {
+		Some(v) => {
+			v.len() == expected.len()
+				&& v.bytes()
+					.zip(expected.bytes())
+					.fold(0, |acc, (a, b)| acc | (a ^ b))
+					== 0
+		}
+		None => false,
+	}
+}
+
+pub fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+	let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+	let mut size = 0;
+	let mut next_line = |reader: &mut R| -> io::Result<String> {
+		let mut line = String::new();
+		size += reader.read_line(&mut line)?;
+		if size > MAX_REQUEST_SIZE {
+			return Err(invalid("request too large"));
+		}
+		Ok(line.trim_end().to_owned())
+	};
+
+	let request_line = next_line(reader)?;
+	let mut parts = request_line.split_whitespace();
+	let (method, target) = match (parts.next(), parts.next()) {
+		(Some(m), Some(t)) => (m.to_owned(), t),
+		_ => return Err(invalid("malformed request line")),
+	};
+	// the query string isn't used by any endpoint
+	let path = target.split('?').next().unwrap_or("").to_owned();
+
+	let mut headers = HashMap::new();
+	loop {
+		let line = next_line(reader)?;
+		if line.is_empty() {
+			break;
+		}
+		let mut kv = line.splitn(2, ':');
+		if let (Some(k), Some(v)) = (kv.next(), kv.next()) {
+			headers.insert(k.trim().to_lowercase(), v.trim().to_owned());
+		}
+	}
+
+	let len = match headers.get("content-length") {
+		Some(l) => l
+			.parse::<usize>()
+			.map_err(|_| invalid("bad content length"))?,
+		None => 0,
+	};
+	if len > MAX_REQUEST_SIZE {
+		return Err(invalid("request too large"));
+	}
+	let mut body = vec![0; len];
+	reader.read_exact(&mut body)?;
+
+	Ok(Request {
+		method: method,
+		path: path,
+		headers: headers,
+		body: body,
+	})
+}
+
+fn write_response<W: Write>(writer: &mut W, response: &Response) -> io::Result<()> {
+	let reason = match response.status {
+		200 => "OK",
+		400 => "Bad Request",
+		401 => "Unauthorized",
+		403 => "Forbidden",
+		404 => "Not Found",
+		405 => "Method Not Allowed",
+		422 => "Unprocessable Entity",
+		502 => "Bad Gateway",
+		503 => "Service Unavailable",
+		504 => "Gateway Timeout",
+		_ => "Internal Server Error",
+	};
+	write!(
+		writer,
+		"HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+		response.status,
+		reason,
+		response.content_type,
+		response.body.len(),
+		response.body
+	)?;
+	writer.flush()
+}
+
+/// Full snapshot of the miner stats
+pub fn stats_json(s: &stats::Stats) -> Value {
+	let m = &s.mining_stats;
+	json!({
+		"paused": m.paused,
+		"connection": s.client_stats,
+		"job": {
+			"height": m.block_height,
+			"job_id": m.job_id,
+			"difficulty": m.target_difficulty,
+			"algorithm": s.client_stats.algorithm_needed,
+		},
+		"hashrate": {
+			"average": m.combined_gps(),
+			"history": m.combined_gps_history(),
+		},
+		"shares": m.solution_stats,
+		"devices": m.device_stats.iter().map(device_json).collect::<Vec<_>>(),
+		"datasets": m.datasets,
+		"workers": s.workers,
+	})
+}
+
+fn device_json(d: &CrStats) -> Value {
+	json!({
+		"device_id": d.device_id,
+		"device_name": d.get_device_name(),
+		"plugin_name": d.get_plugin_name(),
+		"edge_bits": d.edge_bits,
+		"status": if d.has_errored { "ERRORED" } else { "OK" },
+		"error_reason": if d.has_errored { Some(d.get_error_reason()) } else { None },
+		"iterations": d.iterations,
+		"last_start_time": d.last_start_time,
+		"last_end_time": d.last_end_time,
+		"last_solution_time": d.last_solution_time,
+		"hashes_per_sec": d.hashes_per_sec,
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn request(method: &str, path: &str, token: Option<&str>, body: &str) -> Request {
+		let mut headers = HashMap::new();
+		if let Some(t) = token {
+			headers.insert("authorization".to_owned(), format!("Bearer {}", t));
+		}
+		Request {
+			method: method.to_owned(),
+			path: path.to_owned(),
+			headers: headers,
+			body: body.as_bytes().to_vec(),
+		}
+	}
+
+	#[test]
+	fn parse_request() {
+		let raw = "POST /stats?x=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nbody";
+		let req = read_request(&mut raw.as_bytes()).unwrap();
+		assert_eq!(req.method, "POST");
+		assert_eq!(req.path, "/stats");
+		assert_eq!(req.headers["host"], "localhost");
+		assert_eq!(req.body, b"body");
+		assert!(read_request(&mut "\r\n\r\n".as_bytes()).is_err());
+	}
+
+	#[test]
+	fn status_lines() {
+		let mut out = vec![];
+		write_response(&mut out, &Response::error(422, "invalid config")).unwrap();
+		let out = String::from_utf8(out).unwrap();
+		assert!(
+			out.starts_with("HTTP/1.1 422 Unprocessable Entity\r\n"),
+			"{}",
+			out
+		);
+	}
+
+	#[test]
+	fn stats_snapshot() {
+		let stats = Arc::new(RwLock::new(stats::Stats::default()));
+		{
+			let mut s = stats.write().unwrap();
+			s.mining_stats.block_height = 42;
+			s.mining_stats.add_combined_gps(1.5);
+			s.mining_stats.solution_stats.num_shares_accepted = 3;
+			let mut d = CrStats::default();
+			d.set_device_name("cpu");
+			d.has_errored = true;
+			s.mining_stats.device_stats.push(d);
+		}
+		let api = Api {
+			stats: vec![stats],
+			control: None,
+		};
+		let resp = api.handle(&request("GET", "/stats", None, ""));
+		assert_eq!(resp.status, 200);
+		let v: Value = serde_json::from_str(&resp.body).unwrap();
+		assert_eq!(v["job"]["height"], 42);
+		assert_eq!(v["hashrate"]["history"][0], 1.5);
+		assert_eq!(v["shares"]["num_shares_accepted"], 3);
+		assert_eq!(v["connection"]["connected"], false);
+		assert_eq!(v["devices"][0]["device_name"], "cpu");
+		assert_eq!(v["devices"][0]["status"], "ERRORED");
+		assert_eq!(api.handle(&request("GET", "/nope", None, "")).status, 404);
+
+		let resp = api.handle(&request("GET", "/metrics", None, ""));
+		assert_eq!(resp.content_type, metrics::CONTENT_TYPE);
+		assert!(resp
+			.body
+			.contains("epic_miner_block_height{algorithm=\"\"} 42\n"));
+
+		// no token configured
+		let resp = api.handle(&request("POST", "/control/pause", Some("x"), ""));
+		assert_eq!(resp.status, 403);
+
+		// one snapshot per algorithm when mining several
+		let mut other = stats::Stats::default();
+		other.mining_stats.block_height = 43;
+		let api = Api {
+			stats: vec![api.stats[0].clone(), Arc::new(RwLock::new(other))],
+			control: None,
+		};
+		let resp = api.handle(&request("GET", "/stats", None, ""));
+		let v: Value = serde_json::from_str(&resp.body).unwrap();
+		assert_eq!(v["backends"][0]["job"]["height"], 42);
+		assert_eq!(v["backends"][1]["job"]["height"], 43);
+	}
+
+	#[test]
+	fn control() {
+		let (client_tx, client_rx) = mpsc::channel();
+		let (miner_tx, miner_rx) = mpsc::channel();
+		let (reload_tx, reload_rx) = mpsc::channel();
+		let api = Api {
+			stats: vec![Arc::new(RwLock::new(stats::Stats::default()))],
+			control: Some(Control {
+				token: "secret".to_owned(),
+				client_txs: vec![client_tx],
+				miner_txs: vec![miner_tx],
+				reload_tx: reload_tx,
+			}),
+		};
+
+		for token in [None, Some("wrong"), Some("secre")].iter() {
+			let resp = api.handle(&request("POST", "/control/pause", *token, ""));
+			assert_eq!(resp.status, 401);
+		}
+		assert!(miner_rx.try_recv().is_err());
+
+		let resp = api.handle(&request("POST", "/control/pause", Some("secret"), ""));
+		assert_eq!(resp.status, 200);
+		match miner_rx.try_recv() {
+			Ok(types::MinerMessage::Pause) => {}
+			m => panic!("expected a pause, got {:?}", m),
+		}
+		let resp = api.handle(&request("GET", "/control/resume", Some("secret"), ""));
+		assert_eq!(resp.status, 405);
+
+		// the stratum client answers once it tried the new server
+		let client = thread::spawn(move || match client_rx.recv() {
+			Ok(types::ClientMessage::ChangeServer(change, reply)) => {
+				assert_eq!(change.addr, None);
+				assert_eq!(change.login, Some("worker2".to_owned()));
+				let _ = reply.send(Err("can't connect".to_owned()));
+			}
+			m => panic!("expected a server change, got {:?}", m),
+		});
+		let body = r#"{"login": "worker2"}"#;
+		let resp = api.handle(&request("POST", "/control/pool", Some("secret"), body));
+		client.join().unwrap();
+		assert_eq!(resp.status, 502);
+		assert!(resp.body.contains("can't connect"));
+
+		let resp = api.handle(&request("POST", "/control/pool", Some("secret"), "{"));
+		assert_eq!(resp.status, 400);
+
+		let watcher = thread::spawn(move || match reload_rx.recv() {
+			Ok(reply) => {
+				let _ = reply.send(Ok(reload::Changes {
+					applied: vec!["stratum_server_login".to_owned()],
+					need_restart: vec![],
+				}));
+			}
+			Err(_) => panic!("expected a reload request"),
+		});
+		let resp = api.handle(&request("POST", "/control/reload", Some("secret"), ""));
+		watcher.join().unwrap();
+		assert_eq!(resp.status, 200);
+		let changes: Value = serde_json::from_str(&resp.body).unwrap();
+		assert_eq!(changes["applied"], json!(["stratum_server_login"]));
+	}
+
+	#[test]
+	fn slow_control_request_blocks_nothing() {
+		use std::io::Read;
+
+		let (client_tx, client_rx) = mpsc::channel();
+		let (miner_tx, _miner_rx) = mpsc::channel();
+		let (reload_tx, _reload_rx) = mpsc::channel();
+		let control = Control {
+			token: "secret".to_owned(),
+			client_txs: vec![client_tx],
+			miner_txs: vec![miner_tx],
+			reload_tx: reload_tx,
+		};
+		let addr = TcpListener::bind("127.0.0.1:0")
+			.unwrap()
+			.local_addr()
+			.unwrap()
+			.to_string();
+		let stats = vec![Arc::new(RwLock::new(stats::Stats::default()))];
+		start(&addr, stats, Some(control)).unwrap();
+		let send = |raw: String| {
+			let mut s = TcpStream::connect(&addr).unwrap();
+			s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+			s.write_all(raw.as_bytes()).unwrap();
+			s
+		};
+		let read = |mut s: TcpStream| {
+			let mut response = String::new();
+			s.read_to_string(&mut response).unwrap();
+			response
+		};
+
+		let body = r#"{"login": "worker2"}"#;
+		let pool = send(format!(
+			"POST /control/pool HTTP/1.1\r\nAuthorization: Bearer secret\r\n\
+			 Content-Length: {}\r\n\r\n{}",
+			body.len(),
+			body
+		));
+		// the stratum client hasn't answered the change yet
+		let reply = match client_rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+			types::ClientMessage::ChangeServer(_, reply) => reply,
+			m => panic!("expected a server change, got {:?}", m),
+		};
+		let stats = read(send("GET /stats HTTP/1.1\r\n\r\n".to_owned()));
+		assert!(stats.starts_with("HTTP/1.1 200 OK\r\n"), "{}", stats);
+
+		reply.send(Ok("switched".to_owned())).unwrap();
+		let pool = read(pool);
+		assert!(pool.starts_with("HTTP/1.1 200 OK\r\n"), "{}", pool);
+		assert!(pool.contains("switched"));
+	}
+
+	#[test]
+	fn connections_over_the_limit_turned_away() {
+		use std::io::Read;
+
+		let addr = TcpListener::bind("127.0.0.1:0")
+			.unwrap()
+			.local_addr()
+			.unwrap()
+			.to_string();
+		let stats = vec![Arc::new(RwLock::new(stats::Stats::default()))];
+		start(&addr, stats, None).unwrap();
+		// connections that never send their request keep their threads busy
+		let idle: Vec<_> = (0..MAX_CONNECTIONS)
+			.map(|_| TcpStream::connect(&addr).unwrap())
+			.collect();
+		let mut s = TcpStream::connect(&addr).unwrap();
+		s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		let mut response = String::new();
+		s.read_to_string(&mut response).unwrap();
+		assert!(
+			response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+			"{}",
+			response
+		);
+		drop(idle);
+	}
+}
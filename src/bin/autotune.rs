@@ -0,0 +1,280 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Searches for the fastest stable RandomX settings and cuckoo plugin
+//! parameters. Every combination tried is a full benchmark run, so the
+//! search goes one parameter at a time instead of trying all of them.
+
+use benchmark::Report;
+use core::config::{EpicMinerPluginConfig, RxConfig};
+use std::collections::{BTreeMap, HashMap};
+use util::LOGGER;
+
+/// Settings being tuned, booleans are 0 or 1
+pub type Params = BTreeMap<String, u32>;
+
+/// Candidate values of each parameter, searched in this order
+pub type Space = Vec<(&'static str, Vec<u32>)>;
+
+/// Devices whose rate varies more than this relative to the average are
+/// considered unstable, e.g. when throttling
+const MAX_RATE_DEVIATION: f64 = 0.25;
+
+/// The generated epic-miner.toml section
+#[derive(Debug, Default, Serialize)]
+pub struct TunedConfig {
+	pub mining: TunedMining,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TunedMining {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub randomx_config: Option<RxConfig>,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub miner_plugin_config: Vec<EpicMinerPluginConfig>,
+}
+
+/// Outcome of benchmarking one combination
+#[derive(Debug, Clone)]
+pub struct Trial {
+	pub params: Params,
+	/// graphs or hashes per second of all devices together
+	pub rate: f64,
+	/// why the combination can't be used, if it can't
+	pub unstable: Option<String>,
+}
+
+impl Trial {
+	pub fn new(params: &Params, report: &Report) -> Trial {
+		let rate = report
+			.devices
+			.iter()
+			.map(|d| d.graphs_per_second.or(d.hashes_per_second).unwrap_or(0.0))
+			.sum();
+		let unstable = if let Some(ref e) = report.error {
+			Some(e.clone())
+		} else if let Some(d) = report.devices.iter().find(|d| d.errored) {
+			Some(format!(
+				"device {} errored: {}",
+				d.device_id,
+				d.error_reason.clone().unwrap_or_default()
+			))
+		} else if report.hw_errors > 0 {
+			Some(format!("{} hardware errors", report.hw_errors))
+		} else if let Some(d) = report
+			.devices
+			.iter()
+			.find(|d| d.rate_deviation > MAX_RATE_DEVIATION)
+		{
+			Some(format!(
+				"device {} rate varied by {:.0}%",
+				d.device_id,
+				d.rate_deviation * 100.0
+			))
+		} else if rate <= 0.0 {
+			Some("no progress".to_owned())
+		} else {
+			None
+		};
+		Trial {
+			params: params.clone(),
+			rate: rate,
+			unstable: unstable,
+		}
+	}
+}
+
+/// Starting from `start`, tries the values of one parameter after the
+/// other and keeps the fastest stable combination found so far. Returns
+/// the best trial, if any was stable, and all trials made.
+pub fn search<F>(start: &Params, space: &Space, mut measure: F) -> (Option<Trial>, Vec<Trial>)
+where
+	F: FnMut(&Params) -> Trial,
+{
+	let first = measure(start);
+	log_trial(&first);
+	let mut trials = vec![first];
+	let mut best = start.clone();
+	for &(name, ref values) in space {
+		for value in values {
+			let mut params = best.clone();
+			params.insert(name.to_owned(), *value);
+			if trials.iter().any(|t| t.params == params) {
+				continue;
+			}
+			let trial = measure(&params);
+			log_trial(&trial);
+			trials.push(trial);
+		}
+		if let Some(t) = fastest(&trials) {
+			best = t.params.clone();
+		}
+	}
+	(fastest(&trials).cloned(), trials)
+}
+
+fn log_trial(trial: &Trial) {
+	match trial.unstable {
+		Some(ref reason) => warn!(LOGGER, "Autotune: {:?} unstable: {}", trial.params, reason),
+		None => info!(LOGGER, "Autotune: {:?} at {:.2}", trial.params, trial.rate),
+	}
+}
+
+fn fastest(trials: &[Trial]) -> Option<&Trial> {
+	trials
+		.iter()
+		.filter(|t| t.unstable.is_none())
+		.max_by(|a, b| a.rate.partial_cmp(&b.rate).unwrap())
+}
+
+/// Powers of two below `cpus`, `cpus` itself and one less, leaving a
+/// thread for the rest of the system
+fn thread_counts(cpus: u32) -> Vec<u32> {
+	let mut counts: Vec<u32> = (0..32).map(|i| 1 << i).take_while(|n| *n < cpus).collect();
+	counts.push(cpus.saturating_sub(1).max(1));
+	counts.push(cpus);
+	counts.sort();
+	counts.dedup();
+	counts
+}
+
+pub fn rx_params(config: &RxConfig) -> Params {
+	let mut params = Params::new();
+	params.insert("threads".to_owned(), config.threads as u32);
+	params.insert("jit".to_owned(), config.jit as u32);
+	params.insert("hard_aes".to_owned(), config.hard_aes as u32);
+	params.insert("large_pages".to_owned(), config.large_pages as u32);
+	params
+}
+
+pub fn rx_config(params: &Params) -> RxConfig {
+	RxConfig {
+		threads: params["threads"] as u64,
+		jit: params["jit"] != 0,
+		hard_aes: params["hard_aes"] != 0,
+		large_pages: params["large_pages"] != 0,
+	}
+}
+
+/// The CPU features first, they change the rate the most, then the
+/// thread count
+pub fn rx_space(cpus: u32) -> Space {
+	vec![
+		("jit", vec![1, 0]),
+		("hard_aes", vec![1, 0]),
+		("large_pages", vec![1, 0]),
+		("threads", thread_counts(cpus)),
+	]
+}
+
+pub fn plugin_params(config: &EpicMinerPluginConfig) -> Params {
+	config
+		.parameters
+		.as_ref()
+		.map(|p| p.iter().map(|(k, v)| (k.clone(), *v)).collect())
+		.unwrap_or_default()
+}
+
+pub fn plugin_config(name: &str, params: &Params) -> EpicMinerPluginConfig {
+	let parameters: HashMap<String, u32> = params.iter().map(|(k, v)| (k.clone(), *v)).collect();
+	EpicMinerPluginConfig {
+		plugin_name: name.to_owned(),
+		parameters: if parameters.is_empty() {
+			None
+		} else {
+			Some(parameters)
+		},
+	}
+}
+
+/// Parameters worth tuning for a plugin, going by its name. Device and
+/// platform are left alone, OpenCL plugins have nothing to tune.
+pub fn plugin_space(plugin_name: &str, cpus: u32) -> Space {
+	if plugin_name.contains("cuda") {
+		if plugin_name.contains("mean") {
+			vec![("expand", vec![0, 1, 2])]
+		} else {
+			vec![
+				("genablocks", vec![1024, 2048, 4096]),
+				("genatpb", vec![64, 128, 256]),
+				("genbtpb", vec![64, 128, 256]),
+				("trimtpb", vec![256, 512]),
+				("tailtpb", vec![512, 1024]),
+			]
+		}
+	} else if plugin_name == "cpu_cuckatoo" {
+		vec![("expand", vec![0, 1]), ("nthreads", thread_counts(cpus))]
+	} else if plugin_name.contains("cpu") {
+		vec![("nthreads", thread_counts(cpus))]
+	} else {
+		vec![]
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn params(values: &[(&str, u32)]) -> Params {
+		values.iter().map(|&(k, v)| (k.to_owned(), v)).collect()
+	}
+
+	#[test]
+	fn search_keeps_fastest_stable() {
+		let space: Space = vec![("expand", vec![0, 1]), ("nthreads", vec![1, 2, 4])];
+		let mut measured = vec![];
+		let (best, trials) = search(&params(&[("nthreads", 1)]), &space, |p| {
+			measured.push(p.clone());
+			let threads = p["nthreads"] as f64;
+			let expand = p.get("expand").cloned().unwrap_or(0) as f64;
+			Trial {
+				params: p.clone(),
+				rate: threads * (1.0 + expand),
+				// mean mode runs out of memory with 4 threads
+				unstable: if threads * (1.0 + expand) > 4.0 {
+					Some("out of memory".to_owned())
+				} else {
+					None
+				},
+			}
+		});
+		assert_eq!(
+			measured,
+			vec![
+				params(&[("nthreads", 1)]),
+				params(&[("expand", 0), ("nthreads", 1)]),
+				params(&[("expand", 1), ("nthreads", 1)]),
+				params(&[("expand", 1), ("nthreads", 2)]),
+				params(&[("expand", 1), ("nthreads", 4)]),
+			]
+		);
+		assert_eq!(trials.len(), 5);
+		let best = best.unwrap();
+		assert_eq!(best.params, params(&[("expand", 1), ("nthreads", 2)]));
+		assert_eq!(best.rate, 4.0);
+	}
+
+	#[test]
+	fn spaces() {
+		assert_eq!(thread_counts(1), vec![1]);
+		assert_eq!(thread_counts(6), vec![1, 2, 4, 5, 6]);
+		assert_eq!(thread_counts(8), vec![1, 2, 4, 7, 8]);
+		assert!(plugin_space("ocl_cuckatoo", 8).is_empty());
+		assert_eq!(plugin_space("cuckatoo_mean_cuda_31", 8)[0].0, "expand");
+		assert_eq!(plugin_space("cuckaroo_cpu_avx2_29", 8)[0].0, "nthreads");
+
+		let config = RxConfig::default();
+		assert_eq!(rx_config(&rx_params(&config)), config);
+	}
+}
@@ -0,0 +1,371 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmarks miners without a pool, synthetic jobs go through the same
+//! `Miner::notify` path stratum jobs take
+
+use core::{Algorithm, Miner, Stats};
+use rand::{self, Rng};
+use serde_json::Value;
+use std::thread;
+use std::time::{Duration, Instant};
+use util::{to_hex, LOGGER};
+
+/// Give up on datasets that take longer than this to load
+const WARMUP_LIMIT_SECS: u64 = 600;
+
+/// How long a benchmark runs
+pub struct Options {
+	pub duration: Duration,
+	/// stop early once the devices did this many iterations together
+	pub iterations: Option<u64>,
+	/// share difficulty of the synthetic job, per algorithm default if
+	/// not set
+	pub difficulty: Option<u64>,
+}
+
+/// Results of one device
+#[derive(Debug, Serialize)]
+pub struct DeviceReport {
+	pub device_id: u32,
+	pub device_name: String,
+	pub plugin: String,
+	pub edge_bits: u32,
+	/// average over the run, cuckoo plugins only
+	pub graphs_per_second: Option<f64>,
+	/// average over the run, for the hash based algorithms
+	pub hashes_per_second: Option<f64>,
+	/// standard deviation of the rate relative to the average, how steady
+	/// the device was
+	pub rate_deviation: f64,
+	pub iterations: u64,
+	pub errored: bool,
+	pub error_reason: Option<String>,
+}
+
+/// Results of benchmarking one miner
+#[derive(Debug, Serialize)]
+pub struct Report {
+	pub algorithm: Algorithm,
+	/// settings the miner ran with, e.g. the plugin or RandomX flags
+	pub settings: Value,
+	pub difficulty: u64,
+	/// time until the miner could start, e.g. to load datasets
+	pub warmup_secs: f64,
+	pub duration_secs: f64,
+	pub devices: Vec<DeviceReport>,
+	pub solutions_found: u64,
+	/// solutions that failed verification
+	pub hw_errors: u64,
+	/// why the benchmark didn't run to the end
+	pub error: Option<String>,
+}
+
+/// Share difficulty that gives solutions now and then without flooding
+/// the verification
+fn default_difficulty(algorithm: Algorithm) -> u64 {
+	match algorithm {
+		Algorithm::Cuckoo => 1,
+		Algorithm::RandomX => 10_000,
+		Algorithm::ProgPow => 100_000_000,
+	}
+}
+
+/// Per device rate samples and iteration counts
+#[derive(Default)]
+struct DeviceSamples {
+	first_iterations: u32,
+	last: Stats,
+	rates: Vec<f64>,
+}
+
+impl DeviceSamples {
+	fn add(&mut self, s: Stats) {
+		// only cuckoo plugins report graph sizes, and no hash rate
+		let rate = if s.edge_bits > 0 {
+			if s.last_solution_time > 0 {
+				1_000_000_000.0 / s.last_solution_time as f64
+			} else {
+				0.0
+			}
+		} else {
+			s.hashes_per_sec as f64
+		};
+		if rate > 0.0 {
+			self.rates.push(rate);
+		}
+		self.last = s;
+	}
+
+	fn iterations(&self) -> u64 {
+		self.last.iterations.saturating_sub(self.first_iterations) as u64
+	}
+
+	fn report(&self) -> DeviceReport {
+		let (rate, deviation) = if self.rates.is_empty() {
+			(0.0, 0.0)
+		} else {
+			let n = self.rates.len() as f64;
+			let mean = self.rates.iter().sum::<f64>() / n;
+			let variance = self.rates.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / n;
+			(mean, variance.sqrt() / mean)
+		};
+		let s = &self.last;
+		DeviceReport {
+			device_id: s.device_id,
+			device_name: s.get_device_name(),
+			plugin: s.get_plugin_name(),
+			edge_bits: s.edge_bits,
+			graphs_per_second: if s.edge_bits > 0 { Some(rate) } else { None },
+			hashes_per_second: if s.edge_bits > 0 { None } else { Some(rate) },
+			rate_deviation: deviation,
+			iterations: self.iterations(),
+			errored: s.has_errored,
+			error_reason: if s.has_errored {
+				Some(s.get_error_reason())
+			} else {
+				None
+			},
+		}
+	}
+}
+
+/// Whether the miner has what it needs to mine, datasets that failed to
+/// load are an error
+fn ready<T: Miner>(miner: &T) -> Result<bool, String> {
+	let datasets = miner.dataset_status();
+	if let Some(d) = datasets.iter().find(|d| d.state == "failed") {
+		return Err(format!(
+			"dataset for heights {} to {} failed to load",
+			d.start_height, d.end_height
+		));
+	}
+	Ok(datasets.iter().all(|d| d.state == "running"))
+}
+
+/// Mines synthetic jobs with `miner` and reports how it did
+pub fn run<T: Miner>(
+	mut miner: T,
+	algorithm: Algorithm,
+	settings: Value,
+	opts: &Options,
+) -> Report {
+	let difficulty = opts
+		.difficulty
+		.unwrap_or_else(|| default_difficulty(algorithm));
+	let mut report = Report {
+		algorithm: algorithm,
+		settings: settings,
+		difficulty: difficulty,
+		warmup_secs: 0.0,
+		duration_secs: 0.0,
+		devices: vec![],
+		solutions_found: 0,
+		hw_errors: 0,
+		error: None,
+	};
+	if let Err(e) = miner.start_solvers() {
+		report.error = Some(format!("can't start solvers: {:?}", e));
+		return report;
+	}
+	info!(
+		LOGGER,
+		"Benchmarking {:?} with {}", algorithm, report.settings
+	);
+
+	let mut rng = rand::thread_rng();
+	let mut seed = [0u8; 32];
+	rng.fill_bytes(&mut seed);
+	let mut header = vec![0u8; 64];
+	rng.fill_bytes(&mut header);
+	let pre_pow = to_hex(header);
+	miner.add_epoch(0, u64::max_value(), seed);
+
+	// like a pool finding blocks, new heights let the miner load and
+	// switch to the dataset
+	let warmup = Instant::now();
+	let mut height = 1;
+	let result = loop {
+		if let Err(e) = miner.notify(height as u32, height, &pre_pow, "", difficulty) {
+			break Err(format!("can't start job: {:?}", e));
+		}
+		match ready(&miner) {
+			Ok(true) => break Ok(()),
+			Ok(false) => {}
+			Err(e) => break Err(e),
+		}
+		if warmup.elapsed() > Duration::from_secs(WARMUP_LIMIT_SECS) {
+			break Err("miner didn't get ready in time".to_owned());
+		}
+		thread::sleep(Duration::from_secs(1));
+		height += 1;
+	};
+	report.warmup_secs = warmup.elapsed().as_millis() as f64 / 1000.0;
+	if let Err(e) = result {
+		report.error = Some(e);
+		miner.stop_solvers();
+		miner.wait_for_solver_shutdown();
+		return report;
+	}
+	// solutions of the warmup jobs don't count
+	let _ = miner.get_solutions();
+
+	// solver instances don't always have their own device id, so they're
+	// told apart by position
+	let mut devices: Vec<DeviceSamples> = vec![];
+	if let Ok(stats) = miner.get_stats() {
+		for s in stats {
+			devices.push(DeviceSamples {
+				first_iterations: s.iterations,
+				..DeviceSamples::default()
+			});
+		}
+	}
+	let start = Instant::now();
+	let mut next_sample = start + Duration::from_secs(1);
+	loop {
+		thread::sleep(Duration::from_millis(100));
+		for s in miner.get_solutions().unwrap_or_default() {
+			report.solutions_found += 1;
+			if let Ok(false) = miner.verify_solution(height, &pre_pow, "", difficulty, &s) {
+				report.hw_errors += 1;
+			}
+		}
+		let done = start.elapsed() >= opts.duration;
+		if done || Instant::now() >= next_sample {
+			next_sample += Duration::from_secs(1);
+			if let Ok(stats) = miner.get_stats() {
+				for (i, s) in stats.into_iter().enumerate() {
+					if i >= devices.len() {
+						devices.push(DeviceSamples::default());
+					}
+					devices[i].add(s);
+				}
+			}
+		}
+		let iterations: u64 = devices.iter().map(|d| d.iterations()).sum();
+		if done || opts.iterations.map_or(false, |n| iterations >= n) {
+			break;
+		}
+	}
+	report.duration_secs = start.elapsed().as_millis() as f64 / 1000.0;
+	miner.stop_solvers();
+	miner.wait_for_solver_shutdown();
+
+	report.devices = devices.iter().map(|d| d.report()).collect();
+	report
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use core::config::MinerConfig;
+	use core::{AlgorithmParams, DatasetStatus, MinerError, Solution};
+	use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+	/// Finds a solution every call, every other one invalid
+	struct FakeMiner {
+		calls: AtomicUsize,
+		loaded: AtomicBool,
+	}
+
+	impl Miner for FakeMiner {
+		fn new(_: &MinerConfig) -> Self {
+			FakeMiner {
+				calls: AtomicUsize::new(0),
+				loaded: AtomicBool::new(false),
+			}
+		}
+		fn notify(
+			&mut self,
+			_: u32,
+			height: u64,
+			_: &str,
+			_: &str,
+			_: u64,
+		) -> Result<(), MinerError> {
+			self.loaded.store(height > 1, Ordering::Relaxed);
+			Ok(())
+		}
+		fn start_solvers(&mut self) -> Result<(), MinerError> {
+			Ok(())
+		}
+		fn get_stats(&self) -> Result<Vec<Stats>, MinerError> {
+			let calls = self.calls.fetch_add(1, Ordering::Relaxed) + 1;
+			let mut s = Stats::default();
+			s.set_plugin_name("fake");
+			s.iterations = calls as u32 * 10;
+			s.hashes_per_sec = 100;
+			Ok(vec![s])
+		}
+		fn get_solutions(&self) -> Option<Vec<Solution>> {
+			Some(vec![Solution::new(1, 1, AlgorithmParams::RandomX([0; 32]))])
+		}
+		fn stop_solvers(&self) {}
+		fn pause_solvers(&self) {}
+		fn resume_solvers(&self) {}
+		fn wait_for_solver_shutdown(&self) {}
+		fn dataset_status(&self) -> Vec<DatasetStatus> {
+			vec![DatasetStatus {
+				start_height: 0,
+				end_height: u64::max_value(),
+				state: if self.loaded.load(Ordering::Relaxed) {
+					"running"
+				} else {
+					"loading"
+				}
+				.to_owned(),
+			}]
+		}
+		fn set_extranonce(&mut self, _: &[u8]) {}
+		fn verify_solution(
+			&self,
+			_: u64,
+			_: &str,
+			_: &str,
+			_: u64,
+			_: &Solution,
+		) -> Result<bool, MinerError> {
+			Ok(self.calls.load(Ordering::Relaxed) % 2 == 0)
+		}
+	}
+
+	#[test]
+	fn benchmark_fake_miner() {
+		let opts = Options {
+			duration: Duration::from_secs(60),
+			iterations: Some(20),
+			difficulty: None,
+		};
+		let report = run(
+			FakeMiner::new(&MinerConfig::default()),
+			Algorithm::RandomX,
+			json!({ "threads": 1 }),
+			&opts,
+		);
+		assert_eq!(report.error, None);
+		assert_eq!(report.difficulty, 10_000);
+		assert_eq!(report.settings["threads"], 1);
+		assert!(report.warmup_secs >= 1.0);
+		assert!(report.duration_secs < 60.0);
+		assert_eq!(report.devices.len(), 1);
+		assert_eq!(report.devices[0].plugin, "fake");
+		assert_eq!(report.devices[0].hashes_per_second, Some(100.0));
+		assert_eq!(report.devices[0].graphs_per_second, None);
+		assert_eq!(report.devices[0].rate_deviation, 0.0);
+		assert!(report.devices[0].iterations >= 20);
+		assert!(report.solutions_found > 0);
+		assert!(report.hw_errors <= report.solutions_found);
+	}
+}
@@ -0,0 +1,547 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stratum traffic capture and replay. A capture file has one JSON object
+//! per line for every line sent or received and every connect and
+//! disconnect. Replaying plays the server's side of a capture to a client
+//! controller through fake connections, in the captured order but without
+//! the captured pauses, so a pool incident can be reproduced offline.
+
+use client::{self, Connection, Controller, Error};
+use core::config::MinerConfig;
+use core::{Algorithm, Solution};
+use serde_json::{self, Value};
+use stats::{self, SolutionStats};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use time;
+use types;
+use util::LOGGER;
+
+/// How long a replayed connection waits for the client to send a line
+/// the capture has it send before carrying on without it
+const REPLAY_GRACE: Duration = Duration::from_millis(500);
+
+/// The replay ends once nothing happened for this long
+const REPLAY_SETTLE: Duration = Duration::from_millis(300);
+
+/// Gives up on connections the client never made after this long
+const REPLAY_STUCK: Duration = Duration::from_secs(5);
+
+/// Captured in place of pool passwords
+const REDACTED: &str = "redacted";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Event {
+	/// connected, the line is the server address
+	Connect,
+	/// line received from the server
+	In,
+	/// line sent to the server
+	Out,
+	/// the server closed the connection, the line is the reason
+	Disconnect,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+	/// milliseconds since the Unix epoch
+	pub time: i64,
+	pub event: Event,
+	pub line: String,
+}
+
+/// Appends entries to a capture file
+pub struct Capture {
+	path: String,
+	file: File,
+	failed: bool,
+}
+
+impl Capture {
+	pub fn open(path: &str) -> Result<Capture, Error> {
+		let mut options = OpenOptions::new();
+		options.create(true).append(true);
+		// the capture shows the pool login and everything mined with it
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::OpenOptionsExt;
+			options.mode(0o600);
+		}
+		let file = options
+			.open(path)
+			.map_err(|e| Error::GeneralError(format!("Can't open capture file {}: {}", path, e)))?;
+		info!(LOGGER, "Capturing stratum traffic to {}", path);
+		Ok(Capture {
+			path: path.to_owned(),
+			file: file,
+			failed: false,
+		})
+	}
+
+	pub fn path(&self) -> &str {
+		&self.path
+	}
+
+	/// Adds an entry, a broken capture is reported once and never gets
+	/// in the way of mining
+	pub fn record(&mut self, event: Event, line: &str) {
+		let now = time::get_time();
+		let entry = Entry {
+			time: now.sec * 1000 + (now.nsec / 1_000_000) as i64,
+			event: event,
+			line: match event {
+				Event::Out => redact(line),
+				_ => line.to_owned(),
+			},
+		};
+		let result = serde_json::to_string(&entry)
+			.map_err(|e| io::Error::new(ErrorKind::Other, e))
+			.and_then(|json| writeln!(self.file, "{}", json));
+		match result {
+			Ok(_) => self.failed = false,
+			Err(e) => {
+				if !self.failed {
+					error!(LOGGER, "Can't write to capture file {}: {}", self.path, e);
+				}
+				self.failed = true;
+			}
+		}
+	}
+}
+
+/// A line sent to the server without the pool password of a `login` or
+/// `mining.authorize` request
+fn redact(line: &str) -> String {
+	let mut v = match serde_json::from_str::<Value>(line) {
+		Ok(v) => v,
+		Err(_) => return line.to_owned(),
+	};
+	let method = v["method"].as_str().unwrap_or("").to_owned();
+	let password = match method.as_str() {
+		"login" => v.get_mut("params").and_then(|p| p.get_mut("pass")),
+		"mining.authorize" => v.get_mut("params").and_then(|p| p.get_mut(1)),
+		_ => None,
+	};
+	match password {
+		Some(p) => *p = Value::String(REDACTED.to_owned()),
+		None => return line.to_owned(),
+	}
+	v.to_string()
+}
+
+/// Opens the configured capture file, mining goes on without a capture if
+/// it can't be opened
+pub fn open(path: &Option<String>) -> Option<Capture> {
+	let path = path.as_ref()?;
+	match Capture::open(path) {
+		Ok(capture) => Some(capture),
+		Err(e) => {
+			error!(LOGGER, "{:?}", e);
+			None
+		}
+	}
+}
+
+/// Parses the contents of a capture file
+pub fn parse(text: &str) -> Result<Vec<Entry>, Error> {
+	text.lines()
+		.enumerate()
+		.filter(|&(_, line)| !line.trim().is_empty())
+		.map(|(i, line)| {
+			serde_json::from_str(line)
+				.map_err(|e| Error::JsonError(format!("Capture line {}: {}", i + 1, e)))
+		})
+		.collect()
+}
+
+pub fn read(path: &str) -> Result<Vec<Entry>, Error> {
+	let text = fs::read_to_string(path)
+		.map_err(|e| Error::GeneralError(format!("Can't read capture file {}: {}", path, e)))?;
+	parse(&text)
+}
+
+/// The algorithm the captured client asked jobs for, if it did
+pub fn algorithm(entries: &[Entry]) -> Option<Algorithm> {
+	entries
+		.iter()
+		.filter(|e| e.event == Event::Out)
+		.filter_map(|e| serde_json::from_str::<types::RpcRequest>(&e.line).ok())
+		.filter(|r| r.method == "getjobtemplate")
+		.filter_map(|r| r.params)
+		.filter_map(|p| serde_json::from_value::<types::JobParams>(p).ok())
+		.filter_map(|p| {
+			[Algorithm::Cuckoo, Algorithm::RandomX, Algorithm::ProgPow]
+				.iter()
+				.cloned()
+				.find(|&a| client::job_algorithm(a) == p.algorithm)
+		})
+		.next()
+}
+
+/// What a replayed client did
+#[derive(Serialize)]
+pub struct Replay {
+	/// lines the client sent, over all connections
+	pub sent: Vec<String>,
+	/// captured lines the client didn't send this time
+	pub missing: Vec<String>,
+	/// what the miner was told
+	pub miner: Vec<types::MinerMessage>,
+	pub solutions: SolutionStats,
+}
+
+/// Replay state shared by the fake connections
+struct Progress {
+	sent: Vec<String>,
+	missing: Vec<String>,
+	/// connections whose script isn't played to the end yet
+	open_scripts: usize,
+	last_activity: Instant,
+}
+
+/// Plays the server's side of one captured connection. Received lines
+/// are handed out once the client sent what it had sent before them in
+/// the capture, submits are prompted by reporting the captured share as
+/// found by the miner.
+struct ReplayConnection {
+	script: VecDeque<Entry>,
+	/// whether later connections follow, the client is then made to
+	/// reconnect when the script ends, no matter who closed the captured
+	/// connection
+	last: bool,
+	finished: bool,
+	/// a received line being handed out
+	inbound: Vec<u8>,
+	/// the client's output not forming a complete line yet
+	outbound: Vec<u8>,
+	/// captured lines sent, by the client or skipped
+	credit: usize,
+	/// captured lines sent that were matched against the script
+	matched: usize,
+	waiting_since: Option<Instant>,
+	/// the share of the current script line was reported as found
+	injected: bool,
+	tx: mpsc::Sender<types::ClientMessage>,
+	progress: Arc<Mutex<Progress>>,
+}
+
+impl ReplayConnection {
+	fn finish(&mut self) {
+		if !self.finished {
+			self.finished = true;
+			self.progress.lock().unwrap().open_scripts -= 1;
+		}
+	}
+
+	fn next_entry(&mut self) {
+		self.script.pop_front();
+		self.waiting_since = None;
+		self.injected = false;
+		self.progress.lock().unwrap().last_activity = Instant::now();
+	}
+
+	/// Reports a captured share to the client as if the miner found it
+	fn inject_share(&mut self, line: &str) {
+		self.injected = true;
+		let req = match serde_json::from_str::<types::RpcRequest>(line) {
+			Ok(req) => req,
+			Err(_) => return,
+		};
+		if req.method != "submit" {
+			return;
+		}
+		if let Some(params) = req
+			.params
+			.and_then(|p| serde_json::from_value::<types::SubmitParams>(p).ok())
+		{
+			let solution = Solution::new(params.job_id, params.nonce, params.pow);
+			let _ = self
+				.tx
+				.send(types::ClientMessage::FoundSolution(params.height, solution));
+		}
+	}
+
+	fn idle() -> io::Result<usize> {
+		thread::sleep(Duration::from_millis(5));
+		Err(io::Error::new(ErrorKind::TimedOut, "nothing to replay yet"))
+	}
+}
+
+impl Read for ReplayConnection {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		loop {
+			if !self.inbound.is_empty() {
+				let n = buf.len().min(self.inbound.len());
+				buf[..n].copy_from_slice(&self.inbound[..n]);
+				self.inbound.drain(..n);
+				return Ok(n);
+			}
+			let entry = match self.script.front() {
+				Some(entry) => entry.clone(),
+				None => {
+					self.finish();
+					if self.last {
+						return ReplayConnection::idle();
+					}
+					return Ok(0);
+				}
+			};
+			match entry.event {
+				Event::In => {
+					self.inbound = format!("{}\n", entry.line).into_bytes();
+					self.next_entry();
+				}
+				Event::Out => {
+					if self.credit > self.matched {
+						self.matched += 1;
+						self.next_entry();
+						continue;
+					}
+					if !self.injected {
+						self.inject_share(&entry.line);
+					}
+					let since = *self.waiting_since.get_or_insert_with(Instant::now);
+					if since.elapsed() < REPLAY_GRACE {
+						return ReplayConnection::idle();
+					}
+					warn!(LOGGER, "Replay: client didn't send {}", entry.line);
+					self.progress.lock().unwrap().missing.push(entry.line);
+					self.credit += 1;
+				}
+				Event::Disconnect => {
+					self.next_entry();
+					self.script.clear();
+				}
+				Event::Connect => self.next_entry(),
+			}
+		}
+	}
+}
+
+impl Write for ReplayConnection {
+	fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+		self.outbound.extend_from_slice(b);
+		while let Some(pos) = self.outbound.iter().position(|b| *b == b'\n') {
+			let line: Vec<u8> = self.outbound.drain(..pos + 1).collect();
+			let line = String::from_utf8_lossy(&line).trim().to_string();
+			self.credit += 1;
+			let mut progress = self.progress.lock().unwrap();
+			progress.sent.push(line);
+			progress.last_activity = Instant::now();
+		}
+		Ok(b.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl Connection for ReplayConnection {
+	fn socket(&self) -> Option<&TcpStream> {
+		None
+	}
+}
+
+impl Drop for ReplayConnection {
+	fn drop(&mut self) {
+		self.finish();
+	}
+}
+
+/// Replays a capture to a client controller set up for `algorithm` with
+/// the stratum settings of `config`. Reconnect delays and the timers that
+/// depend on the captured pauses are turned off.
+pub fn replay(entries: &[Entry], algorithm: Algorithm, config: &MinerConfig) -> Result<Replay, Error> {
+	let mut scripts: VecDeque<VecDeque<Entry>> = VecDeque::new();
+	for entry in entries {
+		match entry.event {
+			Event::Connect => scripts.push_back(VecDeque::new()),
+			_ => {
+				if let Some(script) = scripts.back_mut() {
+					script.push_back(entry.clone());
+				}
+			}
+		}
+	}
+	if scripts.is_empty() {
+		return Err(Error::GeneralError(
+			"The capture has no connection to replay".to_owned(),
+		));
+	}
+
+	let mut config = config.clone();
+	config.stratum_capture_file = None;
+	config.stratum_backup_servers = vec![];
+	config.stratum_retry_min = 0;
+	config.stratum_retry_max = 0;
+	config.stratum_stall_timeout = 0;
+	config.stratum_keepalive_interval = 0;
+	config.stratum_idle_timeout = 0;
+
+	let progress = Arc::new(Mutex::new(Progress {
+		sent: vec![],
+		missing: vec![],
+		open_scripts: scripts.len(),
+		last_activity: Instant::now(),
+	}));
+	let (miner_tx, miner_rx) = mpsc::channel();
+	let stats = Arc::new(RwLock::new(stats::Stats::default()));
+	let mut controller = Controller::new(algorithm, &config, miner_tx, stats.clone())?;
+	let tx = controller.tx.clone();
+	let conn_progress = progress.clone();
+	controller.set_connector(Box::new(move || {
+		let script = scripts
+			.pop_front()
+			.ok_or_else(|| Error::ConnectionError("End of the capture".to_owned()))?;
+		Ok(Box::new(ReplayConnection {
+			script: script,
+			last: scripts.is_empty(),
+			finished: false,
+			inbound: vec![],
+			outbound: vec![],
+			credit: 0,
+			matched: 0,
+			waiting_since: None,
+			injected: false,
+			tx: tx.clone(),
+			progress: conn_progress.clone(),
+		}) as Box<dyn Connection>)
+	}));
+	let tx = controller.tx.clone();
+	let client = thread::spawn(move || controller.run());
+
+	loop {
+		thread::sleep(Duration::from_millis(50));
+		let p = progress.lock()?;
+		let idle = p.last_activity.elapsed();
+		if idle > REPLAY_SETTLE && (p.open_scripts == 0 || idle > REPLAY_STUCK) {
+			break;
+		}
+	}
+	tx.send(types::ClientMessage::Shutdown)?;
+	let _ = client.join();
+
+	let p = progress.lock()?;
+	let solutions = stats.read()?.mining_stats.solution_stats.clone();
+	Ok(Replay {
+		sent: p.sent.clone(),
+		missing: p.missing.clone(),
+		miner: miner_rx.try_iter().collect(),
+		solutions: solutions,
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// A pool answering a share too late after a block change, then
+	/// dropping the connection and sending the next job after the
+	/// reconnect
+	const STALE_SHARE_CAPTURE: &str = r#"
+{"time":1000,"event":"connect","line":"pool:3416"}
+{"time":1001,"event":"out","line":"{\"id\":\"1\",\"jsonrpc\":\"2.0\",\"method\":\"getjobtemplate\",\"params\":{\"algorithm\":\"randomx\"}}"}
+{"time":1020,"event":"in","line":"{\"id\":\"1\",\"jsonrpc\":\"2.0\",\"method\":\"getjobtemplate\",\"result\":{\"height\":5,\"job_id\":1,\"difficulty\":[[\"randomx\",1]],\"block_difficulty\":[[\"randomx\",1000]],\"pre_pow\":\"00\",\"epochs\":[[0,4096,[1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1]]],\"algorithm\":\"randomx\"},\"error\":null}"}
+{"time":5000,"event":"out","line":"{\"id\":\"2\",\"jsonrpc\":\"2.0\",\"method\":\"submit\",\"params\":{\"height\":5,\"job_id\":1,\"nonce\":42,\"pow\":{\"RandomX\":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0]}}}"}
+{"time":5100,"event":"in","line":"{\"id\":\"2\",\"jsonrpc\":\"2.0\",\"method\":\"submit\",\"result\":null,\"error\":{\"code\":-32503,\"message\":\"Solution submitted too late\"}}"}
+{"time":5200,"event":"disconnect","line":"connection closed by server"}
+{"time":6200,"event":"connect","line":"pool:3416"}
+{"time":6201,"event":"out","line":"{\"id\":\"3\",\"jsonrpc\":\"2.0\",\"method\":\"getjobtemplate\",\"params\":{\"algorithm\":\"randomx\"}}"}
+{"time":6220,"event":"in","line":"{\"id\":\"Stratum\",\"jsonrpc\":\"2.0\",\"method\":\"job\",\"params\":{\"height\":6,\"job_id\":2,\"difficulty\":[[\"randomx\",1]],\"block_difficulty\":[[\"randomx\",1000]],\"pre_pow\":\"00\",\"epochs\":[[0,4096,[1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1]]],\"algorithm\":\"randomx\"}}"}
+"#;
+
+	#[test]
+	fn capture_round_trip() {
+		let path = std::env::temp_dir().join(format!("epic-miner-capture-{}", std::process::id()));
+		let path = path.to_str().unwrap().to_owned();
+		{
+			let mut capture = Capture::open(&path).unwrap();
+			capture.record(Event::Connect, "pool:3416");
+			capture.record(Event::Out, "{\"id\":\"1\"}");
+		}
+		let entries = read(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+		assert_eq!(entries.len(), 2);
+		assert_eq!(entries[0].event, Event::Connect);
+		assert_eq!(entries[1].line, "{\"id\":\"1\"}");
+		assert!(entries[1].time >= entries[0].time);
+		assert!(parse("{\"time\":1}").is_err());
+	}
+
+	#[test]
+	fn capture_hides_passwords() {
+		let path = std::env::temp_dir().join(format!("epic-miner-login-{}", std::process::id()));
+		let path = path.to_str().unwrap().to_owned();
+		{
+			let mut capture = Capture::open(&path).unwrap();
+			capture.record(
+				Event::Out,
+				r#"{"id":"1","jsonrpc":"2.0","method":"login","params":{"login":"w1","pass":"secret","agent":"a"}}"#,
+			);
+			capture.record(
+				Event::Out,
+				r#"{"id":2,"method":"mining.authorize","params":["w1","secret"]}"#,
+			);
+			capture.record(Event::In, r#"{"id":"1","result":"ok","error":null}"#);
+		}
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			let mode = fs::metadata(&path).unwrap().permissions().mode();
+			assert_eq!(mode & 0o777, 0o600);
+		}
+		let entries = read(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+		assert_eq!(entries.len(), 3);
+		for e in &entries[..2] {
+			assert!(!e.line.contains("secret"), "{}", e.line);
+			assert!(e.line.contains("w1"));
+			assert!(e.line.contains(REDACTED));
+		}
+	}
+
+	#[test]
+	fn replay_stale_share() {
+		let entries = parse(STALE_SHARE_CAPTURE).unwrap();
+		assert_eq!(algorithm(&entries), Some(Algorithm::RandomX));
+		let replay = replay(&entries, Algorithm::RandomX, &MinerConfig::default()).unwrap();
+
+		assert_eq!(replay.missing, Vec::<String>::new());
+		let methods: Vec<String> = replay
+			.sent
+			.iter()
+			.map(|l| serde_json::from_str::<types::RpcRequest>(l).unwrap().method)
+			.collect();
+		assert_eq!(methods, vec!["getjobtemplate", "submit", "getjobtemplate"]);
+		let heights: Vec<u64> = replay
+			.miner
+			.iter()
+			.filter_map(|m| match *m {
+				types::MinerMessage::ReceivedJob(height, _, _, _) => Some(height),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(heights, vec![5, 6]);
+		assert_eq!(replay.solutions.num_staled, 1);
+		assert_eq!(replay.solutions.num_rejected, 0);
+	}
+}
@@ -15,20 +15,34 @@
 //! Client network controller, controls requests and responses from the
 //! stratum server
 
-use bufstream::BufStream;
+use capture::{self, Capture, Event};
+use core::config::{MinerConfig, ProxyConfig, StratumServerConfig, TlsConfig};
 use core::Algorithm;
 use core::{AlgorithmParams, Solution};
-use native_tls::{TlsConnector, TlsStream};
+use native_tls::{Certificate, Identity, TlsConnector, TlsStream};
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::PKey;
+use openssl::sha::sha256;
+use openssl::x509::X509;
+use protocol::{self, Protocol};
+use rand::{self, Rng};
 use serde_json;
 use stats;
 use std;
-use std::io::{self, BufRead, ErrorKind, Read, Write};
-use std::net::TcpStream;
-use std::sync::{mpsc, Arc, RwLock};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::thread;
+use std::time::Instant;
 use time;
+use tunnel;
 use types;
+use util::to_hex;
 use util::LOGGER;
+use websocket::{self, WsStream};
 
 #[derive(Debug)]
 pub enum Error {
@@ -37,6 +51,7 @@ pub enum Error {
 	ResponseError(String),
 	JsonError(String),
 	GeneralError(String),
+	TlsError(String),
 }
 
 impl From<serde_json::error::Error> for Error {
@@ -57,121 +72,465 @@ impl<T> From<std::sync::mpsc::SendError<T>> for Error {
 	}
 }
 
+/// A connection to a stratum server, plain or TLS
+pub trait Connection: Read + Write + Send {
+	/// The TCP socket underneath, if there's one. The reader waits on it
+	/// for data without holding up writes.
+	fn socket(&self) -> Option<&TcpStream>;
+}
+
+/// Opens connections instead of connecting to the configured server,
+/// e.g. to replay a capture
+pub type Connector = Box<dyn FnMut() -> Result<Box<dyn Connection>, Error> + Send>;
+
+impl Connection for TcpStream {
+	fn socket(&self) -> Option<&TcpStream> {
+		Some(self)
+	}
+}
+
+impl Connection for TlsStream<TcpStream> {
+	fn socket(&self) -> Option<&TcpStream> {
+		Some(self.get_ref())
+	}
+}
+
+impl<S: Connection> Connection for WsStream<S> {
+	fn socket(&self) -> Option<&TcpStream> {
+		self.get_ref().socket()
+	}
+}
+
+/// A request sent to the server that hasn't been answered yet
+struct PendingRequest {
+	method: String,
+	/// height and solution of a submitted share
+	share: Option<(u64, Solution)>,
+	sent_at: Instant,
+}
+
+fn duration_ms(d: std::time::Duration) -> f64 {
+	d.as_secs() as f64 * 1000.0 + d.subsec_micros() as f64 / 1000.0
+}
+
+/// Name of an algorithm as shown in the stats
+pub fn algorithm_name(algorithm: Algorithm) -> String {
+	match algorithm {
+		Algorithm::Cuckoo => "Cuckatoo".to_string(),
+		Algorithm::RandomX => "RandomX".to_string(),
+		Algorithm::ProgPow => "ProgPow".to_string(),
+	}
+}
+
+/// Name of an algorithm in job templates and job requests
+pub fn job_algorithm(algorithm: Algorithm) -> String {
+	match algorithm {
+		Algorithm::Cuckoo => "cuckoo".to_string(),
+		Algorithm::RandomX => "randomx".to_string(),
+		Algorithm::ProgPow => "progpow".to_string(),
+	}
+}
+
+/// How long the server gets to answer the WebSocket upgrade
+const WS_HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+/// How long the reader of a connection without a socket leaves the lock to
+/// writers between reads
+const READ_PAUSE_MS: u64 = 1;
+
+/// An open stratum connection. A reader thread forwards every complete line
+/// to the controller as soon as it arrives, writes go straight to the
+/// connection. TLS and WebSocket connections can't be split into a read and
+/// a write half, so the reader waits for data on a clone of the socket and
+/// only takes the connection lock to read what has arrived. Dropping the
+/// stream stops the reader.
 struct Stream {
-	stream: Option<BufStream<TcpStream>>,
-	tls_stream: Option<BufStream<TlsStream<TcpStream>>>,
+	id: u64,
+	conn: Arc<Mutex<Box<dyn Connection>>>,
+	socket: Option<TcpStream>,
+	stop: Arc<AtomicBool>,
 }
 
 impl Stream {
-	fn new() -> Stream {
-		Stream {
-			stream: None,
-			tls_stream: None,
-		}
-	}
-	fn try_connect(&mut self, server_url: &str, tls: Option<bool>) -> Result<(), Error> {
-		match TcpStream::connect(server_url) {
-			Ok(conn) => {
-				if tls.is_some() && tls.unwrap() {
-					let connector = TlsConnector::new().map_err(|e| {
-						Error::ConnectionError(format!("Can't create TLS connector: {:?}", e))
-					})?;
-					let url_port: Vec<&str> = server_url.split(":").collect();
-					let splitted_url: Vec<&str> = url_port[0].split(".").collect();
-					let base_host = format!(
-						"{}.{}",
-						splitted_url[splitted_url.len() - 2],
-						splitted_url[splitted_url.len() - 1]
-					);
-					let mut stream = connector.connect(&base_host, conn).map_err(|e| {
-						Error::ConnectionError(format!("Can't establish TLS connection: {:?}", e))
-					})?;
-					stream.get_mut().set_nonblocking(true).map_err(|e| {
-						Error::ConnectionError(format!("Can't switch to nonblocking mode: {:?}", e))
-					})?;
-					self.tls_stream = Some(BufStream::new(stream));
-				} else {
-					let _ = conn.set_nonblocking(true).map_err(|e| {
-						Error::ConnectionError(format!("Can't switch to nonblocking mode: {:?}", e))
-					})?;
-					self.stream = Some(BufStream::new(conn));
-				}
-				Ok(())
+	fn new(
+		id: u64,
+		conn: Box<dyn Connection>,
+		tx: mpsc::Sender<types::ClientMessage>,
+	) -> Result<Stream, Error> {
+		let socket_error =
+			|e: io::Error| Error::ConnectionError(format!("Can't set up socket: {:?}", e));
+		let socket = match conn.socket() {
+			Some(socket) => {
+				// the WebSocket handshake leaves a timeout behind
+				socket.set_read_timeout(None).map_err(socket_error)?;
+				Some(socket.try_clone().map_err(socket_error)?)
 			}
-			Err(e) => Err(Error::ConnectionError(format!("{}", e))),
+			None => None,
+		};
+		let reader_socket = match socket {
+			Some(ref socket) => Some(socket.try_clone().map_err(socket_error)?),
+			None => None,
+		};
+		let conn = Arc::new(Mutex::new(conn));
+		let stop = Arc::new(AtomicBool::new(false));
+		let reader_conn = conn.clone();
+		let reader_stop = stop.clone();
+		thread::Builder::new()
+			.name("stratum_reader".to_string())
+			.spawn(move || read_lines(id, reader_conn, reader_socket, reader_stop, tx))
+			.map_err(|e| Error::GeneralError(format!("Can't start reader thread: {:?}", e)))?;
+		Ok(Stream {
+			id,
+			conn,
+			socket,
+			stop,
+		})
+	}
+}
+
+impl Drop for Stream {
+	fn drop(&mut self) {
+		self.stop.store(true, Ordering::Relaxed);
+		// wakes up the reader waiting for data
+		if let Some(ref socket) = self.socket {
+			let _ = socket.shutdown(Shutdown::Both);
 		}
 	}
 }
 
 impl Write for Stream {
 	fn write(&mut self, b: &[u8]) -> Result<usize, std::io::Error> {
-		if self.tls_stream.is_some() {
-			self.tls_stream.as_mut().unwrap().write(b)
-		} else {
-			self.stream.as_mut().unwrap().write(b)
-		}
+		self.conn.lock().unwrap().write(b)
 	}
 	fn flush(&mut self) -> Result<(), std::io::Error> {
-		if self.tls_stream.is_some() {
-			self.tls_stream.as_mut().unwrap().flush()
-		} else {
-			self.stream.as_mut().unwrap().flush()
+		self.conn.lock().unwrap().flush()
+	}
+}
+
+/// Moves everything that has arrived on `conn` to `pending`, false once the
+/// server closed the connection. Reads don't block while the socket is
+/// switched to non-blocking, which is safe as writers wait for the lock
+/// the caller holds.
+fn read_available(
+	conn: &mut dyn Connection,
+	socket: Option<&TcpStream>,
+	pending: &mut Vec<u8>,
+) -> io::Result<bool> {
+	if let Some(socket) = socket {
+		socket.set_nonblocking(true)?;
+	}
+	let mut buf = [0u8; 4096];
+	// TLS and WebSocket buffer data of their own, so read until there's
+	// nothing left rather than once
+	let res = loop {
+		match conn.read(&mut buf) {
+			Ok(0) => break Ok(false),
+			Ok(n) => pending.extend_from_slice(&buf[..n]),
+			Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+				break Ok(true)
+			}
+			Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+			Err(e) => break Err(e),
 		}
+	};
+	if let Some(socket) = socket {
+		socket.set_nonblocking(false)?;
 	}
+	res
 }
-impl Read for Stream {
-	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-		if self.tls_stream.is_some() {
-			self.tls_stream.as_mut().unwrap().read(buf)
-		} else {
-			self.stream.as_mut().unwrap().read(buf)
+
+/// Reader thread body, splits the incoming data into lines and hands them
+/// to the controller together with their time of arrival
+fn read_lines(
+	id: u64,
+	conn: Arc<Mutex<Box<dyn Connection>>>,
+	socket: Option<TcpStream>,
+	stop: Arc<AtomicBool>,
+	tx: mpsc::Sender<types::ClientMessage>,
+) {
+	let mut pending: Vec<u8> = vec![];
+	while !stop.load(Ordering::Relaxed) {
+		// blocks until the server sends something, or closes the
+		// connection, without the lock
+		let waited = match socket {
+			Some(ref socket) => socket.peek(&mut [0u8; 1]).map(|_| ()),
+			None => Ok(()),
+		};
+		let res = waited.and_then(|_| {
+			let mut conn = conn.lock().unwrap();
+			read_available(&mut **conn, socket.as_ref(), &mut pending)
+		});
+		let received = Instant::now();
+		while let Some(pos) = pending.iter().position(|b| *b == b'\n') {
+			let line: Vec<u8> = pending.drain(..pos + 1).collect();
+			let line = String::from_utf8_lossy(&line).trim().to_string();
+			if line.is_empty() {
+				continue;
+			}
+			let msg = types::ClientMessage::ReceivedLine(id, line, received);
+			if tx.send(msg).is_err() {
+				return;
+			}
+		}
+		let reason = match res {
+			Ok(true) => {
+				if socket.is_none() {
+					thread::sleep(std::time::Duration::from_millis(READ_PAUSE_MS));
+				}
+				continue;
+			}
+			Ok(false) => "connection closed by server".to_string(),
+			Err(e) => format!("{}", e),
+		};
+		if !stop.load(Ordering::Relaxed) {
+			let _ = tx.send(types::ClientMessage::ConnectionLost(id, reason));
 		}
+		return;
 	}
 }
 
-impl BufRead for Stream {
-	fn fill_buf(&mut self) -> io::Result<&[u8]> {
-		if self.tls_stream.is_some() {
-			self.tls_stream.as_mut().unwrap().fill_buf()
-		} else {
-			self.stream.as_mut().unwrap().fill_buf()
+/// Connects to `addr`, through the proxy if one is configured
+fn open_tcp(addr: &str, proxy: &Option<ProxyConfig>) -> io::Result<TcpStream> {
+	match *proxy {
+		Some(ref proxy) => tunnel::connect(proxy, addr),
+		None => TcpStream::connect(addr),
+	}
+}
+
+/// host:port to open the TCP connection to for a server address, which
+/// may be a ws:// or wss:// URL
+fn tcp_addr(server_url: &str) -> String {
+	match websocket::Url::parse(server_url) {
+		Some(url) => url.authority(),
+		None => server_url.to_owned(),
+	}
+}
+
+/// Opens a connection to the stratum server, wrapped in TLS if enabled or
+/// the address is a wss:// URL, and in a WebSocket for ws:// and wss://
+fn connect(
+	server_url: &str,
+	tls: Option<bool>,
+	tls_config: &TlsConfig,
+	proxy: &Option<ProxyConfig>,
+) -> Result<Box<dyn Connection>, Error> {
+	let ws = websocket::Url::parse(server_url);
+	let addr = tcp_addr(server_url);
+	let tls = tls.unwrap_or(false) || ws.as_ref().map(|url| url.secure) == Some(true);
+	match open_tcp(&addr, proxy) {
+		Ok(conn) => {
+			if tls {
+				let server_name = match tls_config.server_name.clone() {
+					Some(name) => name,
+					None => server_host(&addr),
+				};
+				let connector = tls_connector(tls_config, &server_name)?;
+				let stream = connector.connect(&server_name, conn).map_err(|e| {
+					Error::TlsError(format!(
+						"Can't establish TLS connection to {}: {}",
+						server_name, e
+					))
+				})?;
+				if let Some(ref fingerprint) = tls_config.cert_fingerprint {
+					check_fingerprint(&stream, fingerprint)?;
+				}
+				match ws {
+					Some(ref url) => upgrade(stream, url),
+					None => Ok(Box::new(stream)),
+				}
+			} else {
+				match ws {
+					Some(ref url) => upgrade(conn, url),
+					None => Ok(Box::new(conn)),
+				}
+			}
 		}
+		Err(e) => Err(Error::ConnectionError(format!("{}", e))),
 	}
-	fn consume(&mut self, amt: usize) {
-		if self.tls_stream.is_some() {
-			self.tls_stream.as_mut().unwrap().consume(amt)
-		} else {
-			self.stream.as_mut().unwrap().consume(amt)
+}
+
+/// Switches `conn` over to the WebSocket protocol
+fn upgrade<S>(conn: S, url: &websocket::Url) -> Result<Box<dyn Connection>, Error>
+where
+	S: Connection + 'static,
+{
+	if let Some(socket) = conn.socket() {
+		socket
+			.set_read_timeout(Some(std::time::Duration::from_secs(WS_HANDSHAKE_TIMEOUT_SECS)))
+			.map_err(|e| Error::ConnectionError(format!("Can't set read timeout: {:?}", e)))?;
+	}
+	match WsStream::connect(conn, url) {
+		Ok(ws) => Ok(Box::new(ws)),
+		Err(e) => Err(Error::ConnectionError(format!(
+			"WebSocket handshake with {} failed: {}",
+			url.authority(),
+			e
+		))),
+	}
+}
+
+/// Host part of a `host:port` server address, IPv6 brackets removed
+fn server_host(server_url: &str) -> String {
+	let host = match server_url.rfind(':') {
+		Some(i) if !server_url[i..].contains(']') => &server_url[..i],
+		_ => server_url,
+	};
+	host.trim_start_matches('[')
+		.trim_end_matches(']')
+		.to_string()
+}
+
+fn read_file(path: &str, what: &str) -> Result<Vec<u8>, Error> {
+	let mut buf = vec![];
+	File::open(path)
+		.and_then(|mut f| f.read_to_end(&mut buf))
+		.map_err(|e| Error::TlsError(format!("Can't read {} {}: {}", what, path, e)))?;
+	Ok(buf)
+}
+
+/// Builds the TLS connector from the configured CA bundle, client
+/// certificate and validation switches
+fn tls_connector(config: &TlsConfig, server_name: &str) -> Result<TlsConnector, Error> {
+	let mut builder = TlsConnector::builder();
+	if let Some(ref ca_file) = config.ca_file {
+		let pem = read_file(ca_file, "CA file")?;
+		let certs = X509::stack_from_pem(&pem)
+			.map_err(|e| Error::TlsError(format!("Invalid CA file {}: {}", ca_file, e)))?;
+		if certs.is_empty() {
+			return Err(Error::TlsError(format!(
+				"No certificates found in CA file {}",
+				ca_file
+			)));
+		}
+		for cert in certs {
+			let cert = cert
+				.to_der()
+				.map_err(|e| e.to_string())
+				.and_then(|der| Certificate::from_der(&der).map_err(|e| e.to_string()))
+				.map_err(|e| Error::TlsError(format!("Invalid CA file {}: {}", ca_file, e)))?;
+			builder.add_root_certificate(cert);
 		}
 	}
-	fn read_until(&mut self, byte: u8, buf: &mut Vec<u8>) -> io::Result<usize> {
-		if self.tls_stream.is_some() {
-			self.tls_stream.as_mut().unwrap().read_until(byte, buf)
-		} else {
-			self.stream.as_mut().unwrap().read_until(byte, buf)
+	match (&config.client_cert_file, &config.client_key_file) {
+		(Some(cert_file), Some(key_file)) => {
+			builder.identity(client_identity(cert_file, key_file)?);
+		}
+		(None, None) => {}
+		_ => {
+			return Err(Error::TlsError(
+				"Both client_cert_file and client_key_file are needed for a client certificate"
+					.to_string(),
+			))
 		}
 	}
-	fn read_line(&mut self, string: &mut String) -> io::Result<usize> {
-		if self.tls_stream.is_some() {
-			self.tls_stream.as_mut().unwrap().read_line(string)
-		} else {
-			self.stream.as_mut().unwrap().read_line(string)
+	// A pinned certificate replaces the usual chain validation
+	if config.accept_invalid_certs || config.cert_fingerprint.is_some() {
+		builder.danger_accept_invalid_certs(true);
+		builder.danger_accept_invalid_hostnames(true);
+	}
+	// SNI isn't defined for IP addresses
+	if server_name.parse::<IpAddr>().is_ok() {
+		builder.use_sni(false);
+	}
+	builder
+		.build()
+		.map_err(|e| Error::TlsError(format!("Can't create TLS connector: {}", e)))
+}
+
+/// Packs a PEM certificate and key into the PKCS#12 identity native-tls
+/// expects
+fn client_identity(cert_file: &str, key_file: &str) -> Result<Identity, Error> {
+	let cert = X509::from_pem(&read_file(cert_file, "client certificate")?)
+		.map_err(|e| Error::TlsError(format!("Invalid client certificate {}: {}", cert_file, e)))?;
+	let key = PKey::private_key_from_pem(&read_file(key_file, "client key")?)
+		.map_err(|e| Error::TlsError(format!("Invalid client key {}: {}", key_file, e)))?;
+	let pkcs12 = Pkcs12::builder()
+		.build("", "epic-miner", &key, &cert)
+		.and_then(|p| p.to_der())
+		.map_err(|e| Error::TlsError(format!("Can't use client certificate: {}", e)))?;
+	Identity::from_pkcs12(&pkcs12, "")
+		.map_err(|e| Error::TlsError(format!("Can't use client certificate: {}", e)))
+}
+
+/// Compares the SHA-256 fingerprint of the server certificate with the
+/// pinned one, colons and case in the configured value are ignored
+fn check_fingerprint(stream: &TlsStream<TcpStream>, expected: &str) -> Result<(), Error> {
+	let cert = stream
+		.peer_certificate()
+		.map_err(|e| Error::TlsError(format!("Can't read server certificate: {}", e)))?
+		.ok_or(Error::TlsError(
+			"Server didn't present a certificate".to_string(),
+		))?;
+	let der = cert
+		.to_der()
+		.map_err(|e| Error::TlsError(format!("Can't read server certificate: {}", e)))?;
+	let actual = to_hex(sha256(&der).to_vec());
+	let expected = expected.replace(":", "").to_lowercase();
+	if actual != expected {
+		return Err(Error::TlsError(format!(
+			"Server certificate fingerprint {} doesn't match pinned fingerprint {}",
+			actual, expected
+		)));
+	}
+	Ok(())
+}
+
+/// Whether a server accepts TCP connections, through its proxy if it has
+/// one
+fn server_reachable(server: &StratumServerConfig) -> bool {
+	let addr = tcp_addr(&server.addr);
+	if let Some(ref proxy) = server.proxy {
+		return tunnel::connect(proxy, &addr).is_ok();
+	}
+	let addrs = match addr.to_socket_addrs() {
+		Ok(a) => a,
+		Err(_) => return false,
+	};
+	for addr in addrs {
+		if TcpStream::connect_timeout(&addr, std::time::Duration::from_secs(5)).is_ok() {
+			return true;
 		}
 	}
+	false
 }
 
 pub struct Controller {
 	_id: u32,
 	algorithm: Algorithm,
-	server_url: String,
-	server_login: Option<String>,
-	server_password: Option<String>,
-	server_tls_enabled: Option<bool>,
+	servers: Vec<StratumServerConfig>,
+	active_server: usize,
+	failed_connects: u32,
+	failover_retries: u32,
+	stall_timeout: i64,
+	primary_retry_interval: i64,
+	/// a check whether the primary server is back is under way
+	probing_primary: bool,
+	last_job_time: i64,
 	stream: Option<Stream>,
+	next_stream_id: u64,
+	line_received: Option<Instant>,
 	rx: mpsc::Receiver<types::ClientMessage>,
 	pub tx: mpsc::Sender<types::ClientMessage>,
 	miner_tx: mpsc::Sender<types::MinerMessage>,
+	/// miners of every algorithm when following the algorithm of the
+	/// jobs, empty otherwise
+	followed: Vec<(Algorithm, mpsc::Sender<types::MinerMessage>)>,
+	/// since when the active miner has a job
+	mining_since: Option<Instant>,
 	last_request_id: u32,
+	pending: HashMap<String, PendingRequest>,
+	request_timeout: u64,
+	protocol: Box<dyn Protocol>,
+	keepalive_interval: i64,
+	idle_timeout: i64,
+	last_message_time: i64,
+	retry_min: u64,
+	retry_max: u64,
+	retry_attempts: u32,
+	share_queue: VecDeque<(u64, Solution)>,
+	share_queue_size: usize,
+	connector: Option<Connector>,
+	capture: Option<Capture>,
 	stats: Arc<RwLock<stats::Stats>>,
 }
 
@@ -185,82 +544,289 @@ fn invlalid_error_response() -> types::RpcError {
 impl Controller {
 	pub fn new(
 		algorithm: Algorithm,
-		server_url: &str,
-		server_login: Option<String>,
-		server_password: Option<String>,
-		server_tls_enabled: Option<bool>,
+		config: &MinerConfig,
 		miner_tx: mpsc::Sender<types::MinerMessage>,
 		stats: Arc<RwLock<stats::Stats>>,
 	) -> Result<Controller, Error> {
 		let (tx, rx) = mpsc::channel::<types::ClientMessage>();
+		// the stats are labeled with it before the server says anything
+		stats.write()?.client_stats.my_algorithm = algorithm_name(algorithm);
 		Ok(Controller {
 			_id: 0,
 			algorithm,
-			server_url: server_url.to_string(),
-			server_login: server_login,
-			server_password: server_password,
-			server_tls_enabled: server_tls_enabled,
+			servers: config.stratum_servers(),
+			active_server: 0,
+			failed_connects: 0,
+			failover_retries: config.stratum_failover_retries,
+			stall_timeout: config.stratum_stall_timeout as i64,
+			primary_retry_interval: config.stratum_primary_retry_interval as i64,
+			probing_primary: false,
+			last_job_time: time::get_time().sec,
 			stream: None,
+			next_stream_id: 0,
+			line_received: None,
 			tx: tx,
 			rx: rx,
 			miner_tx: miner_tx,
+			followed: vec![],
+			mining_since: None,
 			last_request_id: 0,
+			pending: HashMap::new(),
+			request_timeout: config.stratum_request_timeout,
+			protocol: protocol::create(config.stratum_protocol),
+			keepalive_interval: config.stratum_keepalive_interval as i64,
+			idle_timeout: config.stratum_idle_timeout as i64,
+			last_message_time: time::get_time().sec,
+			retry_min: config.stratum_retry_min,
+			retry_max: config.stratum_retry_max,
+			retry_attempts: 0,
+			share_queue: VecDeque::new(),
+			share_queue_size: config.stratum_share_queue_size,
+			connector: None,
+			capture: capture::open(&config.stratum_capture_file),
 			stats: stats,
 		})
 	}
 
+	/// Follows the algorithm the jobs ask for, switching between the
+	/// given miners. Only the miner of our current algorithm is left
+	/// running.
+	pub fn follow(&mut self, miners: Vec<(Algorithm, mpsc::Sender<types::MinerMessage>)>) {
+		for &(algorithm, ref tx) in &miners {
+			if algorithm != self.algorithm {
+				let _ = tx.send(types::MinerMessage::StopJob);
+			}
+		}
+		self.followed = miners;
+	}
+
+	/// Makes the client get its connections from `connector`
+	pub fn set_connector(&mut self, connector: Connector) {
+		self.connector = Some(connector);
+	}
+
+	/// Adds a line to the traffic capture, if one is configured
+	fn record(&mut self, event: Event, line: &str) {
+		if let Some(ref mut capture) = self.capture {
+			capture.record(event, line);
+		}
+	}
+
+	/// Sends a message to every miner, not just the active one, so the
+	/// idle ones are ready when the pool switches algorithm
+	fn send_to_miners(&self, message: &dyn Fn() -> types::MinerMessage) -> Result<(), Error> {
+		if self.followed.is_empty() {
+			return self.miner_tx.send(message()).map_err(|e| e.into());
+		}
+		for &(_, ref tx) in &self.followed {
+			tx.send(message())?;
+		}
+		Ok(())
+	}
+
+	/// Makes the miner of `algorithm` the active one, false if there's
+	/// none
+	fn switch_algorithm(&mut self, algorithm: Algorithm) -> Result<bool, Error> {
+		let tx = match self.followed.iter().find(|&&(a, _)| a == algorithm) {
+			Some(&(_, ref tx)) => tx.clone(),
+			None => return Ok(false),
+		};
+		info!(
+			LOGGER,
+			"Pool switched algorithm, mining {:?} instead of {:?}", algorithm, self.algorithm
+		);
+		self.send_miner_stop()?;
+		self.algorithm = algorithm;
+		self.miner_tx = tx;
+		self.stats.write()?.client_stats.my_algorithm = algorithm_name(algorithm);
+		Ok(true)
+	}
+
+	/// Adds the time since the last call to the active algorithm
+	fn update_algorithm_time(&mut self) {
+		if self.followed.is_empty() {
+			return;
+		}
+		if let Some(since) = self.mining_since {
+			let now = Instant::now();
+			let mut stats = self.stats.write().unwrap();
+			*stats
+				.client_stats
+				.algorithm_seconds
+				.entry(algorithm_name(self.algorithm))
+				.or_insert(0.0) += duration_ms(now - since) / 1000.0;
+			self.mining_since = Some(now);
+		}
+	}
+
+	/// The stratum server currently in use
+	fn server(&self) -> &StratumServerConfig {
+		&self.servers[self.active_server]
+	}
+
 	pub fn try_connect(&mut self) -> Result<(), Error> {
-		self.stream = Some(Stream::new());
-		self.stream
-			.as_mut()
-			.unwrap()
-			.try_connect(&self.server_url, self.server_tls_enabled)?;
+		let server = self.server().clone();
+		self.stream = None;
+		// answers to requests on an old connection never arrive, shares
+		// the server may never have seen go back into the queue
+		let mut unanswered: Vec<PendingRequest> = self.pending.drain().map(|(_, p)| p).collect();
+		unanswered.sort_by_key(|p| p.sent_at);
+		for p in unanswered {
+			if let Some((height, solution)) = p.share {
+				self.queue_share(height, solution);
+			}
+		}
+		let conn = match self.connector {
+			Some(ref mut connect) => connect()?,
+			None => connect(
+				&server.addr,
+				server.tls_enabled,
+				&server.tls.unwrap_or_default(),
+				&server.proxy,
+			)?,
+		};
+		self.next_stream_id += 1;
+		self.stream = Some(Stream::new(self.next_stream_id, conn, self.tx.clone())?);
+		self.record(Event::Connect, &server.addr);
+		self.protocol = protocol::create(server.protocol.unwrap_or_default());
+		// an extranonce only applies to the connection that assigned it
+		self.send_to_miners(&|| types::MinerMessage::SetExtranonce(vec![]))?;
+		for line in self.protocol.handshake()? {
+			self.send_message(&line)?;
+		}
 		Ok(())
 	}
 
-	fn read_message(&mut self) -> Result<Option<String>, Error> {
-		if let None = self.stream {
-			return Err(Error::ConnectionError("broken pipe".to_string()));
+	/// Drops the current connection and makes the server at `index` the
+	/// active one, the next loop iteration connects to it
+	fn switch_server(&mut self, index: usize, reason: String) {
+		let from = self.server().addr.clone();
+		self.active_server = index;
+		self.failed_connects = 0;
+		self.stream = None;
+		self.last_job_time = time::get_time().sec;
+		let to = self.server().addr.clone();
+		warn!(
+			LOGGER,
+			"Switching stratum server from {} to {}: {}", from, to, reason
+		);
+		let mut stats = self.stats.write().unwrap();
+		stats.client_stats.server_url = to.clone();
+		stats.client_stats.active_server = index;
+		stats.client_stats.server_switch_reason = reason;
+		stats.client_stats.connected = false;
+		stats.client_stats.connection_status =
+			format!("Connection Status: Switching to server {}", to);
+	}
+
+	/// Moves on to the next server in the list, wrapping around to the
+	/// primary one after the last backup
+	fn switch_to_next_server(&mut self, reason: String) {
+		let next = (self.active_server + 1) % self.servers.len();
+		self.switch_server(next, reason);
+	}
+
+	/// Takes over the stratum settings of a reloaded configuration, the
+	/// connection is only dropped when the server in use changed
+	fn reconfigure(&mut self, config: &MinerConfig) {
+		self.failover_retries = config.stratum_failover_retries;
+		self.stall_timeout = config.stratum_stall_timeout as i64;
+		self.primary_retry_interval = config.stratum_primary_retry_interval as i64;
+		self.request_timeout = config.stratum_request_timeout;
+		self.keepalive_interval = config.stratum_keepalive_interval as i64;
+		self.idle_timeout = config.stratum_idle_timeout as i64;
+		self.retry_min = config.stratum_retry_min;
+		self.retry_max = config.stratum_retry_max;
+		self.share_queue_size = config.stratum_share_queue_size;
+		if self.capture.as_ref().map(|c| c.path()) != config.stratum_capture_file.as_deref() {
+			self.capture = capture::open(&config.stratum_capture_file);
 		}
-		let mut line = String::new();
-		match self.stream.as_mut().unwrap().read_line(&mut line) {
-			Ok(_) => {
-				// stream is not returning a proper error on disconnect
-				if line == "" {
-					return Err(Error::ConnectionError("broken pipe".to_string()));
-				}
-				return Ok(Some(line));
+		while self.share_queue.len() > self.share_queue_size {
+			self.share_queue.pop_front();
+			self.stats.write().unwrap().client_stats.num_dropped_shares += 1;
+		}
+		self.stats.write().unwrap().client_stats.queued_shares = self.share_queue.len();
+
+		let servers = config.stratum_servers();
+		if servers == self.servers {
+			return;
+		}
+		let same_server = servers.get(self.active_server) == Some(self.server());
+		self.servers = servers;
+		if !same_server {
+			self.switch_server(0, "configuration changed".to_string());
+		}
+	}
+
+	/// Applies server settings changed at runtime and reconnects with them
+	fn change_server(&mut self, change: types::ServerChange) -> Result<String, String> {
+		let index = if change.addr.is_some() {
+			0
+		} else {
+			self.active_server
+		};
+		{
+			let server = &mut self.servers[index];
+			if let Some(addr) = change.addr {
+				server.addr = addr;
 			}
-			Err(ref e) if e.kind() == ErrorKind::BrokenPipe => {
-				return Err(Error::ConnectionError("broken pipe".to_string()));
+			if change.login.is_some() {
+				server.login = change.login;
 			}
-			Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
-				return Ok(None);
+			if change.password.is_some() {
+				server.password = change.password;
 			}
+			if change.tls_enabled.is_some() {
+				server.tls_enabled = change.tls_enabled;
+			}
+			if change.protocol.is_some() {
+				server.protocol = change.protocol;
+			}
+		}
+		self.switch_server(index, "server settings changed".to_string());
+		let addr = self.server().addr.clone();
+		match self.try_connect() {
+			Ok(_) => Ok(format!("connected to {}", addr)),
 			Err(e) => {
-				error!(LOGGER, "Communication error with stratum server: {}", e);
-				return Err(Error::ConnectionError("broken pipe".to_string()));
+				self.stream = None;
+				Err(format!("can't connect to {}, retrying: {:?}", addr, e))
 			}
 		}
 	}
 
+	/// Checks in the background whether the primary server accepts TCP
+	/// connections again, that can take a while and lines keep coming in
+	/// meanwhile. The outcome arrives as `PrimaryProbed`.
+	fn probe_primary(&mut self) {
+		let server = self.servers[0].clone();
+		let tx = self.tx.clone();
+		let res = thread::Builder::new()
+			.name("primary_probe".to_string())
+			.spawn(move || {
+				let reachable = server_reachable(&server);
+				let _ = tx.send(types::ClientMessage::PrimaryProbed(server.addr, reachable));
+			});
+		match res {
+			Ok(_) => self.probing_primary = true,
+			Err(e) => warn!(LOGGER, "Can't check the primary server: {:?}", e),
+		}
+	}
+
 	fn send_message(&mut self, message: &str) -> Result<(), Error> {
 		if let None = self.stream {
 			return Err(Error::ConnectionError(String::from("No server connection")));
 		}
 		debug!(LOGGER, "sending request: {}", message);
-		let _ = self.stream.as_mut().unwrap().write(message.as_bytes());
-		let _ = self.stream.as_mut().unwrap().write("\n".as_bytes());
-		let _ = self.stream.as_mut().unwrap().flush();
-		Ok(())
+		self.record(Event::Out, message);
+		let stream = self.stream.as_mut().unwrap();
+		stream
+			.write_all(format!("{}\n", message).as_bytes())
+			.and_then(|_| stream.flush())
+			.map_err(|e| Error::ConnectionError(format!("Can't send message: {}", e)))
 	}
 
 	fn parse_algorithm(&self) -> String {
-		match self.algorithm {
-			Algorithm::Cuckoo => "cuckoo".to_string(),
-			Algorithm::RandomX => "randomx".to_string(),
-			Algorithm::ProgPow => "progpow".to_string(),
-		}
+		job_algorithm(self.algorithm)
 	}
 
 	fn get_parse_algorithm(&self, algo: String) -> Result<Algorithm, Error> {
@@ -290,33 +856,99 @@ impl Controller {
 		)
 	}
 
-	fn send_message_get_job_template(&mut self) -> Result<(), Error> {
+	/// Sends a request under a fresh id and remembers it until the
+	/// answer arrives
+	fn send_request(
+		&mut self,
+		method: &str,
+		params: Option<serde_json::Value>,
+		share: Option<(u64, Solution)>,
+	) -> Result<(), Error> {
+		// 0 is the id of the mining.* subscribe request, skipped after a
+		// wrap so no answer is taken for the subscribe result
+		self.last_request_id = self.last_request_id.wrapping_add(1).max(1);
 		let req = types::RpcRequest {
 			id: self.last_request_id.to_string(),
 			jsonrpc: "2.0".to_string(),
-			method: "getjobtemplate".to_string(),
-			params: Some(serde_json::to_value(types::JobParams {
-				algorithm: self.parse_algorithm(),
-			})?),
+			method: method.to_string(),
+			params: params,
+		};
+		let req_str = match self.protocol.encode(&req)? {
+			Some(s) => s,
+			// nothing to wait for if the dialect has no such request
+			None => return Ok(()),
 		};
-		let req_str = serde_json::to_string(&req)?;
+		// a request that never went out isn't pending, its share is queued
+		// by the caller
+		self.send_message(&req_str)?;
+		self.pending.insert(
+			req.id,
+			PendingRequest {
+				method: req.method,
+				share: share,
+				sent_at: Instant::now(),
+			},
+		);
+		Ok(())
+	}
+
+	/// Drops requests the server didn't answer in time, unacknowledged
+	/// shares go back into the queue to be resubmitted with the next job
+	fn expire_pending_requests(&mut self) {
+		let timeout = std::time::Duration::from_secs(self.request_timeout);
+		let expired: Vec<String> = self
+			.pending
+			.iter()
+			.filter(|(_, p)| p.sent_at.elapsed() > timeout)
+			.map(|(id, _)| id.clone())
+			.collect();
+		for id in expired {
+			let p = self.pending.remove(&id).unwrap();
+			self.stats.write().unwrap().client_stats.num_timed_out += 1;
+			match p.share {
+				Some((height, solution)) => {
+					warn!(
+						LOGGER,
+						"No answer to share submit for height {}, job {}, nonce {} after {} seconds",
+						height,
+						solution.get_id(),
+						solution.get_nonce(),
+						self.request_timeout
+					);
+					self.queue_share(height, solution);
+				}
+				None => warn!(
+					LOGGER,
+					"No answer to {} request {} after {} seconds",
+					p.method,
+					id,
+					self.request_timeout
+				),
+			}
+		}
+	}
+
+	fn send_message_get_job_template(&mut self) -> Result<(), Error> {
+		let params = serde_json::to_value(types::JobParams {
+			algorithm: self.parse_algorithm(),
+		})?;
 		{
 			let mut stats = self.stats.write()?;
 			stats.client_stats.last_message_sent = format!("Last Message Sent: Get New Job");
 		}
-		self.send_message(&req_str)
+		self.send_request("getjobtemplate", Some(params), None)
 	}
 
 	fn send_login(&mut self) -> Result<(), Error> {
 		// only send the login request if a login string is configured
-		let login_str = match self.server_login.clone() {
+		let login_str = match self.server().login.clone() {
 			None => "".to_string(),
 			Some(server_login) => server_login.clone(),
 		};
 		if login_str == "" {
 			return Ok(());
 		}
-		let password_str = match self.server_password.clone() {
+		let password_str = match self.server().password.clone() {
 			None => "".to_string(),
 			Some(server_password) => server_password.clone(),
 		};
@@ -325,29 +957,33 @@ impl Controller {
 			pass: password_str,
 			agent: format!("epic-miner/v{}", env!("CARGO_PKG_VERSION")),
 		};
-		let req = types::RpcRequest {
-			id: self.last_request_id.to_string(),
-			jsonrpc: "2.0".to_string(),
-			method: "login".to_string(),
-			params: Some(serde_json::to_value(params)?),
-		};
-		let req_str = serde_json::to_string(&req)?;
+		let params = serde_json::to_value(params)?;
 		{
 			let mut stats = self.stats.write()?;
 			stats.client_stats.last_message_sent = format!("Last Message Sent: Login");
 		}
-		self.send_message(&req_str)
+		self.send_request("login", Some(params), None)
 	}
 
 	fn send_message_get_status(&mut self) -> Result<(), Error> {
-		let req = types::RpcRequest {
-			id: self.last_request_id.to_string(),
-			jsonrpc: "2.0".to_string(),
-			method: "status".to_string(),
-			params: None,
-		};
-		let req_str = serde_json::to_string(&req)?;
-		self.send_message(&req_str)
+		self.send_request("status", None, None)
+	}
+
+	fn send_keepalive(&mut self) -> Result<(), Error> {
+		self.send_request("keepalive", None, None)
+	}
+
+	/// Delay before the next reconnect attempt, doubling with every failed
+	/// attempt up to `retry_max`. The actual delay is picked at random from
+	/// the upper half so miners of a restarted pool don't all come back at
+	/// the same moment.
+	fn next_retry_delay(&mut self) -> std::time::Duration {
+		let max_ms = self.retry_max.max(self.retry_min) * 1000;
+		let exp = self.retry_attempts.min(16);
+		let delay_ms = (self.retry_min * 1000).saturating_mul(1 << exp).min(max_ms);
+		self.retry_attempts += 1;
+		let jittered = rand::thread_rng().gen_range(delay_ms / 2, delay_ms + 1);
+		std::time::Duration::from_millis(jittered)
 	}
 
 	fn send_message_submit(&mut self, height: u64, solution: Solution) -> Result<(), Error> {
@@ -357,14 +993,7 @@ impl Controller {
 			nonce: solution.get_nonce(),
 			pow: solution.get_algorithm_params(),
 		};
-		let params = serde_json::to_string(&params_in)?;
-		let req = types::RpcRequest {
-			id: self.last_request_id.to_string(),
-			jsonrpc: "2.0".to_string(),
-			method: "submit".to_string(),
-			params: Some(serde_json::from_str(&params)?),
-		};
-		let req_str = serde_json::to_string(&req)?;
+		let params = serde_json::to_value(&params_in)?;
 		{
 			let mut stats = self.stats.write()?;
 			stats.client_stats.last_message_sent = format!(
@@ -372,12 +1001,78 @@ impl Controller {
 				params_in.height, params_in.nonce
 			);
 		}
-		self.send_message(&req_str)
+		self.send_request("submit", Some(params), Some((height, solution)))
+	}
+
+	/// Keeps a share that couldn't be submitted until the next job from
+	/// the server, the oldest share goes if the queue is full
+	fn queue_share(&mut self, height: u64, solution: Solution) {
+		if self.share_queue_size == 0 {
+			warn!(
+				LOGGER,
+				"Dropping share for height {}, nonce {}: no server connection",
+				height,
+				solution.get_nonce()
+			);
+			return;
+		}
+		if self.share_queue.len() >= self.share_queue_size {
+			if let Some((h, s)) = self.share_queue.pop_front() {
+				warn!(
+					LOGGER,
+					"Share queue full, dropping share for height {}, nonce {}",
+					h,
+					s.get_nonce()
+				);
+				self.stats.write().unwrap().client_stats.num_dropped_shares += 1;
+			}
+		}
+		debug!(
+			LOGGER,
+			"Queueing share for height {}, nonce {}",
+			height,
+			solution.get_nonce()
+		);
+		self.share_queue.push_back((height, solution));
+		self.stats.write().unwrap().client_stats.queued_shares = self.share_queue.len();
+	}
+
+	/// Resubmits queued shares that are still for the current height,
+	/// stale ones are dropped
+	fn flush_share_queue(&mut self, current_height: u64) -> Result<(), Error> {
+		while let Some((height, solution)) = self.share_queue.pop_front() {
+			if height == current_height {
+				info!(
+					LOGGER,
+					"Resubmitting queued share for height {}, nonce {}",
+					height,
+					solution.get_nonce()
+				);
+				if let Err(e) = self.send_message_submit(height, solution.clone()) {
+					self.share_queue.push_front((height, solution));
+					self.stats.write()?.client_stats.queued_shares = self.share_queue.len();
+					return Err(e);
+				}
+			} else {
+				warn!(
+					LOGGER,
+					"Dropping stale queued share for height {}, nonce {} (current height {})",
+					height,
+					solution.get_nonce(),
+					current_height
+				);
+				self.stats.write()?.client_stats.num_dropped_shares += 1;
+			}
+		}
+		self.stats.write()?.client_stats.queued_shares = 0;
+		Ok(())
 	}
 
 	fn send_miner_job(&mut self, job: types::JobTemplate) -> Result<(), Error> {
-		let miner_message = types::MinerMessage::ReceivedSeed(job.epochs);
-		self.miner_tx.send(miner_message)?;
+		self.last_job_time = time::get_time().sec;
+		// the server is doing its job, start over with short retry delays
+		self.retry_attempts = 0;
+		self.send_to_miners(&|| types::MinerMessage::ReceivedSeed(job.epochs.clone()))?;
 
 		let difficulty = {
 			let mut diff = 1;
@@ -401,26 +1096,61 @@ impl Controller {
 		let current_network_diff = self.parse_difficulty(&job.block_difficulty);
 		let miner_message =
 			types::MinerMessage::ReceivedJob(job.height, job.job_id, difficulty, job.pre_pow);
-		let mut stats = self.stats.write()?;
-		stats.client_stats.last_message_received = format!(
-			"Last Message Received: Start Job for Height: {}, Share Difficulty: {}",
-			job.height, job_diff
-		);
-		stats.client_stats.algorithm_needed = algo_needed;
-		stats.client_stats.current_network_difficulty = current_network_diff;
-		self.miner_tx.send(miner_message).map_err(|e| e.into())
+		{
+			let mut stats = self.stats.write()?;
+			stats.client_stats.last_message_received = format!(
+				"Last Message Received: Start Job for Height: {}, Share Difficulty: {}",
+				job.height, job_diff
+			);
+			if let Some(received) = self.line_received {
+				stats
+					.client_stats
+					.add_job_latency(duration_ms(received.elapsed()));
+			}
+			stats.client_stats.algorithm_needed = algo_needed;
+			stats.client_stats.current_network_difficulty = current_network_diff;
+		}
+		self.miner_tx.send(miner_message)?;
+		if self.mining_since.is_none() {
+			self.mining_since = Some(Instant::now());
+		}
+		// a job means we're logged in, so shares kept while disconnected
+		// can go out now
+		self.flush_share_queue(job.height)
 	}
 
 	fn send_miner_seed(&mut self, job: types::EpochTemplate) -> Result<(), Error> {
-		let miner_message = types::MinerMessage::ReceivedSeed(job.epochs);
-		self.miner_tx.send(miner_message).map_err(|e| e.into())
+		self.send_to_miners(&|| types::MinerMessage::ReceivedSeed(job.epochs.clone()))
 	}
 
 	fn send_miner_stop(&mut self) -> Result<(), Error> {
+		self.update_algorithm_time();
+		self.mining_since = None;
 		let miner_message = types::MinerMessage::StopJob;
 		self.miner_tx.send(miner_message).map_err(|e| e.into())
 	}
 
+	/// Hands a job to the miner of its algorithm, switching miners when
+	/// following the pool's algorithm. Without a miner for it mining stops.
+	fn handle_job(&mut self, job: types::JobTemplate) -> Result<(), Error> {
+		let needed = self.get_parse_algorithm(job.algorithm.clone()).ok();
+		if needed == Some(self.algorithm) {
+			return self.send_miner_job(job);
+		}
+		if let Some(algorithm) = needed {
+			if self.switch_algorithm(algorithm)? {
+				return self.send_miner_job(job);
+			}
+		}
+		info!(
+			LOGGER,
+			"my algo: {}, algo from job {}",
+			self.parse_algorithm(),
+			job.algorithm
+		);
+		self.send_miner_stop()
+	}
+
 	pub fn handle_request(&mut self, req: types::RpcRequest) -> Result<(), Error> {
 		debug!(LOGGER, "Received request type: {}", req.method);
 		match req.method.as_str() {
@@ -430,24 +1160,7 @@ impl Controller {
 					let job = serde_json::from_value::<types::JobTemplate>(params)?;
 					info!(LOGGER, "Got a new job: {:?}", job);
 
-					let algo_needed = match job.algorithm.as_str() {
-						"cuckoo" => "cuckoo".to_string(),
-						"randomx" => "randomx".to_string(),
-						"progpow" => "progpow".to_string(),
-						_ => "".to_string(),
-					};
-
-					if self.parse_algorithm() == algo_needed {
-						self.send_miner_job(job)
-					} else {
-						info!(
-							LOGGER,
-							"my algo: {}, algo from job {}",
-							self.parse_algorithm(),
-							algo_needed
-						);
-						self.send_miner_stop()
-					}
+					self.handle_job(job)
 				}
 			},
 			_ => Err(Error::RequestError("Unknonw method".to_owned())),
@@ -456,7 +1169,19 @@ impl Controller {
 
 	pub fn handle_response(&mut self, res: types::RpcResponse) -> Result<(), Error> {
 		debug!(LOGGER, "Received response with id: {}", res.id);
-		match res.method.as_str() {
+		// the id tells what the response answers, the echoed method is
+		// only trusted for messages we didn't ask for
+		let pending = self.pending.remove(&res.id);
+		let method = match pending {
+			Some(ref p) => {
+				let mut stats = self.stats.write()?;
+				stats.client_stats.add_rtt(duration_ms(p.sent_at.elapsed()));
+				p.method.clone()
+			}
+			None => res.method.clone(),
+		};
+		let share = pending.and_then(|p| p.share);
+		match method.as_str() {
 			// "status" response can be used to further populate stats object
 			"status" => {
 				if let Some(result) = res.result {
@@ -503,24 +1228,7 @@ impl Controller {
 						"Got a job at height {} and share difficulty {:?}", job.height, job_diff
 					);
 
-					let algo_needed = match job.algorithm.as_str() {
-						"cuckoo" => "cuckoo".to_string(),
-						"randomx" => "randomx".to_string(),
-						"progpow" => "progpow".to_string(),
-						_ => "".to_string(),
-					};
-
-					if self.parse_algorithm() == algo_needed {
-						self.send_miner_job(job)
-					} else {
-						info!(
-							LOGGER,
-							"my algo: {}, algo from job {}",
-							self.parse_algorithm(),
-							algo_needed
-						);
-						self.send_miner_stop()
-					}
+					self.handle_job(job)
 				} else {
 					let err = res.error.unwrap_or_else(|| invlalid_error_response());
 					let mut stats = self.stats.write()?;
@@ -534,8 +1242,18 @@ impl Controller {
 			}
 			// "submit" response
 			"submit" => {
-				if let Some(result) = res.result {
-					info!(LOGGER, "Share Accepted!!");
+				let submitted = share.as_ref().map(|&(height, ref s)| (height, s.get_nonce()));
+				let share = match share {
+					Some((height, solution)) => format!(
+						" (height {}, job {}, nonce {})",
+						height,
+						solution.get_id(),
+						solution.get_nonce()
+					),
+					None => "".to_string(),
+				};
+				let refused = if let Some(result) = res.result {
+					info!(LOGGER, "Share Accepted!!{}", share);
 					let mut stats = self.stats.write()?;
 					stats.client_stats.last_message_received =
 						format!("Last Message Received: Share Accepted!!");
@@ -547,6 +1265,7 @@ impl Controller {
 							format!("Last Message Received: Block Found!!");
 						stats.mining_stats.solution_stats.num_blocks_found += 1;
 					}
+					None
 				} else {
 					let err = res.error.unwrap_or_else(|| invlalid_error_response());
 					let mut stats = self.stats.write()?;
@@ -559,7 +1278,13 @@ impl Controller {
 					} else {
 						stats.mining_stats.solution_stats.num_rejected += 1;
 					}
-					error!(LOGGER, "Failed to submit a solution: {:?}", err);
+					error!(LOGGER, "Failed to submit a solution{}: {:?}", share, err);
+					Some(err.message)
+				};
+				// lets a proxy answer the miner that found the share
+				if let Some((height, nonce)) = submitted {
+					self.miner_tx
+						.send(types::MinerMessage::SubmitResult(height, nonce, refused))?;
 				}
 				Ok(())
 			}
@@ -623,45 +1348,111 @@ impl Controller {
 		}
 	}
 
+	/// Parses a line received from the server and dispatches it
+	fn handle_line(&mut self, m: String) {
+		{
+			let mut stats = self.stats.write().unwrap();
+			stats.client_stats.my_algorithm = algorithm_name(self.algorithm);
+			stats.client_stats.connected = true;
+		}
+		// figure out what kind of message,
+		// and dispatch appropriately
+		debug!(LOGGER, "Received message: {}", m);
+		let messages = match self.protocol.decode(&m) {
+			Ok(messages) => messages,
+			Err(e) => {
+				error!(LOGGER, "Error parsing message {} : {:?}", m, e);
+				return;
+			}
+		};
+		for message in messages {
+			let result = match message {
+				protocol::Inbound::Request(request) => self.handle_request(request),
+				protocol::Inbound::Response(response) => self.handle_response(response),
+				protocol::Inbound::Extranonce(extranonce) => {
+					info!(
+						LOGGER,
+						"Pool assigned extranonce {}",
+						to_hex(extranonce.clone())
+					);
+					self.send_to_miners(&|| types::MinerMessage::SetExtranonce(extranonce.clone()))
+				}
+			};
+			if let Err(err) = result {
+				error!(LOGGER, "Error handling message {} : :{:?}", m, err)
+			}
+		}
+	}
+
+	/// Whether a message from the reader of connection `id` is still
+	/// relevant, lines of dropped connections are ignored
+	fn is_current_stream(&self, id: u64) -> bool {
+		match self.stream {
+			Some(ref s) => s.id == id,
+			None => false,
+		}
+	}
+
+	/// The main wait of the client, returns as soon as a line from the
+	/// server or a message from the mining controller arrives
 	pub fn run(mut self) {
-		let server_read_interval = 1;
-		let server_retry_interval = 5;
-		let mut next_server_read = time::get_time().sec + server_read_interval;
 		let status_interval = 30;
+		let mut next_server_retry = Instant::now();
+		let mut next_keepalive = time::get_time().sec + self.keepalive_interval;
 		let mut next_status_request = time::get_time().sec + status_interval;
-		let mut next_server_retry = time::get_time().sec;
-		// Request the first job template
-		thread::sleep(std::time::Duration::from_secs(1));
+		let mut next_primary_check = time::get_time().sec + self.primary_retry_interval;
 		let mut was_disconnected = true;
 		loop {
+			self.update_algorithm_time();
 			// Check our connection status, and try to correct if possible
 			if let None = self.stream {
 				if !was_disconnected {
 					let _ = self.send_miner_stop();
 				}
 				was_disconnected = true;
-				if time::get_time().sec > next_server_retry {
-					if let Err(_) = self.try_connect() {
-						let status = format!("Connection Status: Can't establish server connection to {}. Will retry every {} seconds",
-							self.server_url,
-							server_retry_interval);
-						warn!(LOGGER, "{}", status);
-						let mut stats = self.stats.write().unwrap();
-						stats.client_stats.connection_status = status;
-						stats.client_stats.connected = false;
+				if Instant::now() >= next_server_retry {
+					let result = self.try_connect();
+					let retry_delay = self.next_retry_delay();
+					next_server_retry = Instant::now() + retry_delay;
+					if let Err(e) = result {
+						let status = format!(
+							"Connection Status: Can't establish server connection to {}. Retrying in {:.1} seconds",
+							self.server().addr,
+							duration_ms(retry_delay) / 1000.0
+						);
+						warn!(LOGGER, "{} ({:?})", status, e);
+						{
+							let mut stats = self.stats.write().unwrap();
+							stats.client_stats.connection_status = status;
+							stats.client_stats.connected = false;
+						}
 						self.stream = None;
+						self.failed_connects += 1;
+						if self.servers.len() > 1 && self.failed_connects >= self.failover_retries {
+							let reason = format!(
+								"{} failed reconnects to {}",
+								self.failed_connects,
+								self.server().addr
+							);
+							self.switch_to_next_server(reason);
+							next_primary_check = time::get_time().sec + self.primary_retry_interval;
+							continue;
+						}
 					} else {
 						let status = format!(
 							"Connection Status: Connected to Epic server at {}.",
-							self.server_url
+							self.server().addr
 						);
 						warn!(LOGGER, "{}", status);
+						self.failed_connects = 0;
+						self.last_job_time = time::get_time().sec;
+						self.last_message_time = time::get_time().sec;
+						next_keepalive = time::get_time().sec + self.keepalive_interval;
 						let mut stats = self.stats.write().unwrap();
 						stats.client_stats.connection_status = status;
-					}
-					next_server_retry = time::get_time().sec + server_retry_interval;
-					if let None = self.stream {
-						thread::sleep(std::time::Duration::from_secs(1));
+						stats.client_stats.server_url = self.server().addr.clone();
+						// Log in and ask for work straight away rather than
+						// after the next message wait
 						continue;
 					}
 				}
@@ -672,110 +1463,364 @@ impl Controller {
 					let _ = self.send_message_get_job_template();
 					was_disconnected = false;
 				}
-				// read messages from server
-				if time::get_time().sec > next_server_read {
-					match self.read_message() {
-						Ok(message) => {
-							match message {
-								Some(m) => {
-									{
-										let mut stats = self.stats.write().unwrap();
-										stats.client_stats.my_algorithm = match self.algorithm {
-											Algorithm::Cuckoo => "Cuckatoo".to_string(),
-											Algorithm::RandomX => "RandomX".to_string(),
-											Algorithm::ProgPow => "ProgPow".to_string(),
-											_ => "".to_string(),
-										};
-										stats.client_stats.connected = true;
-									}
-									// figure out what kind of message,
-									// and dispatch appropriately
-									debug!(LOGGER, "Received message: {}", m);
-									// Deserialize to see what type of object it is
-									if let Ok(v) = serde_json::from_str::<serde_json::Value>(&m) {
-										// Is this a response or request?
-										if v["method"] == String::from("job") {
-											// this is a request
-											match serde_json::from_str::<types::RpcRequest>(&m) {
-												Err(e) => error!(
-													LOGGER,
-													"Error parsing request {} : {:?}", m, e
-												),
-												Ok(request) => {
-													if let Err(err) = self.handle_request(request) {
-														error!(
-															LOGGER,
-															"Error handling request {} : :{:?}",
-															m,
-															err
-														)
-													}
-												}
-											}
-											continue;
-										} else {
-											// this is a response
-											match serde_json::from_str::<types::RpcResponse>(&m) {
-												Err(e) => error!(
-													LOGGER,
-													"Error parsing response {} : {:?}", m, e
-												),
-												Ok(response) => {
-													if let Err(err) = self.handle_response(response)
-													{
-														error!(
-															LOGGER,
-															"Error handling response {} : :{:?}",
-															m,
-															err
-														)
-													}
-												}
-											}
-											continue;
-										}
-									} else {
-										error!(LOGGER, "Error parsing message: {}", m)
-									}
-								}
-								None => {} // No messages from the server at this time
-							}
-						}
-						Err(e) => {
-							error!(LOGGER, "Error reading message: {:?}", e);
-							self.stream = None;
-							continue;
-						}
-					}
-					next_server_read = time::get_time().sec + server_read_interval;
-				}
 
 				// Request a status message from the server
 				if time::get_time().sec > next_status_request {
 					let _ = self.send_message_get_status();
 					next_status_request = time::get_time().sec + status_interval;
 				}
+
+				if self.keepalive_interval > 0 && time::get_time().sec > next_keepalive {
+					let _ = self.send_keepalive();
+					next_keepalive = time::get_time().sec + self.keepalive_interval;
+				}
+
+				self.expire_pending_requests();
+
+				// Half-open connections never return an error, only silence
+				if self.idle_timeout > 0
+					&& time::get_time().sec - self.last_message_time > self.idle_timeout
+				{
+					warn!(
+						LOGGER,
+						"Nothing received from {} for {} seconds, reconnecting",
+						self.server().addr,
+						self.idle_timeout
+					);
+					self.stream = None;
+					continue;
+				}
+
+				// A server that stopped sending jobs is as good as a dead one
+				if self.stall_timeout > 0
+					&& time::get_time().sec - self.last_job_time > self.stall_timeout
+				{
+					let reason = format!(
+						"no job received from {} for {} seconds",
+						self.server().addr,
+						self.stall_timeout
+					);
+					if self.servers.len() > 1 {
+						self.switch_to_next_server(reason);
+						next_primary_check = time::get_time().sec + self.primary_retry_interval;
+					} else {
+						warn!(LOGGER, "Reconnecting, {}", reason);
+						self.stream = None;
+						self.last_job_time = time::get_time().sec;
+					}
+					continue;
+				}
+			}
+
+			// Go back to the primary server as soon as it's reachable again
+			if self.active_server != 0
+				&& !self.probing_primary
+				&& time::get_time().sec > next_primary_check
+			{
+				self.probe_primary();
+				next_primary_check = time::get_time().sec + self.primary_retry_interval;
 			}
 
-			// Talk to the cuckoo miner plugin
-			while let Some(message) = self.rx.try_iter().next() {
-				debug!(LOGGER, "Client received message: {:?}", message);
-				let result = match message {
-					types::ClientMessage::FoundSolution(height, solution) => {
-						self.send_message_submit(height, solution)
+			// Wait for the server or the mining controller, the timeout only
+			// keeps the timers above ticking
+			let message = match self.rx.recv_timeout(std::time::Duration::from_millis(500)) {
+				Ok(message) => message,
+				Err(mpsc::RecvTimeoutError::Timeout) => continue,
+				Err(mpsc::RecvTimeoutError::Disconnected) => return,
+			};
+			let result = match message {
+				types::ClientMessage::ReceivedLine(id, line, received) => {
+					if self.is_current_stream(id) {
+						self.record(Event::In, &line);
+						self.last_message_time = time::get_time().sec;
+						self.line_received = Some(received);
+						self.handle_line(line);
+						self.line_received = None;
 					}
-					types::ClientMessage::Shutdown => {
-						//TODO: Inform server?
-						debug!(LOGGER, "Shutting down client controller");
-						return;
+					Ok(())
+				}
+				types::ClientMessage::ConnectionLost(id, reason) => {
+					if self.is_current_stream(id) {
+						self.record(Event::Disconnect, &reason);
+						error!(LOGGER, "Error reading message: {}", reason);
+						self.stream = None;
 					}
-				};
-				if let Err(e) = result {
+					Ok(())
+				}
+				types::ClientMessage::FoundSolution(height, solution) => {
+					debug!(LOGGER, "Client received solution for height {}", height);
+					if self.stream.is_none() || was_disconnected {
+						self.queue_share(height, solution);
+						Ok(())
+					} else {
+						match self.send_message_submit(height, solution.clone()) {
+							Err(Error::ConnectionError(e)) => {
+								self.queue_share(height, solution);
+								Err(Error::ConnectionError(e))
+							}
+							r => r,
+						}
+					}
+				}
+				types::ClientMessage::ChangeServer(change, reply) => {
+					let result = self.change_server(change);
+					if result.is_ok() {
+						// log in on the new connection
+						was_disconnected = true;
+					}
+					let _ = reply.send(result);
+					Ok(())
+				}
+				types::ClientMessage::Reconfigure(config) => {
+					self.reconfigure(&config);
+					Ok(())
+				}
+				types::ClientMessage::PrimaryProbed(addr, reachable) => {
+					self.probing_primary = false;
+					// the server list may have changed during the probe
+					if reachable && self.active_server != 0 && self.servers[0].addr == addr {
+						self.switch_server(0, "primary server is reachable again".to_string());
+					}
+					Ok(())
+				}
+				types::ClientMessage::Shutdown => {
+					//TODO: Inform server?
+					debug!(LOGGER, "Shutting down client controller");
+					return;
+				}
+			};
+			match result {
+				Err(Error::ConnectionError(e)) => {
 					error!(LOGGER, "Mining Controller Error {:?}", e);
 					self.stream = None;
 				}
+				Err(e) => error!(LOGGER, "Mining Controller Error {:?}", e),
+				Ok(_) => {}
 			}
-			thread::sleep(std::time::Duration::from_millis(10));
 		} // loop
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn job(algorithm: &str) -> types::JobTemplate {
+		types::JobTemplate {
+			height: 10,
+			job_id: 2,
+			difficulty: vec![(algorithm.to_owned(), 5)],
+			block_difficulty: vec![],
+			pre_pow: "00".to_owned(),
+			epochs: vec![],
+			algorithm: algorithm.to_owned(),
+		}
+	}
+
+	#[test]
+	fn request_ids_skip_zero() {
+		let stats = Arc::new(RwLock::new(stats::Stats::default()));
+		let (miner_tx, _miner_rx) = mpsc::channel();
+		let mut cc =
+			Controller::new(Algorithm::RandomX, &MinerConfig::default(), miner_tx, stats).unwrap();
+		cc.last_request_id = u32::max_value();
+		// not connected, the id is taken all the same
+		assert!(cc.send_request("status", None, None).is_err());
+		assert_eq!(cc.last_request_id, 1);
+	}
+
+	#[test]
+	fn follow_algorithm() {
+		let stats = Arc::new(RwLock::new(stats::Stats::default()));
+		let (rx_tx, rx_rx) = mpsc::channel();
+		let (cuckoo_tx, cuckoo_rx) = mpsc::channel();
+		let mut cc = Controller::new(
+			Algorithm::RandomX,
+			&MinerConfig::default(),
+			rx_tx.clone(),
+			stats.clone(),
+		)
+		.unwrap();
+		// labeled before the server sends anything
+		assert_eq!(stats.read().unwrap().client_stats.my_algorithm, "RandomX");
+		cc.follow(vec![
+			(Algorithm::RandomX, rx_tx),
+			(Algorithm::Cuckoo, cuckoo_tx),
+		]);
+		match cuckoo_rx.try_recv() {
+			Ok(types::MinerMessage::StopJob) => {}
+			m => panic!("expected the idle miner to stop, got {:?}", m),
+		}
+
+		cc.handle_job(job("cuckoo")).unwrap();
+		// seeds go to both to keep the idle miner's datasets warm
+		let rx_messages: Vec<_> = rx_rx.try_iter().collect();
+		assert!(rx_messages.iter().any(|m| match m {
+			types::MinerMessage::StopJob => true,
+			_ => false,
+		}));
+		assert!(rx_messages.iter().all(|m| match m {
+			types::MinerMessage::ReceivedJob(..) => false,
+			_ => true,
+		}));
+		match cuckoo_rx.try_iter().last() {
+			Some(types::MinerMessage::ReceivedJob(10, 2, 5, _)) => {}
+			m => panic!("expected a job for the cuckoo miner, got {:?}", m),
+		}
+		assert_eq!(stats.read().unwrap().client_stats.my_algorithm, "Cuckatoo");
+
+		// without a miner for it the job is ignored
+		cc.handle_job(job("progpow")).unwrap();
+		match cuckoo_rx.try_iter().last() {
+			Some(types::MinerMessage::StopJob) => {}
+			m => panic!("expected the cuckoo miner to stop, got {:?}", m),
+		}
+		assert!(stats
+			.read()
+			.unwrap()
+			.client_stats
+			.algorithm_seconds
+			.contains_key("Cuckatoo"));
+	}
+
+	/// Self-signed certificate and key for "localhost"
+	fn self_signed() -> Identity {
+		use openssl::asn1::Asn1Time;
+		use openssl::hash::MessageDigest;
+		use openssl::rsa::Rsa;
+		use openssl::x509::X509Name;
+
+		let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+		let mut name = X509Name::builder().unwrap();
+		name.append_entry_by_text("CN", "localhost").unwrap();
+		let name = name.build();
+		let mut cert = X509::builder().unwrap();
+		cert.set_subject_name(&name).unwrap();
+		cert.set_issuer_name(&name).unwrap();
+		cert.set_pubkey(&key).unwrap();
+		cert.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+		cert.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+		cert.sign(&key, MessageDigest::sha256()).unwrap();
+		let pkcs12 = Pkcs12::builder()
+			.build("", "localhost", &key, &cert.build())
+			.unwrap();
+		Identity::from_pkcs12(&pkcs12.to_der().unwrap(), "").unwrap()
+	}
+
+	#[test]
+	fn tls_through_proxy() {
+		use core::config::ProxyKind;
+		use native_tls::TlsAcceptor;
+		use std::io::{BufRead, BufReader};
+		use std::net::TcpListener;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let server = listener.local_addr().unwrap().to_string();
+		let acceptor = TlsAcceptor::new(self_signed()).unwrap();
+		thread::spawn(move || {
+			let (s, _) = listener.accept().unwrap();
+			let mut s = acceptor.accept(s).unwrap();
+			let mut line = String::new();
+			BufReader::new(&mut s).read_line(&mut line).unwrap();
+			write!(s, "tls {}", line).unwrap();
+		});
+		let (proxy_addr, target) = tunnel::test::socks5_stand_in(None, 0, server.clone());
+		let proxy = Some(ProxyConfig {
+			kind: ProxyKind::Socks5,
+			addr: proxy_addr,
+			username: None,
+			password: None,
+			remote_dns: true,
+		});
+		let tls = TlsConfig {
+			server_name: Some("localhost".to_owned()),
+			accept_invalid_certs: true,
+			..TlsConfig::default()
+		};
+		let mut conn = connect(&server, Some(true), &tls, &proxy).unwrap();
+		assert_eq!(target.recv().unwrap(), server);
+		conn.write_all(b"ping\n").unwrap();
+		let mut line = String::new();
+		BufReader::new(conn).read_line(&mut line).unwrap();
+		assert_eq!(line, "tls ping\n");
+	}
+
+	#[test]
+	fn websocket_over_tls() {
+		use native_tls::TlsAcceptor;
+		use std::io::{BufRead, BufReader};
+		use std::net::TcpListener;
+		use websocket::test::{accept, frame, read_frame};
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let acceptor = TlsAcceptor::new(self_signed()).unwrap();
+		thread::spawn(move || {
+			let (s, _) = listener.accept().unwrap();
+			let mut s = acceptor.accept(s).unwrap();
+			let header = accept(&mut s);
+			assert!(header.starts_with("GET /stratum HTTP/1.1\r\n"), "{}", header);
+			let (_, line) = read_frame(&mut s);
+			let mut reply = b"ws ".to_vec();
+			reply.extend_from_slice(&line);
+			s.write_all(&frame(1, true, &reply)).unwrap();
+		});
+		// the TLS settings apply as with plain stratum
+		let tls = TlsConfig {
+			accept_invalid_certs: true,
+			..TlsConfig::default()
+		};
+		let addr = format!("wss://localhost:{}/stratum", port);
+		let mut conn = connect(&addr, None, &tls, &None).unwrap();
+		conn.write_all(b"ping\n").unwrap();
+		conn.flush().unwrap();
+		let mut line = String::new();
+		BufReader::new(conn).read_line(&mut line).unwrap();
+		assert_eq!(line, "ws ping\n");
+	}
+
+	#[test]
+	fn stream_over_tls() {
+		use native_tls::TlsAcceptor;
+		use std::io::{BufRead, BufReader};
+		use std::net::TcpListener;
+		use std::time::Duration;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let server = listener.local_addr().unwrap().to_string();
+		let acceptor = TlsAcceptor::new(self_signed()).unwrap();
+		thread::spawn(move || {
+			let (s, _) = listener.accept().unwrap();
+			let mut s = BufReader::new(acceptor.accept(s).unwrap());
+			let mut line = String::new();
+			s.read_line(&mut line).unwrap();
+			// two lines in one TLS record, then one more later
+			s.get_mut().write_all(b"a\nb\n").unwrap();
+			thread::sleep(Duration::from_millis(100));
+			s.get_mut().write_all(b"c\n").unwrap();
+			line.clear();
+			s.read_line(&mut line).unwrap();
+			write!(s.get_mut(), "got {}", line).unwrap();
+		});
+		let tls = TlsConfig {
+			accept_invalid_certs: true,
+			..TlsConfig::default()
+		};
+		let conn = connect(&server, Some(true), &tls, &None).unwrap();
+		let (tx, rx) = mpsc::channel();
+		let mut stream = Stream::new(1, conn, tx).unwrap();
+		let next = || match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+			types::ClientMessage::ReceivedLine(1, line, _) => line,
+			types::ClientMessage::ConnectionLost(1, reason) => format!("lost: {}", reason),
+			m => panic!("unexpected message {:?}", m),
+		};
+
+		// the reader is waiting for the server already, writes still go out
+		thread::sleep(Duration::from_millis(50));
+		stream.write_all(b"ping\n").unwrap();
+		assert_eq!((next(), next(), next()), ("a".into(), "b".into(), "c".into()));
+		stream.write_all(b"pong\n").unwrap();
+		assert_eq!(next(), "got pong");
+		assert_eq!(next(), "lost: connection closed by server");
+	}
+}
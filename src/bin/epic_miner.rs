@@ -29,33 +29,51 @@ extern crate epic_miner_core as core;
 extern crate epic_miner_plugin as plugin;
 extern crate epic_miner_util as util;
 
-extern crate bufstream;
 extern crate native_tls;
+extern crate openssl;
+extern crate rand;
 extern crate time;
+extern crate toml;
 #[macro_use]
 extern crate serde_derive;
 #[macro_use]
 extern crate clap;
+#[macro_use]
 extern crate serde_json;
 #[macro_use]
 extern crate slog;
 
 #[cfg(feature = "tui")]
 extern crate cursive;
+#[cfg(unix)]
+extern crate libc;
 
+pub mod api;
+pub mod autotune;
+pub mod benchmark;
+pub mod capture;
 pub mod client;
+pub mod metrics;
 pub mod mining;
+pub mod protocol;
+pub mod proxy;
+pub mod reload;
+pub mod solo;
 pub mod stats;
+pub mod tunnel;
 pub mod types;
+pub mod websocket;
 
 #[cfg(feature = "tui")]
 pub mod tui;
 
 use clap::App;
 use config::GlobalConfig;
+use std::fs;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, RwLock};
+use std::sync::{mpsc, Arc, RwLock};
 use std::thread;
+use std::time::Duration;
 
 use core::config::MinerConfig;
 use core::errors::MinerError;
@@ -105,12 +123,13 @@ mod with_tui {
 	use tui::ui;
 	use types;
 
+	/// `sessions` holds the algorithms and stats of each stratum session
 	pub fn start_tui(
-		s: Arc<RwLock<stats::Stats>>,
-		client_tx: mpsc::Sender<types::ClientMessage>,
-		miner_tx: mpsc::Sender<types::MinerMessage>,
+		sessions: Vec<(Vec<Algorithm>, Arc<RwLock<stats::Stats>>)>,
+		client_txs: Vec<mpsc::Sender<types::ClientMessage>>,
+		miner_txs: Vec<mpsc::Sender<types::MinerMessage>>,
+		quit: Arc<AtomicBool>,
 		stop: Arc<AtomicBool>,
-		algorithm: Algorithm,
 	) {
 		// Run the UI controller.. here for now for simplicity to access
 		// everything it might need
@@ -119,72 +138,65 @@ mod with_tui {
 		let _ = thread::Builder::new()
 			.name("ui".to_string())
 			.spawn(move || {
-				let mut controller = ui::Controller::new(algorithm).unwrap_or_else(|e| {
+				let (algorithms, stats): (Vec<_>, Vec<_>) = sessions.into_iter().unzip();
+				let mut controller = ui::Controller::new(&algorithms).unwrap_or_else(|e| {
 					panic!("Error loading UI controller: {}", e);
 				});
-				controller.run(s.clone());
+				controller.run(stats, quit);
 				// Shut down everything else on tui exit
-				let _ = client_tx.send(types::ClientMessage::Shutdown);
-				let _ = miner_tx.send(types::MinerMessage::Shutdown);
+				for tx in client_txs {
+					let _ = tx.send(types::ClientMessage::Shutdown);
+				}
+				for tx in miner_txs {
+					let _ = tx.send(types::MinerMessage::Shutdown);
+				}
 				stop.store(true, Ordering::Relaxed);
 			});
 	}
 }
 
-fn start_miner<T>(mut miner: T, algorithm: Algorithm, mining_config: &MinerConfig)
-where
-	T: Miner + 'static,
-{
-	let stats = Arc::new(RwLock::new(stats::Stats::default()));
-
-	let mut mc =
-		mining::Controller::new(mining_config.clone(), stats.clone()).unwrap_or_else(|e| {
-			panic!("Error loading mining controller: {}", e);
-		});
+/// A stratum session with its miners, one per algorithm when following
+/// the algorithm of the pool's jobs
+struct Session {
+	// shown by the TUI only
+	#[cfg_attr(not(feature = "tui"), allow(dead_code))]
+	algorithms: Vec<Algorithm>,
+	stats: Arc<RwLock<stats::Stats>>,
+	client_tx: mpsc::Sender<types::ClientMessage>,
+	miner_txs: Vec<mpsc::Sender<types::MinerMessage>>,
+	miners_stopped: Vec<Arc<AtomicBool>>,
+	client_stopped: Arc<AtomicBool>,
+}
 
-	let cc = client::Controller::new(
-		algorithm.clone(),
-		&mining_config.stratum_server_addr,
-		mining_config.stratum_server_login.clone(),
-		mining_config.stratum_server_password.clone(),
-		mining_config.stratum_server_tls_enabled.clone(),
-		mc.tx.clone(),
-		stats.clone(),
-	)
-	.unwrap_or_else(|e| {
-		panic!("Error loading stratum client controller: {:?}", e);
-	});
+impl Session {
+	fn stopped(&self) -> bool {
+		self.miners_stopped.iter().all(|s| s.load(Ordering::Relaxed))
+			&& self.client_stopped.load(Ordering::Relaxed)
+	}
 
-	let tui_stopped = Arc::new(AtomicBool::new(false));
-	let miner_stopped = Arc::new(AtomicBool::new(false));
-	let client_stopped = Arc::new(AtomicBool::new(false));
+	fn shutdown(&self) {
+		let _ = self.client_tx.send(types::ClientMessage::Shutdown);
+		for tx in &self.miner_txs {
+			let _ = tx.send(types::MinerMessage::Shutdown);
+		}
+	}
+}
 
+/// Starts the solvers of `miner` and runs the mining controller for them
+/// in the background
+fn spawn_miner<T>(mut miner: T, mut mc: mining::Controller) -> Option<Arc<AtomicBool>>
+where
+	T: Miner + 'static,
+{
 	if let Err(e) = miner.start_solvers() {
 		println!("Error starting plugins. Please check logs for further info.");
 		println!("Error details:");
 		println!("{:?}", e);
 		println!("Exiting");
-		return;
+		return None;
 	}
 
-	if mining_config.run_tui {
-		#[cfg(feature = "tui")]
-		with_tui::start_tui(
-			stats.clone(),
-			cc.tx.clone(),
-			mc.tx.clone(),
-			tui_stopped.clone(),
-			mining_config.algorithm.clone().unwrap(),
-		);
-
-		#[cfg(not(feature = "tui"))]
-		warn!(LOGGER, "Epic-miner was built with TUI support disabled!");
-	} else {
-		tui_stopped.store(true, Ordering::Relaxed);
-	}
-
-	mc.set_client_tx(cc.tx.clone());
-
+	let miner_stopped = Arc::new(AtomicBool::new(false));
 	let miner_stopped_internal = miner_stopped.clone();
 	let _ = thread::Builder::new()
 		.name("mining_controller".to_string())
@@ -198,25 +210,408 @@ where
 			}
 			miner_stopped_internal.store(true, Ordering::Relaxed);
 		});
+	Some(miner_stopped)
+}
 
-	let client_stopped_internal = client_stopped.clone();
+/// Creates the miner for `algorithm` and starts mining with it
+fn start_algorithm(
+	algorithm: Algorithm,
+	mining_config: &MinerConfig,
+	mc: mining::Controller,
+) -> Option<Arc<AtomicBool>> {
+	match algorithm {
+		Algorithm::RandomX => spawn_miner(randomx::RxMiner::new(mining_config), mc),
+		Algorithm::Cuckoo => spawn_miner(cuckoo::CuckooMiner::new(mining_config), mc),
+		#[cfg(feature = "opencl")]
+		Algorithm::ProgPow => spawn_miner(progpow::PpMiner::new(mining_config), mc),
+		#[cfg(feature = "cuda")]
+		Algorithm::ProgPow => spawn_miner(progpow::PpMiner::new(mining_config), mc),
+		#[allow(unreachable_patterns)]
+		_ => panic!("This algorithm is not supported in this build!"),
+	}
+}
+
+/// Connects to the stratum server, or the node when solo mining, and starts
+/// a miner for each of `algorithms`, the first one mines until the pool
+/// asks for another
+fn start_session(algorithms: &[Algorithm], mining_config: &MinerConfig) -> Option<Session> {
+	let stats = Arc::new(RwLock::new(stats::Stats::default()));
+
+	let mut miners = vec![];
+	for &algorithm in algorithms {
+		// every miner only sees its own algorithm
+		let mut config = mining_config.clone();
+		config.algorithm = Some(algorithm);
+		let mc = mining::Controller::new(config.clone(), stats.clone()).unwrap_or_else(|e| {
+			panic!("Error loading mining controller: {}", e);
+		});
+		miners.push((algorithm, config, mc));
+	}
+
+	// solo mining talks to the node instead of a stratum server
+	let (client_tx, run_client): (mpsc::Sender<types::ClientMessage>, Box<dyn FnOnce() + Send>) =
+		if mining_config.node_api_url.is_some() {
+			let sc = solo::Controller::new(
+				algorithms[0],
+				mining_config,
+				miners[0].2.tx.clone(),
+				stats.clone(),
+			)
+			.unwrap_or_else(|e| {
+				panic!("Error loading solo client controller: {:?}", e);
+			});
+			(sc.tx.clone(), Box::new(move || sc.run()))
+		} else {
+			let mut cc = client::Controller::new(
+				algorithms[0],
+				mining_config,
+				miners[0].2.tx.clone(),
+				stats.clone(),
+			)
+			.unwrap_or_else(|e| {
+				panic!("Error loading stratum client controller: {:?}", e);
+			});
+			if miners.len() > 1 {
+				cc.follow(miners.iter().map(|m| (m.0, m.2.tx.clone())).collect());
+			}
+			(cc.tx.clone(), Box::new(move || cc.run()))
+		};
+
+	let mut session = Session {
+		algorithms: algorithms.to_vec(),
+		stats: stats,
+		client_tx: client_tx.clone(),
+		miner_txs: miners.iter().map(|m| m.2.tx.clone()).collect(),
+		miners_stopped: vec![],
+		client_stopped: Arc::new(AtomicBool::new(false)),
+	};
+	for (algorithm, config, mut mc) in miners {
+		mc.set_client_tx(client_tx.clone());
+		match start_algorithm(algorithm, &config, mc) {
+			Some(stopped) => session.miners_stopped.push(stopped),
+			None => {
+				session.shutdown();
+				return None;
+			}
+		}
+	}
+
+	let client_stopped_internal = session.client_stopped.clone();
 	let _ = thread::Builder::new()
 		.name("client_controller".to_string())
 		.spawn(move || {
-			cc.run();
+			run_client();
 			client_stopped_internal.store(true, Ordering::Relaxed);
 		});
 
-	loop {
-		if miner_stopped.load(Ordering::Relaxed)
-			&& client_stopped.load(Ordering::Relaxed)
-			&& tui_stopped.load(Ordering::Relaxed)
+	Some(session)
+}
+
+fn start_miner(mining_config: &MinerConfig, watcher: reload::Watcher) {
+	let algorithms = mining_config.mined_algorithms();
+	if algorithms.is_empty() {
+		println!("No algorithm configured to mine. Exiting");
+		return;
+	}
+	// one connection for all algorithms when following the pool's
+	// algorithm, otherwise one each
+	let solo = mining_config.node_api_url.is_some();
+	if solo && mining_config.follow_algorithm {
+		warn!(
+			LOGGER,
+			"follow_algorithm has no effect when solo mining, every algorithm asks the node for its own blocks"
+		);
+	}
+	let groups: Vec<Vec<Algorithm>> = if mining_config.follow_algorithm && !solo {
+		vec![algorithms.clone()]
+	} else {
+		algorithms.iter().map(|&a| vec![a]).collect()
+	};
+	let mut sessions: Vec<Session> = vec![];
+	for group in groups {
+		match start_session(&group, mining_config) {
+			Some(s) => sessions.push(s),
+			None => {
+				for s in &sessions {
+					s.shutdown();
+				}
+				return;
+			}
+		}
+	}
+	let client_txs: Vec<_> = sessions.iter().map(|s| s.client_tx.clone()).collect();
+	let miner_txs: Vec<_> = sessions
+		.iter()
+		.flat_map(|s| s.miner_txs.iter().cloned())
+		.collect();
+
+	if let Some(ref addr) = mining_config.api_listen_addr {
+		let control = mining_config.api_token.as_ref().map(|t| api::Control {
+			token: t.clone(),
+			client_txs: client_txs.clone(),
+			miner_txs: miner_txs.clone(),
+			reload_tx: watcher.sender(),
+		});
+		let stats = sessions.iter().map(|s| s.stats.clone()).collect();
+		if let Err(e) = api::start(addr, stats, control) {
+			error!(
+				LOGGER,
+				"Can't start the monitoring API on {}: {:?}", addr, e
+			);
+		}
+	}
+
+	watcher.start(client_txs.clone());
+
+	let tui_quit = Arc::new(AtomicBool::new(false));
+	let tui_stopped = Arc::new(AtomicBool::new(false));
+
+	if mining_config.run_tui {
+		#[cfg(feature = "tui")]
+		with_tui::start_tui(
+			sessions
+				.iter()
+				.map(|s| (s.algorithms.clone(), s.stats.clone()))
+				.collect(),
+			client_txs,
+			miner_txs,
+			tui_quit.clone(),
+			tui_stopped.clone(),
+		);
+
+		#[cfg(not(feature = "tui"))]
 		{
-			thread::sleep(std::time::Duration::from_millis(100));
-			break;
+			warn!(LOGGER, "Epic-miner was built with TUI support disabled!");
+			tui_stopped.store(true, Ordering::Relaxed);
+		}
+	} else {
+		tui_stopped.store(true, Ordering::Relaxed);
+	}
+
+	loop {
+		// sessions also stop without the TUI, e.g. when shut down through
+		// the API, close it then
+		if sessions.iter().all(|s| s.stopped()) {
+			tui_quit.store(true, Ordering::Relaxed);
+			if tui_stopped.load(Ordering::Relaxed) {
+				thread::sleep(Duration::from_millis(100));
+				break;
+			}
+		}
+		thread::sleep(Duration::from_millis(100));
+	}
+}
+
+/// Benchmarks the miner of `algorithm` as configured
+fn benchmark_algorithm(
+	algorithm: Algorithm,
+	mining_config: &MinerConfig,
+	opts: &benchmark::Options,
+) -> benchmark::Report {
+	match algorithm {
+		Algorithm::RandomX => benchmark::run(
+			randomx::RxMiner::new(mining_config),
+			algorithm,
+			json!(mining_config.randomx_config),
+			opts,
+		),
+		Algorithm::Cuckoo => benchmark::run(
+			cuckoo::CuckooMiner::new(mining_config),
+			algorithm,
+			json!(mining_config.miner_plugin_config),
+			opts,
+		),
+		#[cfg(feature = "opencl")]
+		Algorithm::ProgPow => benchmark::run(
+			progpow::PpMiner::new(mining_config),
+			algorithm,
+			json!(mining_config.gpu_config),
+			opts,
+		),
+		#[cfg(feature = "cuda")]
+		Algorithm::ProgPow => benchmark::run(
+			progpow::PpMiner::new(mining_config),
+			algorithm,
+			json!(mining_config.gpu_config),
+			opts,
+		),
+		#[allow(unreachable_patterns)]
+		_ => panic!("This algorithm is not supported in this build!"),
+	}
+}
+
+/// Benchmark options given on the command line, runs last `duration`
+/// seconds unless set
+fn benchmark_options(args: &clap::ArgMatches, duration: u64) -> benchmark::Options {
+	let number = |name: &str| {
+		args.value_of(name).map(|v| {
+			v.parse::<u64>()
+				.unwrap_or_else(|_| panic!("Error: {} must be a number", name))
+		})
+	};
+	benchmark::Options {
+		duration: Duration::from_secs(number("duration").unwrap_or(duration)),
+		iterations: number("iterations"),
+		difficulty: number("difficulty"),
+	}
+}
+
+/// Saves the output of a subcommand to the file given with `--output`,
+/// or prints it
+fn write_output(args: &clap::ArgMatches, what: &str, output: &str) {
+	match args.value_of("output") {
+		Some(path) => {
+			fs::write(path, output)
+				.unwrap_or_else(|e| panic!("Error writing {}: {}", what, e));
+			println!("{} saved to {}", what, path);
+		}
+		None => println!("{}", output),
+	}
+}
+
+/// Runs `epic-miner benchmark` for every configured algorithm, cuckoo
+/// plugins one at a time so they don't compete for the same devices
+fn run_benchmark(mining_config: &MinerConfig, args: &clap::ArgMatches) {
+	let opts = benchmark_options(args, 60);
+
+	let mut reports = vec![];
+	for algorithm in mining_config.mined_algorithms() {
+		let mut config = mining_config.clone();
+		config.algorithm = Some(algorithm);
+		if algorithm == Algorithm::Cuckoo {
+			for plugin in &mining_config.miner_plugin_config {
+				config.miner_plugin_config = vec![plugin.clone()];
+				reports.push(benchmark_algorithm(algorithm, &config, &opts));
+			}
+		} else {
+			reports.push(benchmark_algorithm(algorithm, &config, &opts));
 		}
-		thread::sleep(std::time::Duration::from_millis(100));
 	}
+
+	let report = serde_json::to_string_pretty(&reports).unwrap();
+	write_output(args, "Benchmark report", &report);
+}
+
+/// Runs `epic-miner autotune`, the RandomX settings and every cuckoo
+/// plugin are tuned on their own
+fn run_autotune(mining_config: &MinerConfig, args: &clap::ArgMatches) {
+	let opts = benchmark_options(args, 30);
+	let cpus = util::cpu_threads();
+	let mut tuned = autotune::TunedConfig::default();
+	for algorithm in mining_config.mined_algorithms() {
+		let mut config = mining_config.clone();
+		config.algorithm = Some(algorithm);
+		match algorithm {
+			Algorithm::RandomX => {
+				let start = autotune::rx_params(&mining_config.randomx_config);
+				let (best, _) = autotune::search(&start, &autotune::rx_space(cpus), |params| {
+					config.randomx_config = autotune::rx_config(params);
+					autotune::Trial::new(params, &benchmark_algorithm(algorithm, &config, &opts))
+				});
+				match best {
+					Some(t) => tuned.mining.randomx_config = Some(autotune::rx_config(&t.params)),
+					None => warn!(LOGGER, "Autotune: no stable RandomX settings found"),
+				}
+			}
+			Algorithm::Cuckoo => {
+				for plugin in &mining_config.miner_plugin_config {
+					let name = &plugin.plugin_name;
+					let start = autotune::plugin_params(plugin);
+					let space = autotune::plugin_space(name, cpus);
+					let (best, _) = autotune::search(&start, &space, |params| {
+						config.miner_plugin_config = vec![autotune::plugin_config(name, params)];
+						let report = benchmark_algorithm(algorithm, &config, &opts);
+						autotune::Trial::new(params, &report)
+					});
+					match best {
+						Some(t) => tuned
+							.mining
+							.miner_plugin_config
+							.push(autotune::plugin_config(name, &t.params)),
+						None => {
+							warn!(LOGGER, "Autotune: no stable parameters found for {}", name);
+							tuned.mining.miner_plugin_config.push(plugin.clone());
+						}
+					}
+				}
+			}
+			Algorithm::ProgPow => warn!(LOGGER, "Autotune: ProgPow has nothing to tune"),
+		}
+	}
+
+	let section = format!(
+		"# Generated by epic-miner autotune, replace the matching sections\n\
+		 # of epic-miner.toml with these\n\n{}",
+		toml::to_string(&tuned).unwrap()
+	);
+	write_output(args, "Tuned configuration", &section);
+}
+
+/// Runs `epic-miner replay`, with the algorithm the captured client
+/// mined and the configured stratum settings
+fn run_replay(mining_config: &MinerConfig, args: &clap::ArgMatches) {
+	let path = args.value_of("capture").unwrap();
+	let entries = capture::read(path).unwrap_or_else(|e| panic!("Error: {:?}", e));
+	let algorithm = capture::algorithm(&entries)
+		.or_else(|| mining_config.mined_algorithms().first().cloned())
+		.expect("No algorithm configured to replay with");
+	info!(
+		LOGGER,
+		"Replaying {} captured lines with {:?}",
+		entries.len(),
+		algorithm
+	);
+	let replay = capture::replay(&entries, algorithm, mining_config)
+		.unwrap_or_else(|e| panic!("Error replaying {}: {:?}", path, e));
+	let report = serde_json::to_string_pretty(&replay).unwrap();
+	write_output(args, "Replay report", &report);
+}
+
+/// Runs `epic-miner proxy`, mining with the miners that connect to it
+/// through one session with the stratum server
+fn run_proxy(mining_config: &MinerConfig, args: &clap::ArgMatches) {
+	let algorithm = match mining_config.mined_algorithms().first() {
+		Some(&a) => a,
+		None => {
+			println!("No algorithm configured to mine. Exiting");
+			return;
+		}
+	};
+	if mining_config.mined_algorithms().len() > 1 || mining_config.follow_algorithm {
+		warn!(LOGGER, "The proxy only serves jobs for {:?}", algorithm);
+	}
+	if mining_config.node_api_url.is_some() {
+		warn!(LOGGER, "The proxy connects to the stratum server, node_api_url is not used");
+	}
+	let addr = args
+		.value_of("listen")
+		.unwrap_or(&mining_config.proxy_listen_addr)
+		.to_owned();
+
+	let stats = Arc::new(RwLock::new(stats::Stats::default()));
+	let (miner_tx, miner_rx) = mpsc::channel();
+	let cc = client::Controller::new(algorithm, mining_config, miner_tx, stats.clone())
+		.unwrap_or_else(|e| {
+			panic!("Error loading stratum client controller: {:?}", e);
+		});
+	let proxy = proxy::Proxy::new(algorithm, cc.tx.clone(), stats.clone());
+	if let Err(e) = proxy::Proxy::listen(proxy.clone(), &addr) {
+		error!(LOGGER, "Can't accept miners on {}: {:?}", addr, e);
+		return;
+	}
+	if let Some(ref api_addr) = mining_config.api_listen_addr {
+		if let Err(e) = api::start(api_addr, vec![stats], None) {
+			error!(
+				LOGGER,
+				"Can't start the monitoring API on {}: {:?}", api_addr, e
+			);
+		}
+	}
+
+	let _ = thread::Builder::new()
+		.name("client_controller".to_string())
+		.spawn(move || cc.run());
+	proxy.run(miner_rx);
 }
 
 fn main() {
@@ -224,7 +619,7 @@ fn main() {
 	let args = App::from_yaml(yml).get_matches();
 	if let ("new_config", _) = args.subcommand() {
 		GlobalConfig::default()
-			.copy_config_file()
+			.create_config_file()
 			.unwrap_or_else(|e| panic!("Error: {}", e));
 		return ();
 	}
@@ -233,11 +628,13 @@ fn main() {
 		GlobalConfig::new(args.value_of("config_file_path")).unwrap_or_else(|e| {
 			panic!("Error parsing config file: {}", e);
 		});
+	let config_file_path = global_config
+		.config_file_path
+		.clone()
+		.expect("Couldn't find the configuration file");
 	println!(
 		"Starting Epic-Miner from config file at: {}",
-		global_config
-			.config_file_path
-			.expect("Couldn't find the configuration file")
+		config_file_path
 			.to_str()
 			.expect("The path to the configuration file is not a valid utf-8 string")
 	);
@@ -261,34 +658,34 @@ fn main() {
 
 	log_build_info();
 
+	match args.subcommand() {
+		("benchmark", Some(bench_args)) => {
+			run_benchmark(&mining_config, bench_args);
+			return;
+		}
+		("autotune", Some(tune_args)) => {
+			run_autotune(&mining_config, tune_args);
+			return;
+		}
+		("replay", Some(replay_args)) => {
+			run_replay(&mining_config, replay_args);
+			return;
+		}
+		("proxy", Some(proxy_args)) => {
+			run_proxy(&mining_config, proxy_args);
+			return;
+		}
+		_ => {}
+	}
+
 	// Load plugin configuration and start solvers first,
 	// so we can exit pre-tui if something is obviously wrong
 	debug!(LOGGER, "Starting solvers");
 
-	match mining_config.algorithm.clone().unwrap() {
-		Algorithm::RandomX => start_miner(
-			randomx::RxMiner::new(&mining_config),
-			mining_config.algorithm.clone().unwrap(),
-			&mining_config,
-		), //randomx::RxMiner::new(&mining_config),
-		Algorithm::Cuckoo => start_miner(
-			cuckoo::CuckooMiner::new(&mining_config),
-			mining_config.algorithm.clone().unwrap(),
-			&mining_config,
-		),
-		#[cfg(feature = "opencl")]
-		Algorithm::ProgPow => start_miner(
-			progpow::PpMiner::new(&mining_config),
-			mining_config.algorithm.clone().unwrap(),
-			&mining_config,
-		),
-		#[cfg(feature = "cuda")]
-		Algorithm::ProgPow => start_miner(
-			progpow::PpMiner::new(&mining_config),
-			mining_config.algorithm.clone().unwrap(),
-			&mining_config,
-		),
-		#[allow(unreachable_patterns)]
-		_ => panic!("This algorithm is not supported in this build!"),
-	}
+	let watcher = reload::Watcher::new(
+		config_file_path,
+		global_config.members.clone().unwrap(),
+	);
+
+	start_miner(&mining_config, watcher);
 }
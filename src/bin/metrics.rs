@@ -0,0 +1,323 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Miner stats in the Prometheus text exposition format
+
+use stats;
+use std::fmt::Write;
+
+/// Content type of the exposition format
+pub const CONTENT_TYPE: &'static str = "text/plain; version=0.0.4";
+
+/// Collects the samples of one metric family
+struct Family {
+	name: &'static str,
+	help: &'static str,
+	kind: &'static str,
+	samples: Vec<(Vec<(&'static str, String)>, f64)>,
+}
+
+impl Family {
+	fn new(name: &'static str, kind: &'static str, help: &'static str) -> Family {
+		Family {
+			name: name,
+			help: help,
+			kind: kind,
+			samples: vec![],
+		}
+	}
+
+	fn add(&mut self, labels: Vec<(&'static str, String)>, value: f64) {
+		self.samples.push((labels, value));
+	}
+
+	fn write_to(&self, out: &mut String) {
+		if self.samples.is_empty() {
+			return;
+		}
+		let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+		let _ = writeln!(out, "# TYPE {} {}", self.name, self.kind);
+		for &(ref labels, value) in &self.samples {
+			out.push_str(self.name);
+			if !labels.is_empty() {
+				let labels: Vec<_> = labels
+					.iter()
+					.map(|&(k, ref v)| format!("{}=\"{}\"", k, escape(v)))
+					.collect();
+				let _ = write!(out, "{{{}}}", labels.join(","));
+			}
+			let _ = writeln!(out, " {}", value);
+		}
+	}
+}
+
+fn escape(v: &str) -> String {
+	v.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+/// Renders all metrics for the stats snapshots of the mined algorithms
+pub fn render(all: &[stats::Stats]) -> String {
+	let mut hps = Family::new(
+		"epic_miner_device_hashes_per_second",
+		"gauge",
+		"Hashes per second of a device",
+	);
+	let mut gps = Family::new(
+		"epic_miner_device_graphs_per_second",
+		"gauge",
+		"Cuck(at)oo graphs per second of a device",
+	);
+	let mut iterations = Family::new(
+		"epic_miner_device_iterations_total",
+		"counter",
+		"Solver iterations of a device",
+	);
+	let mut errored = Family::new(
+		"epic_miner_device_errored",
+		"gauge",
+		"1 if the plugin running on a device has errored",
+	);
+	let mut counters = vec![
+		Family::new(
+			"epic_miner_solutions_found_total",
+			"counter",
+			"Solutions found and submitted",
+		),
+		Family::new(
+			"epic_miner_shares_accepted_total",
+			"counter",
+			"Shares accepted by the pool",
+		),
+		Family::new(
+			"epic_miner_shares_rejected_total",
+			"counter",
+			"Shares rejected by the pool",
+		),
+		Family::new(
+			"epic_miner_shares_stale_total",
+			"counter",
+			"Shares the pool considered stale",
+		),
+		Family::new("epic_miner_blocks_found_total", "counter", "Blocks found"),
+		Family::new(
+			"epic_miner_hardware_errors_total",
+			"counter",
+			"Solutions that failed verification and were dropped",
+		),
+	];
+	let mut paused = Family::new(
+		"epic_miner_paused",
+		"gauge",
+		"1 while mining is paused on request",
+	);
+	let mut connected = Family::new(
+		"epic_miner_pool_connected",
+		"gauge",
+		"1 while connected to the stratum server",
+	);
+	let mut height = Family::new(
+		"epic_miner_block_height",
+		"gauge",
+		"Height of the block being mined",
+	);
+	let mut difficulty = Family::new(
+		"epic_miner_target_difficulty",
+		"gauge",
+		"Share difficulty of the current job",
+	);
+	let mut datasets = Family::new(
+		"epic_miner_randomx_dataset_state",
+		"gauge",
+		"RandomX dataset of an epoch, 1 for its current load state",
+	);
+	let mut worker_shares = Family::new(
+		"epic_miner_worker_shares_total",
+		"counter",
+		"Shares of a miner connected to the proxy, by result",
+	);
+	let mut worker_connections = Family::new(
+		"epic_miner_worker_connections",
+		"gauge",
+		"Open connections of a miner to the proxy",
+	);
+
+	for s in all {
+		let algorithm = s.client_stats.my_algorithm.clone();
+		let algo = || vec![("algorithm", algorithm.clone())];
+		let m = &s.mining_stats;
+		let sols = &m.solution_stats;
+
+		for d in &m.device_stats {
+			let labels = vec![
+				("device", d.get_device_name()),
+				("device_id", d.device_id.to_string()),
+				("plugin", d.get_plugin_name()),
+				("algorithm", algorithm.clone()),
+			];
+			// only cuckoo plugins report graph sizes, and no hash rate
+			if d.edge_bits > 0 {
+				let rate = if d.last_solution_time > 0 {
+					1_000_000_000.0 / d.last_solution_time as f64
+				} else {
+					0.0
+				};
+				gps.add(labels.clone(), rate);
+			} else {
+				hps.add(labels.clone(), d.hashes_per_sec as f64);
+			}
+			iterations.add(labels.clone(), d.iterations as f64);
+			errored.add(labels, if d.has_errored { 1.0 } else { 0.0 });
+		}
+
+		let values = [
+			sols.num_solutions_found,
+			sols.num_shares_accepted,
+			sols.num_rejected,
+			sols.num_staled,
+			sols.num_blocks_found,
+			sols.num_hw_errors,
+		];
+		for (f, value) in counters.iter_mut().zip(values.iter()) {
+			f.add(algo(), *value as f64);
+		}
+
+		paused.add(algo(), if m.paused { 1.0 } else { 0.0 });
+		connected.add(
+			vec![
+				("server", s.client_stats.server_url.clone()),
+				("algorithm", algorithm.clone()),
+			],
+			if s.client_stats.connected { 1.0 } else { 0.0 },
+		);
+		height.add(algo(), m.block_height as f64);
+		difficulty.add(algo(), m.target_difficulty as f64);
+
+		for d in &m.datasets {
+			for state in &["waiting", "loading", "loaded", "running", "failed"] {
+				datasets.add(
+					vec![
+						("start_height", d.start_height.to_string()),
+						("end_height", d.end_height.to_string()),
+						("state", state.to_string()),
+					],
+					if d.state == *state { 1.0 } else { 0.0 },
+				);
+			}
+		}
+
+		for w in &s.workers {
+			for &(result, value) in &[
+				("accepted", w.shares_accepted),
+				("rejected", w.shares_rejected),
+				("stale", w.shares_stale),
+			] {
+				worker_shares.add(
+					vec![("worker", w.name.clone()), ("result", result.to_owned())],
+					value as f64,
+				);
+			}
+			worker_connections.add(vec![("worker", w.name.clone())], w.connections as f64);
+		}
+	}
+
+	let mut out = String::new();
+	for f in [hps, gps, iterations, errored]
+		.iter()
+		.chain(counters.iter())
+		.chain(
+			[
+				paused,
+				connected,
+				height,
+				difficulty,
+				datasets,
+				worker_shares,
+				worker_connections,
+			]
+				.iter(),
+		)
+	{
+		f.write_to(&mut out);
+	}
+	out
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use core::{DatasetStatus, Stats};
+
+	#[test]
+	fn render_metrics() {
+		let mut s = stats::Stats::default();
+		s.client_stats.my_algorithm = "Cuckatoo".to_owned();
+		s.client_stats.connected = true;
+		s.mining_stats.solution_stats.num_shares_accepted = 7;
+		let mut d = Stats::default();
+		d.set_device_name("GPU \"0\"");
+		d.set_plugin_name("ocl_cuckatoo");
+		d.edge_bits = 31;
+		d.last_solution_time = 500_000_000;
+		d.has_errored = true;
+		s.mining_stats.device_stats.push(d);
+		s.mining_stats.datasets.push(DatasetStatus {
+			start_height: 0,
+			end_height: 100,
+			state: "loading".to_owned(),
+		});
+
+		let mut other = stats::Stats::default();
+		other.client_stats.my_algorithm = "RandomX".to_owned();
+		other.mining_stats.solution_stats.num_shares_accepted = 2;
+		other.workers.push(stats::WorkerStats {
+			name: "rig1".to_owned(),
+			connections: 1,
+			shares_stale: 3,
+			..stats::WorkerStats::default()
+		});
+
+		let out = render(&[s, other]);
+		let labels = "device=\"GPU \\\"0\\\"\",device_id=\"0\",plugin=\"ocl_cuckatoo\",\
+		              algorithm=\"Cuckatoo\"";
+		assert!(out.contains("# TYPE epic_miner_device_graphs_per_second gauge\n"));
+		assert!(out.contains(&format!(
+			"epic_miner_device_graphs_per_second{{{}}} 2\n",
+			labels
+		)));
+		assert!(out.contains(&format!("epic_miner_device_errored{{{}}} 1\n", labels)));
+		assert!(!out.contains("epic_miner_device_hashes_per_second"));
+		assert!(out.contains("epic_miner_shares_accepted_total{algorithm=\"Cuckatoo\"} 7\n"));
+		assert!(out.contains("epic_miner_shares_accepted_total{algorithm=\"RandomX\"} 2\n"));
+		assert_eq!(
+			out.matches("# TYPE epic_miner_shares_accepted_total")
+				.count(),
+			1
+		);
+		assert!(out.contains("epic_miner_pool_connected{server=\"\",algorithm=\"Cuckatoo\"} 1\n"));
+		assert!(out.contains(
+			"epic_miner_randomx_dataset_state{start_height=\"0\",end_height=\"100\",\
+			 state=\"loading\"} 1\n"
+		));
+		assert!(out.contains(
+			"epic_miner_randomx_dataset_state{start_height=\"0\",end_height=\"100\",\
+			 state=\"running\"} 0\n"
+		));
+		assert!(out.contains(
+			"epic_miner_worker_shares_total{worker=\"rig1\",result=\"stale\"} 3\n"
+		));
+		assert!(out.contains("epic_miner_worker_connections{worker=\"rig1\"} 1\n"));
+	}
+}
@@ -12,17 +12,29 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std;
 /// Plugin controller, listens for messages sent from the stratum
 /// server, controls plugins and responds appropriately
+use std::collections::VecDeque;
 use std::sync::{mpsc, Arc, RwLock};
-use std::{self, thread};
 use time;
 use util::LOGGER;
 use {config, stats, types};
 
 use core::config::MinerConfig;
 use core::errors::MinerError;
-use core::{Algorithm, AlgorithmParams, Miner, Stats};
+use core::{Algorithm, AlgorithmParams, Miner, Solution, Stats};
+
+/// How many recent jobs are kept around to verify solutions against
+const MAX_RECENT_JOBS: usize = 16;
+
+/// Job the solvers may still return solutions for
+struct RecentJob {
+	job_id: u32,
+	height: u64,
+	difficulty: u64,
+	pre_pow: String,
+}
 
 pub struct Controller {
 	_config: MinerConfig,
@@ -33,6 +45,10 @@ pub struct Controller {
 	current_job_id: u64,
 	current_target_diff: u64,
 	current_seed: [u8; 32],
+	recent_jobs: VecDeque<RecentJob>,
+	paused: bool,
+	/// no job for our algorithm since the client stopped us
+	standby: bool,
 	stats: Arc<RwLock<stats::Stats>>,
 }
 
@@ -55,6 +71,9 @@ impl Controller {
 			current_job_id: 0,
 			current_target_diff: 0,
 			current_seed: [0; 32],
+			recent_jobs: VecDeque::new(),
+			paused: false,
+			standby: false,
 			stats: stats,
 		})
 	}
@@ -73,20 +92,47 @@ impl Controller {
 		let mut next_stat_output = time::get_time().sec + stat_output_interval;
 
 		loop {
-			while let Some(message) = self.rx.try_iter().next() {
+			// wait for the next message instead of sleeping, so new jobs
+			// reach the solvers right away
+			let mut messages = vec![];
+			if let Ok(message) = self.rx.recv_timeout(std::time::Duration::from_millis(100)) {
+				messages.push(message);
+				messages.extend(self.rx.try_iter());
+			}
+			for message in messages {
 				debug!(LOGGER, "Miner received message: {:?}", message);
 				let result = match message {
 					types::MinerMessage::ReceivedJob(height, job_id, diff, pre_pow) => {
+						self.standby = false;
 						self.current_height = height;
 						self.current_job_id = job_id;
 						self.current_target_diff = diff;
-						miner.notify(
-							self.current_job_id as u32,
-							self.current_height,
-							&pre_pow,
-							"",
-							diff,
-						)
+						self.recent_jobs.push_front(RecentJob {
+							job_id: job_id as u32,
+							height: height,
+							difficulty: diff,
+							pre_pow: pre_pow.clone(),
+						});
+						self.recent_jobs.truncate(MAX_RECENT_JOBS);
+						{
+							let mut s_stats = self.stats.write().unwrap();
+							s_stats.mining_stats.block_height = height;
+							s_stats.mining_stats.job_id = job_id;
+							s_stats.mining_stats.target_difficulty = diff;
+						}
+						// notifying would restart the solvers, the job is
+						// handed over on resume instead
+						if self.paused {
+							Ok(())
+						} else {
+							miner.notify(
+								self.current_job_id as u32,
+								self.current_height,
+								&pre_pow,
+								"",
+								diff,
+							)
+						}
 					}
 					types::MinerMessage::ReceivedSeed(epochs) => {
 						for (start_height, end_height, seed) in epochs {
@@ -94,8 +140,42 @@ impl Controller {
 						}
 						Ok(())
 					}
+					types::MinerMessage::SetExtranonce(extranonce) => {
+						miner.set_extranonce(&extranonce);
+						Ok(())
+					}
+					// the client keeps the share stats
+					types::MinerMessage::SubmitResult(..) => Ok(()),
+					types::MinerMessage::Pause => {
+						info!(LOGGER, "Pausing mining");
+						self.set_paused(true);
+						miner.pause_solvers();
+						Ok(())
+					}
+					types::MinerMessage::Resume => {
+						info!(LOGGER, "Resuming mining");
+						self.set_paused(false);
+						// after a stop the last job is outdated, mining
+						// starts again with the next one
+						if self.standby {
+							continue;
+						}
+						let result = match self.recent_jobs.front() {
+							Some(job) => miner.notify(
+								job.job_id,
+								job.height,
+								&job.pre_pow,
+								"",
+								job.difficulty,
+							),
+							None => Ok(()),
+						};
+						miner.resume_solvers();
+						result
+					}
 					types::MinerMessage::StopJob => {
 						debug!(LOGGER, "Stopping jobs");
+						self.standby = true;
 						miner.pause_solvers();
 						Ok(())
 					}
@@ -111,25 +191,85 @@ impl Controller {
 				}
 			}
 
-			if time::get_time().sec > next_stat_output {
+			// when following the pool's algorithm the miners share their
+			// stats, only the one mining reports
+			let reports = !(self.standby && self._config.follow_algorithm);
+			if reports && time::get_time().sec > next_stat_output {
 				self.output_job_stats(miner.get_stats().unwrap());
+				self.stats.write().unwrap().mining_stats.datasets = miner.dataset_status();
 				next_stat_output = time::get_time().sec + stat_output_interval;
 			}
 
 			let solutions = miner.get_solutions();
 			if let Some(ss) = solutions {
-				let len = ss.len();
 				for i in ss {
+					if !self.verify_solution(&miner, &i) {
+						let mut s_stats = self.stats.write().unwrap();
+						s_stats.mining_stats.solution_stats.num_hw_errors += 1;
+						continue;
+					}
 					let _ = self
 						.client_tx
 						.as_mut()
 						.unwrap()
 						.send(types::ClientMessage::FoundSolution(self.current_height, i));
+					let mut s_stats = self.stats.write().unwrap();
+					s_stats.mining_stats.solution_stats.num_solutions_found += 1;
 				}
-				let mut s_stats = self.stats.write().unwrap();
-				s_stats.mining_stats.solution_stats.num_solutions_found += len as u32;
 			}
-			thread::sleep(std::time::Duration::from_millis(100));
+		}
+	}
+
+	fn set_paused(&mut self, paused: bool) {
+		self.paused = paused;
+		self.stats.write().unwrap().mining_stats.paused = paused;
+	}
+
+	/// Recomputes the proof of work of a solution before it's submitted,
+	/// so a faulty device doesn't get the miner banned from the pool.
+	/// Solutions that can't be checked right now are let through.
+	fn verify_solution<T>(&self, miner: &T, solution: &Solution) -> bool
+	where
+		T: Miner,
+	{
+		let job = match self
+			.recent_jobs
+			.iter()
+			.find(|j| j.job_id as u64 == solution.get_id())
+		{
+			Some(j) => j,
+			None => {
+				debug!(
+					LOGGER,
+					"No job {} to verify solution against, submitting it unchecked",
+					solution.get_id()
+				);
+				return true;
+			}
+		};
+		match miner.verify_solution(job.height, &job.pre_pow, "", job.difficulty, solution) {
+			Ok(true) => true,
+			Ok(false) => {
+				warn!(
+					LOGGER,
+					"Hardware error: solution for job {} at height {} with nonce {} doesn't \
+					 meet difficulty {}, dropping it",
+					job.job_id,
+					job.height,
+					solution.get_nonce(),
+					job.difficulty
+				);
+				false
+			}
+			Err(e) => {
+				debug!(
+					LOGGER,
+					"Couldn't verify solution for job {}, submitting it unchecked: {:?}",
+					job.job_id,
+					e
+				);
+				true
+			}
 		}
 	}
 
@@ -0,0 +1,488 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stratum protocol dialects. The client controller works with Epic
+//! protocol requests and responses, a `Protocol` translates them to and
+//! from what actually goes over the wire.
+
+use client::Error;
+use core::config::StratumProtocol;
+use core::util::from_hex_string;
+use serde_json::{self, Value};
+use std::collections::{HashMap, VecDeque};
+use types;
+use util::LOGGER;
+
+/// Number of pool job ids remembered for share submission
+const MAX_JOBS: usize = 16;
+
+/// Longest extranonce accepted, in hex digits. Only 7 bytes of it go into
+/// the nonce, the last byte is left to the miner.
+const MAX_EXTRANONCE_HEX: usize = 14;
+
+/// A message from the server, translated into the Epic protocol
+#[derive(Debug)]
+pub enum Inbound {
+	/// server initiated request, e.g. a new job
+	Request(types::RpcRequest),
+	/// answer to one of our requests
+	Response(types::RpcResponse),
+	/// leading nonce bytes assigned by the pool
+	Extranonce(Vec<u8>),
+}
+
+pub trait Protocol: Send {
+	/// Lines to send right after connecting, before logging in
+	fn handshake(&mut self) -> Result<Vec<String>, Error> {
+		Ok(vec![])
+	}
+
+	/// Wire format of an Epic protocol request, `None` if the dialect has
+	/// no equivalent and the request shouldn't be sent at all
+	fn encode(&mut self, req: &types::RpcRequest) -> Result<Option<String>, Error>;
+
+	/// Translates a line received from the server
+	fn decode(&mut self, line: &str) -> Result<Vec<Inbound>, Error>;
+}
+
+/// Creates the protocol handler for a new connection
+pub fn create(protocol: StratumProtocol) -> Box<dyn Protocol> {
+	match protocol {
+		StratumProtocol::Epic => Box::new(EpicProtocol),
+		StratumProtocol::Stratum => Box::new(StandardProtocol::new()),
+	}
+}
+
+/// The Epic node's own protocol, already what the controller speaks. On
+/// top of it `epic-miner proxy` assigns every miner its leading nonce bytes
+/// with `set_extranonce {"extranonce": hex}`.
+pub struct EpicProtocol;
+
+impl Protocol for EpicProtocol {
+	fn encode(&mut self, req: &types::RpcRequest) -> Result<Option<String>, Error> {
+		Ok(Some(serde_json::to_string(req)?))
+	}
+
+	fn decode(&mut self, line: &str) -> Result<Vec<Inbound>, Error> {
+		let v = serde_json::from_str::<Value>(line)?;
+		if v["method"] == "set_extranonce" {
+			let extranonce = extranonce(v["params"].get("extranonce"))?;
+			return Ok(vec![Inbound::Extranonce(extranonce)]);
+		}
+		// Is this a response or request?
+		if v["method"] == String::from("job") {
+			Ok(vec![Inbound::Request(serde_json::from_value(v)?)])
+		} else {
+			Ok(vec![Inbound::Response(serde_json::from_value(v)?)])
+		}
+	}
+}
+
+/// The conventional mining.* dialect used by many pools:
+///
+/// * `mining.subscribe` -> `[[subscriptions], extranonce1, extranonce2_size]`
+/// * `mining.authorize [login, password]` -> `true`
+/// * `mining.set_difficulty [difficulty]`
+/// * `mining.set_extranonce [extranonce1, extranonce2_size]`
+/// * `mining.notify [job_id, height, algorithm, pre_pow, clean_jobs, epochs]`
+/// * `mining.submit [login, job_id, nonce, pow]` -> `true`
+///
+/// Jobs are pushed by the pool, so there's no equivalent of
+/// `getjobtemplate`, `status` or `keepalive`.
+pub struct StandardProtocol {
+	subscribe_id: u64,
+	login: String,
+	difficulty: u64,
+	/// methods of our requests by id, to translate their results
+	methods: HashMap<u64, String>,
+	/// internal job id and the pool's job id of recent jobs
+	jobs: VecDeque<(u64, String)>,
+	next_job_id: u64,
+}
+
+impl StandardProtocol {
+	pub fn new() -> StandardProtocol {
+		StandardProtocol {
+			subscribe_id: 0,
+			login: String::new(),
+			difficulty: 1,
+			methods: HashMap::new(),
+			jobs: VecDeque::new(),
+			next_job_id: 1,
+		}
+	}
+
+	fn request(id: u64, method: &str, params: Value) -> Result<String, Error> {
+		Ok(serde_json::to_string(&json!({
+			"id": id,
+			"method": method,
+			"params": params,
+		}))?)
+	}
+
+	fn pool_job_id(&self, job_id: u64) -> Option<String> {
+		self.jobs
+			.iter()
+			.find(|(id, _)| *id == job_id)
+			.map(|(_, pool_id)| pool_id.clone())
+	}
+
+	fn notify(&mut self, params: &Value) -> Result<types::RpcRequest, Error> {
+		let field = |i: usize| {
+			params.get(i).cloned().ok_or(Error::RequestError(format!(
+				"mining.notify is missing parameter {}",
+				i
+			)))
+		};
+		let pool_job_id = match field(0)? {
+			Value::String(s) => s,
+			v => v.to_string(),
+		};
+		let height = field(1)?.as_u64().ok_or(Error::RequestError(
+			"Invalid height in mining.notify".to_string(),
+		))?;
+		let algorithm = field(2)?
+			.as_str()
+			.ok_or(Error::RequestError(
+				"Invalid algorithm in mining.notify".to_string(),
+			))?
+			.to_string();
+		let pre_pow = field(3)?
+			.as_str()
+			.ok_or(Error::RequestError(
+				"Invalid header in mining.notify".to_string(),
+			))?
+			.to_string();
+		if params.get(4).and_then(|v| v.as_bool()) == Some(true) {
+			self.jobs.clear();
+		}
+		let epochs = match params.get(5) {
+			Some(v) if !v.is_null() => serde_json::from_value(v.clone())?,
+			_ => vec![],
+		};
+
+		let job_id = self.next_job_id;
+		self.next_job_id += 1;
+		self.jobs.push_back((job_id, pool_job_id));
+		if self.jobs.len() > MAX_JOBS {
+			self.jobs.pop_front();
+		}
+
+		let job = types::JobTemplate {
+			height: height,
+			job_id: job_id,
+			difficulty: vec![(algorithm.clone(), self.difficulty)],
+			block_difficulty: vec![],
+			pre_pow: pre_pow,
+			epochs: epochs,
+			algorithm: algorithm,
+		};
+		Ok(types::RpcRequest {
+			id: "0".to_string(),
+			jsonrpc: "2.0".to_string(),
+			method: "job".to_string(),
+			params: Some(serde_json::to_value(job)?),
+		})
+	}
+
+	fn response(&mut self, id: u64, v: &Value) -> Result<Vec<Inbound>, Error> {
+		let error = match v.get("error") {
+			Some(e) if !e.is_null() => Some(stratum_error(e)),
+			_ => None,
+		};
+		if id == self.subscribe_id {
+			if let Some(e) = error {
+				return Err(Error::ResponseError(format!(
+					"mining.subscribe failed: {}",
+					e.message
+				)));
+			}
+			let extranonce = extranonce(v["result"].get(1))?;
+			return Ok(vec![Inbound::Extranonce(extranonce)]);
+		}
+		let method = self.methods.remove(&id).unwrap_or(String::new());
+		// login and submit results are plain booleans
+		let (result, error) = match (v.get("result"), error) {
+			(_, Some(e)) => (None, Some(e)),
+			(Some(&Value::Bool(true)), None) => (Some(Value::String("ok".to_string())), None),
+			(Some(&Value::Bool(false)), None) | (Some(&Value::Null), None) | (None, None) => (
+				None,
+				Some(types::RpcError {
+					code: -1,
+					message: format!("{} rejected", method),
+				}),
+			),
+			(Some(r), None) => (Some(r.clone()), None),
+		};
+		Ok(vec![Inbound::Response(types::RpcResponse {
+			id: id.to_string(),
+			method: method,
+			jsonrpc: "2.0".to_string(),
+			result: result,
+			error: error,
+		})])
+	}
+}
+
+/// Extranonce of a subscribe result or set_extranonce request in either
+/// dialect, checked here as it comes straight from the pool
+fn extranonce(value: Option<&Value>) -> Result<Vec<u8>, Error> {
+	match value.and_then(|v| v.as_str()) {
+		Some(hex)
+			if hex.len() % 2 == 0
+				&& hex.len() <= MAX_EXTRANONCE_HEX
+				&& hex.chars().all(|c| c.is_ascii_hexdigit()) =>
+		{
+			Ok(from_hex_string(hex))
+		}
+		_ => Err(Error::ResponseError(format!(
+			"Invalid extranonce: {:?}",
+			value
+		))),
+	}
+}
+
+/// Errors come as `[code, message, data]` or as an object
+fn stratum_error(e: &Value) -> types::RpcError {
+	match serde_json::from_value::<types::RpcError>(e.clone()) {
+		Ok(err) => err,
+		Err(_) => types::RpcError {
+			code: e.get(0).and_then(|c| c.as_i64()).unwrap_or(-1) as i32,
+			message: e
+				.get(1)
+				.and_then(|m| m.as_str())
+				.map(|m| m.to_string())
+				.unwrap_or(e.to_string()),
+		},
+	}
+}
+
+impl Protocol for StandardProtocol {
+	fn handshake(&mut self) -> Result<Vec<String>, Error> {
+		let agent = format!("epic-miner/v{}", env!("CARGO_PKG_VERSION"));
+		Ok(vec![StandardProtocol::request(
+			self.subscribe_id,
+			"mining.subscribe",
+			json!([agent]),
+		)?])
+	}
+
+	fn encode(&mut self, req: &types::RpcRequest) -> Result<Option<String>, Error> {
+		let id = req
+			.id
+			.parse::<u64>()
+			.map_err(|_| Error::RequestError(format!("Invalid request id {}", req.id)))?;
+		let params = req.params.clone().unwrap_or(Value::Null);
+		let line = match req.method.as_str() {
+			"login" => {
+				let login: types::LoginParams = serde_json::from_value(params)?;
+				self.login = login.login.clone();
+				StandardProtocol::request(id, "mining.authorize", json!([login.login, login.pass]))?
+			}
+			"submit" => {
+				let submit: types::SubmitParams = serde_json::from_value(params)?;
+				let job_id =
+					self.pool_job_id(submit.job_id)
+						.ok_or(Error::RequestError(format!(
+							"Job {} is no longer known to the pool",
+							submit.job_id
+						)))?;
+				StandardProtocol::request(
+					id,
+					"mining.submit",
+					json!([
+						self.login,
+						job_id,
+						format!("{:016x}", submit.nonce),
+						submit.pow
+					]),
+				)?
+			}
+			_ => return Ok(None),
+		};
+		self.methods.insert(id, req.method.clone());
+		Ok(Some(line))
+	}
+
+	fn decode(&mut self, line: &str) -> Result<Vec<Inbound>, Error> {
+		let v = serde_json::from_str::<Value>(line)?;
+		let params = v["params"].clone();
+		match v["method"].as_str() {
+			Some("mining.notify") => Ok(vec![Inbound::Request(self.notify(&params)?)]),
+			Some("mining.set_difficulty") => {
+				self.difficulty =
+					params[0]
+						.as_f64()
+						.map(|d| d.max(1.0) as u64)
+						.ok_or(Error::RequestError(format!(
+							"Invalid difficulty {}",
+							params
+						)))?;
+				Ok(vec![])
+			}
+			Some("mining.set_extranonce") => {
+				Ok(vec![Inbound::Extranonce(extranonce(params.get(0))?)])
+			}
+			Some(method) => {
+				warn!(LOGGER, "Ignoring unsupported stratum request {}", method);
+				Ok(vec![])
+			}
+			None => match v["id"].as_u64() {
+				Some(id) => self.response(id, &v),
+				None => Err(Error::ResponseError(format!(
+					"Response without a valid id: {}",
+					line
+				))),
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn epic_extranonce() {
+		let mut p = EpicProtocol;
+		let line = r#"{"id":"Stratum","jsonrpc":"2.0","method":"set_extranonce","params":{"extranonce":"0a01"}}"#;
+		match p.decode(line).unwrap().pop() {
+			Some(Inbound::Extranonce(ref e)) => assert_eq!(e, &vec![10, 1]),
+			m => panic!("expected an extranonce, got {:?}", m),
+		}
+		assert!(p.decode(r#"{"method":"set_extranonce","params":{}}"#).is_err());
+	}
+
+	#[test]
+	fn epic_bad_extranonce() {
+		let mut p = EpicProtocol;
+		for bad in &["0a1", "0g", "é0", "0102030405060708"] {
+			let line = json!({"method": "set_extranonce", "params": {"extranonce": bad}});
+			match p.decode(&line.to_string()) {
+				Err(Error::ResponseError(_)) => (),
+				r => panic!("{} accepted: {:?}", bad, r),
+			}
+		}
+	}
+
+	fn job(line: &str, p: &mut StandardProtocol) -> types::JobTemplate {
+		match p.decode(line).unwrap().pop() {
+			Some(Inbound::Request(r)) => serde_json::from_value(r.params.unwrap()).unwrap(),
+			m => panic!("expected a job, got {:?}", m),
+		}
+	}
+
+	#[test]
+	fn standard_subscribe_and_extranonce() {
+		let mut p = StandardProtocol::new();
+		let hs = p.handshake().unwrap();
+		assert!(hs[0].contains("mining.subscribe"));
+		let msgs = p
+			.decode(
+				r#"{"id":0,"result":[[["mining.notify","ae6812eb"]],"08000002",4],"error":null}"#,
+			)
+			.unwrap();
+		match msgs[0] {
+			Inbound::Extranonce(ref e) => assert_eq!(e, &vec![8, 0, 0, 2]),
+			ref m => panic!("expected an extranonce, got {:?}", m),
+		}
+	}
+
+	#[test]
+	fn standard_bad_extranonce() {
+		let mut p = StandardProtocol::new();
+		p.handshake().unwrap();
+		let subscribed = p.decode(r#"{"id":0,"result":[[],"08000é",4],"error":null}"#);
+		match subscribed {
+			Err(Error::ResponseError(_)) => (),
+			r => panic!("extranonce accepted: {:?}", r),
+		}
+		for bad in &["080", "zz", "0102030405060708"] {
+			let line = json!({"id": null, "method": "mining.set_extranonce", "params": [bad, 4]});
+			match p.decode(&line.to_string()) {
+				Err(Error::ResponseError(_)) => (),
+				r => panic!("{} accepted: {:?}", bad, r),
+			}
+		}
+	}
+
+	#[test]
+	fn standard_job_and_submit() {
+		let mut p = StandardProtocol::new();
+		assert!(p
+			.decode(r#"{"id":null,"method":"mining.set_difficulty","params":[16]}"#)
+			.unwrap()
+			.is_empty());
+		let j = job(
+			r#"{"id":null,"method":"mining.notify","params":["bf0a",100,"randomx","00ff",true]}"#,
+			&mut p,
+		);
+		assert_eq!(j.height, 100);
+		assert_eq!(j.difficulty, vec![("randomx".to_string(), 16)]);
+		assert_eq!(j.pre_pow, "00ff");
+
+		let submit = types::RpcRequest {
+			id: "7".to_string(),
+			jsonrpc: "2.0".to_string(),
+			method: "submit".to_string(),
+			params: Some(
+				serde_json::to_value(types::SubmitParams {
+					height: 100,
+					job_id: j.job_id,
+					nonce: 255,
+					pow: ::core::AlgorithmParams::RandomX([0; 32]),
+				})
+				.unwrap(),
+			),
+		};
+		let line = p.encode(&submit).unwrap().unwrap();
+		assert!(line.contains(r#""mining.submit""#));
+		assert!(line.contains(r#""bf0a""#));
+		assert!(line.contains(r#""00000000000000ff""#));
+
+		match p
+			.decode(r#"{"id":7,"result":true,"error":null}"#)
+			.unwrap()
+			.pop()
+		{
+			Some(Inbound::Response(r)) => {
+				assert_eq!(r.method, "submit");
+				assert!(r.result.is_some());
+			}
+			m => panic!("expected a response, got {:?}", m),
+		}
+		match p
+			.decode(r#"{"id":8,"result":null,"error":[23,"Low difficulty share",null]}"#)
+			.unwrap()
+			.pop()
+		{
+			Some(Inbound::Response(r)) => {
+				assert_eq!(r.error.unwrap().message, "Low difficulty share")
+			}
+			m => panic!("expected a response, got {:?}", m),
+		}
+	}
+
+	#[test]
+	fn standard_has_no_job_template_request() {
+		let mut p = StandardProtocol::new();
+		let req = types::RpcRequest {
+			id: "1".to_string(),
+			jsonrpc: "2.0".to_string(),
+			method: "getjobtemplate".to_string(),
+			params: None,
+		};
+		assert!(p.encode(&req).unwrap().is_none());
+	}
+}
@@ -0,0 +1,681 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stratum proxy. Miners connect to it as to an Epic stratum server while
+//! a client controller keeps the one session with the pool. Every miner
+//! gets the pool's extranonce plus a byte of its own, so no two of them
+//! search the same nonces, and their shares are submitted upstream and
+//! accounted to the worker that found them.
+
+use client;
+use core::{Algorithm, Solution};
+use serde_json::{self, Value};
+use stats::{self, WorkerStats};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use time;
+use types;
+use util::{to_hex, LOGGER};
+
+/// Miners connected at the same time, each one gets a byte of the nonce
+pub const MAX_WORKERS: usize = 256;
+
+/// A miner that doesn't take a line within this time is disconnected
+const WRITE_TIMEOUT_SECS: u64 = 10;
+
+/// Submits the pool never answered are forgotten after this long
+const PENDING_SUBMIT_SECS: u64 = 300;
+
+/// An open miner connection
+struct Downstream {
+	/// tells a reused slot from the connection that used it before
+	id: u64,
+	name: String,
+	/// lines for the connection's writer thread, so a miner that stopped
+	/// reading holds up nobody else
+	lines: mpsc::Sender<String>,
+	socket: TcpStream,
+}
+
+/// A share on its way to the pool
+struct PendingSubmit {
+	slot: usize,
+	conn_id: u64,
+	/// id of the miner's submit request
+	request_id: String,
+	name: String,
+	sent_at: Instant,
+}
+
+struct Job {
+	height: u64,
+	job_id: u64,
+	difficulty: u64,
+	pre_pow: String,
+}
+
+struct State {
+	job: Option<Job>,
+	epochs: Vec<(u64, u64, [u8; 32])>,
+	/// leading nonce bytes assigned by the pool
+	extranonce: Vec<u8>,
+	slots: Vec<Option<Downstream>>,
+	next_conn_id: u64,
+	/// by height and nonce
+	pending: HashMap<(u64, u64), PendingSubmit>,
+}
+
+pub struct Proxy {
+	algorithm: Algorithm,
+	upstream_tx: mpsc::Sender<types::ClientMessage>,
+	state: Mutex<State>,
+	stats: Arc<RwLock<stats::Stats>>,
+}
+
+/// Nonce bytes of the miner in `slot`
+fn worker_extranonce(upstream: &[u8], slot: usize) -> Vec<u8> {
+	let mut extranonce = upstream.to_vec();
+	extranonce.push(slot as u8);
+	extranonce
+}
+
+/// Whether `nonce` starts with `extranonce`, the most significant bytes
+/// first as the miners apply it
+fn nonce_in_range(extranonce: &[u8], nonce: u64) -> bool {
+	let len = extranonce.len().min(7);
+	let bytes: [u8; 8] = nonce.to_be_bytes();
+	bytes[..len] == extranonce[..len]
+}
+
+fn response(id: &str, method: &str, result: Result<Value, (i32, String)>) -> Value {
+	let (result, error) = match result {
+		Ok(result) => (result, Value::Null),
+		Err((code, message)) => (Value::Null, json!({ "code": code, "message": message })),
+	};
+	json!({
+		"id": id,
+		"jsonrpc": "2.0",
+		"method": method,
+		"result": result,
+		"error": error,
+	})
+}
+
+fn notification(method: &str, params: Value) -> Value {
+	json!({
+		"id": "Stratum",
+		"jsonrpc": "2.0",
+		"method": method,
+		"params": params,
+	})
+}
+
+impl Proxy {
+	pub fn new(
+		algorithm: Algorithm,
+		upstream_tx: mpsc::Sender<types::ClientMessage>,
+		stats: Arc<RwLock<stats::Stats>>,
+	) -> Arc<Proxy> {
+		Arc::new(Proxy {
+			algorithm: algorithm,
+			upstream_tx: upstream_tx,
+			state: Mutex::new(State {
+				job: None,
+				epochs: vec![],
+				extranonce: vec![],
+				slots: (0..MAX_WORKERS).map(|_| None).collect(),
+				next_conn_id: 0,
+				pending: HashMap::new(),
+			}),
+			stats: stats,
+		})
+	}
+
+	/// Accepts miners on `addr` in the background
+	pub fn listen(proxy: Arc<Proxy>, addr: &str) -> io::Result<()> {
+		let listener = TcpListener::bind(addr)?;
+		info!(LOGGER, "Proxy accepting miners on {}", addr);
+		thread::Builder::new()
+			.name("proxy".to_string())
+			.spawn(move || {
+				for stream in listener.incoming() {
+					match stream {
+						Ok(s) => proxy.accept(s),
+						Err(e) => warn!(LOGGER, "Proxy can't accept connection: {:?}", e),
+					}
+				}
+			})?;
+		Ok(())
+	}
+
+	fn accept(self: &Arc<Self>, stream: TcpStream) {
+		let addr = stream
+			.peer_addr()
+			.map(|a| a.to_string())
+			.unwrap_or_default();
+		let (lines, lines_rx) = mpsc::channel();
+		let socket = stream
+			.set_write_timeout(Some(Duration::from_secs(WRITE_TIMEOUT_SECS)))
+			.and_then(|_| stream.try_clone())
+			.and_then(|socket| {
+				let writer = stream.try_clone()?;
+				let name = addr.clone();
+				thread::Builder::new()
+					.name("proxy_writer".to_string())
+					.spawn(move || Proxy::write_lines(writer, lines_rx, name))?;
+				Ok(socket)
+			});
+		let socket = match socket {
+			Ok(s) => s,
+			Err(e) => {
+				warn!(LOGGER, "Proxy: can't set up connection from {}: {:?}", addr, e);
+				return;
+			}
+		};
+		let (slot, conn_id, extranonce) = {
+			let mut state = self.state.lock().unwrap();
+			let slot = match state.slots.iter().position(|s| s.is_none()) {
+				Some(slot) => slot,
+				None => {
+					warn!(
+						LOGGER,
+						"Proxy: refusing {}, {} miners connected already", addr, MAX_WORKERS
+					);
+					let _ = stream.shutdown(Shutdown::Both);
+					return;
+				}
+			};
+			state.next_conn_id += 1;
+			let conn_id = state.next_conn_id;
+			state.slots[slot] = Some(Downstream {
+				id: conn_id,
+				name: addr.clone(),
+				lines: lines,
+				socket: socket,
+			});
+			let extranonce = worker_extranonce(&state.extranonce, slot);
+			let message = notification("set_extranonce", json!({ "extranonce": to_hex(extranonce.clone()) }));
+			Proxy::send(&state, slot, &message);
+			(slot, conn_id, extranonce)
+		};
+		self.worker(&addr, |w| {
+			w.addr = addr.clone();
+			w.connections += 1;
+			w.extranonce = to_hex(extranonce);
+		});
+		info!(LOGGER, "Proxy: miner {} connected", addr);
+
+		let proxy = self.clone();
+		let _ = thread::Builder::new()
+			.name("proxy_worker".to_string())
+			.spawn(move || {
+				proxy.serve(slot, conn_id, stream);
+				proxy.disconnect(slot, conn_id);
+			});
+	}
+
+	/// Frees the slot of a connection that ended
+	fn disconnect(&self, slot: usize, conn_id: u64) {
+		let name = {
+			let mut state = self.state.lock().unwrap();
+			match state.slots[slot].take() {
+				Some(ref d) if d.id == conn_id => d.name.clone(),
+				other => {
+					state.slots[slot] = other;
+					return;
+				}
+			}
+		};
+		info!(LOGGER, "Proxy: miner {} disconnected", name);
+		self.worker(&name, |w| w.connections = w.connections.saturating_sub(1));
+	}
+
+	/// Updates the stats of the worker called `name`, added if new
+	fn worker<F>(&self, name: &str, update: F)
+	where
+		F: FnOnce(&mut WorkerStats),
+	{
+		let mut stats = self.stats.write().unwrap();
+		let index = match stats.workers.iter().position(|w| w.name == name) {
+			Some(index) => index,
+			None => {
+				stats.workers.push(WorkerStats {
+					name: name.to_owned(),
+					..WorkerStats::default()
+				});
+				stats.workers.sort_by(|a, b| a.name.cmp(&b.name));
+				stats.workers.iter().position(|w| w.name == name).unwrap()
+			}
+		};
+		update(&mut stats.workers[index]);
+	}
+
+	/// Writes the lines queued for a miner until its connection is gone, a
+	/// miner that can't keep up is disconnected
+	fn write_lines(mut writer: TcpStream, lines: mpsc::Receiver<String>, name: String) {
+		for line in lines {
+			if let Err(e) = writeln!(writer, "{}", line) {
+				warn!(LOGGER, "Proxy: can't write to miner {}, disconnecting: {:?}", name, e);
+				let _ = writer.shutdown(Shutdown::Both);
+				return;
+			}
+		}
+	}
+
+	/// Queues a line for the miner in `slot`
+	fn send(state: &State, slot: usize, message: &Value) {
+		if let Some(ref d) = state.slots[slot] {
+			let _ = d.lines.send(message.to_string());
+		}
+	}
+
+	fn broadcast<F>(state: &State, message: F)
+	where
+		F: Fn(&State, usize) -> Value,
+	{
+		for slot in 0..MAX_WORKERS {
+			if state.slots[slot].is_some() {
+				Proxy::send(state, slot, &message(state, slot));
+			}
+		}
+	}
+
+	fn job_template(&self, state: &State) -> Option<types::JobTemplate> {
+		let algorithm = client::job_algorithm(self.algorithm);
+		state.job.as_ref().map(|job| types::JobTemplate {
+			height: job.height,
+			job_id: job.job_id,
+			difficulty: vec![(algorithm.clone(), job.difficulty)],
+			block_difficulty: vec![],
+			pre_pow: job.pre_pow.clone(),
+			epochs: state.epochs.clone(),
+			algorithm: algorithm.clone(),
+		})
+	}
+
+	/// Answers the requests of the miner in `slot` until it disconnects
+	fn serve(&self, slot: usize, conn_id: u64, stream: TcpStream) {
+		for line in BufReader::new(stream).lines() {
+			let line = match line {
+				Ok(line) => line,
+				Err(_) => return,
+			};
+			if line.trim().is_empty() {
+				continue;
+			}
+			let req = match serde_json::from_str::<types::RpcRequest>(&line) {
+				Ok(req) => req,
+				Err(e) => {
+					debug!(LOGGER, "Proxy: invalid request {}: {:?}", line, e);
+					continue;
+				}
+			};
+			let result = match req.method.as_str() {
+				"login" => self.login(slot, req.params),
+				"getjobtemplate" => {
+					let state = self.state.lock().unwrap();
+					match self.job_template(&state) {
+						Some(job) => serde_json::to_value(job).map_err(|e| (-32603, e.to_string())),
+						None => Err((-32000, "No job from the pool yet".to_owned())),
+					}
+				}
+				"keepalive" => Ok(json!("ok")),
+				"status" => self.status(slot),
+				"submit" => match self.submit(slot, conn_id, &req) {
+					// answered once the pool decided
+					Ok(()) => continue,
+					Err(e) => Err(e),
+				},
+				_ => Err((-32601, "Method not found".to_owned())),
+			};
+			let state = self.state.lock().unwrap();
+			Proxy::send(&state, slot, &response(&req.id, &req.method, result));
+		}
+	}
+
+	fn login(&self, slot: usize, params: Option<Value>) -> Result<Value, (i32, String)> {
+		let params = params
+			.and_then(|p| serde_json::from_value::<types::LoginParams>(p).ok())
+			.ok_or_else(|| (-32602, "Invalid login".to_owned()))?;
+		if params.login.is_empty() {
+			return Ok(json!("ok"));
+		}
+		let (old, extranonce) = {
+			let mut state = self.state.lock().unwrap();
+			let extranonce = worker_extranonce(&state.extranonce, slot);
+			match state.slots[slot] {
+				Some(ref mut d) => (
+					std::mem::replace(&mut d.name, params.login.clone()),
+					extranonce,
+				),
+				None => return Err((-32603, "Not connected".to_owned())),
+			}
+		};
+		if old != params.login {
+			// the connection counted under its address until now
+			self.worker(&old, |w| w.connections = w.connections.saturating_sub(1));
+			self.stats.write().unwrap().workers.retain(|w| {
+				w.name != old || w.connections > 0 || w.shares_submitted > 0
+			});
+			let addr = old.clone();
+			self.worker(&params.login, |w| {
+				w.addr = addr;
+				w.connections += 1;
+				w.extranonce = to_hex(extranonce);
+			});
+			info!(LOGGER, "Proxy: miner {} logged in as {}", old, params.login);
+		}
+		Ok(json!("ok"))
+	}
+
+	fn status(&self, slot: usize) -> Result<Value, (i32, String)> {
+		let (name, height, difficulty) = {
+			let state = self.state.lock().unwrap();
+			let name = state.slots[slot]
+				.as_ref()
+				.map(|d| d.name.clone())
+				.unwrap_or_default();
+			let (height, difficulty) = state
+				.job
+				.as_ref()
+				.map_or((0, 0), |j| (j.height, j.difficulty));
+			(name, height, difficulty)
+		};
+		let stats = self.stats.read().unwrap();
+		let w = stats
+			.workers
+			.iter()
+			.find(|w| w.name == name)
+			.cloned()
+			.unwrap_or_default();
+		serde_json::to_value(types::WorkerStatus {
+			id: name,
+			height: height,
+			difficulty: difficulty,
+			accepted: w.shares_accepted,
+			rejected: w.shares_rejected,
+			stale: w.shares_stale,
+		})
+		.map_err(|e| (-32603, e.to_string()))
+	}
+
+	/// Checks a share and passes it on to the pool, the answer to the miner
+	/// waits for the pool's
+	fn submit(&self, slot: usize, conn_id: u64, req: &types::RpcRequest) -> Result<(), (i32, String)> {
+		let params = req
+			.params
+			.clone()
+			.and_then(|p| serde_json::from_value::<types::SubmitParams>(p).ok())
+			.ok_or_else(|| (-32602, "Invalid submit".to_owned()))?;
+		let mut state = self.state.lock().unwrap();
+		let name = match state.slots[slot] {
+			Some(ref d) => d.name.clone(),
+			None => return Err((-32603, "Not connected".to_owned())),
+		};
+		let extranonce = worker_extranonce(&state.extranonce, slot);
+		let outcome = if !nonce_in_range(&extranonce, params.nonce) {
+			Some((-32502, "Nonce outside of the miner's range".to_owned()))
+		} else if state.job.as_ref().map(|j| j.height) != Some(params.height) {
+			Some((-32503, "Solution submitted too late".to_owned()))
+		} else {
+			None
+		};
+		let now = time::get_time().sec;
+		if let Some((code, message)) = outcome {
+			drop(state);
+			let stale = code == -32503;
+			self.worker(&name, |w| {
+				w.shares_submitted += 1;
+				w.last_share_time = now;
+				if stale {
+					w.shares_stale += 1;
+				} else {
+					w.shares_rejected += 1;
+				}
+			});
+			return Err((code, message));
+		}
+
+		state
+			.pending
+			.retain(|_, p| p.sent_at.elapsed() < Duration::from_secs(PENDING_SUBMIT_SECS));
+		state.pending.insert(
+			(params.height, params.nonce),
+			PendingSubmit {
+				slot: slot,
+				conn_id: conn_id,
+				request_id: req.id.clone(),
+				name: name.clone(),
+				sent_at: Instant::now(),
+			},
+		);
+		drop(state);
+		self.worker(&name, |w| {
+			w.shares_submitted += 1;
+			w.last_share_time = now;
+		});
+		let solution = Solution::new(params.job_id, params.nonce, params.pow);
+		self.upstream_tx
+			.send(types::ClientMessage::FoundSolution(params.height, solution))
+			.map_err(|_| (-32603, "Proxy is shutting down".to_owned()))
+	}
+
+	/// Hands the pool's answer to a share to the miner that found it
+	fn submit_result(&self, height: u64, nonce: u64, refused: Option<String>) {
+		let p = match self.state.lock().unwrap().pending.remove(&(height, nonce)) {
+			Some(p) => p,
+			None => return,
+		};
+		self.worker(&p.name, |w| match refused {
+			None => w.shares_accepted += 1,
+			Some(ref m) if m.contains("too late") => w.shares_stale += 1,
+			Some(_) => w.shares_rejected += 1,
+		});
+		let result = match refused {
+			None => Ok(json!("ok")),
+			Some(message) => Err((-32502, message)),
+		};
+		let state = self.state.lock().unwrap();
+		if state.slots[p.slot].as_ref().map(|d| d.id) == Some(p.conn_id) {
+			Proxy::send(&state, p.slot, &response(&p.request_id, "submit", result));
+		}
+	}
+
+	/// Plays the miner for the client controller, passing what the pool
+	/// sends on to the connected miners until shut down
+	pub fn run(&self, rx: mpsc::Receiver<types::MinerMessage>) {
+		while let Ok(message) = rx.recv() {
+			debug!(LOGGER, "Proxy received message: {:?}", message);
+			match message {
+				types::MinerMessage::ReceivedJob(height, job_id, difficulty, pre_pow) => {
+					{
+						let mut s = self.stats.write().unwrap();
+						s.mining_stats.block_height = height;
+						s.mining_stats.job_id = job_id;
+						s.mining_stats.target_difficulty = difficulty;
+					}
+					let mut state = self.state.lock().unwrap();
+					state.job = Some(Job {
+						height: height,
+						job_id: job_id,
+						difficulty: difficulty,
+						pre_pow: pre_pow,
+					});
+					let job = self.job_template(&state);
+					Proxy::broadcast(&state, |_, _| notification("job", json!(job)));
+				}
+				types::MinerMessage::ReceivedSeed(epochs) => {
+					let mut state = self.state.lock().unwrap();
+					if state.epochs == epochs {
+						continue;
+					}
+					state.epochs = epochs.clone();
+					let seed = response("Stratum", "seed", Ok(json!({ "epochs": epochs })));
+					Proxy::broadcast(&state, |_, _| seed.clone());
+				}
+				types::MinerMessage::SetExtranonce(extranonce) => {
+					if extranonce.len() >= 7 {
+						warn!(
+							LOGGER,
+							"Proxy: the pool's extranonce leaves no room for the miners' nonce bytes"
+						);
+					}
+					let mut state = self.state.lock().unwrap();
+					state.extranonce = extranonce;
+					Proxy::broadcast(&state, |state, slot| {
+						let extranonce = worker_extranonce(&state.extranonce, slot);
+						notification("set_extranonce", json!({ "extranonce": to_hex(extranonce) }))
+					});
+				}
+				types::MinerMessage::SubmitResult(height, nonce, refused) => {
+					self.submit_result(height, nonce, refused)
+				}
+				// the miners keep their job, their shares are queued by the
+				// client while the pool is away
+				types::MinerMessage::StopJob
+				| types::MinerMessage::Pause
+				| types::MinerMessage::Resume => {}
+				types::MinerMessage::Shutdown => break,
+			}
+		}
+		let state = self.state.lock().unwrap();
+		for d in state.slots.iter().flatten() {
+			let _ = d.socket.shutdown(Shutdown::Both);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use core::AlgorithmParams;
+	use std::io::BufReader;
+
+	fn read_message(reader: &mut BufReader<TcpStream>) -> Value {
+		let mut line = String::new();
+		reader.read_line(&mut line).unwrap();
+		serde_json::from_str(&line).unwrap()
+	}
+
+	fn request(writer: &mut TcpStream, id: u32, method: &str, params: Value) {
+		let req = json!({ "id": id.to_string(), "jsonrpc": "2.0", "method": method, "params": params });
+		writeln!(writer, "{}", req).unwrap();
+	}
+
+	fn connect(addr: &str) -> (TcpStream, BufReader<TcpStream>) {
+		let stream = TcpStream::connect(addr).unwrap();
+		stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		let reader = BufReader::new(stream.try_clone().unwrap());
+		(stream, reader)
+	}
+
+	#[test]
+	fn nonce_ranges() {
+		assert_eq!(worker_extranonce(&[8, 0], 3), vec![8, 0, 3]);
+		assert!(nonce_in_range(&[8, 0, 3], 0x0800_03ff_0000_0001));
+		assert!(!nonce_in_range(&[8, 0, 3], 0x0800_04ff_0000_0001));
+		assert!(nonce_in_range(&[], 42));
+	}
+
+	#[test]
+	fn proxy_two_workers() {
+		let (upstream_tx, upstream_rx) = mpsc::channel();
+		let (miner_tx, miner_rx) = mpsc::channel();
+		let stats = Arc::new(RwLock::new(stats::Stats::default()));
+		let proxy = Proxy::new(Algorithm::RandomX, upstream_tx, stats.clone());
+		let addr = TcpListener::bind("127.0.0.1:0")
+			.unwrap()
+			.local_addr()
+			.unwrap()
+			.to_string();
+		let addr = addr.as_str();
+		Proxy::listen(proxy.clone(), addr).unwrap();
+		let run_proxy = proxy.clone();
+		let proxy_thread = thread::spawn(move || run_proxy.run(miner_rx));
+		miner_tx.send(types::MinerMessage::SetExtranonce(vec![9])).unwrap();
+		while proxy.state.lock().unwrap().extranonce.is_empty() {
+			thread::sleep(Duration::from_millis(10));
+		}
+
+		let (mut w1, mut r1) = connect(addr);
+		assert_eq!(read_message(&mut r1)["params"]["extranonce"], "0900");
+		let (mut w2, mut r2) = connect(addr);
+		assert_eq!(read_message(&mut r2)["params"]["extranonce"], "0901");
+		request(&mut w1, 1, "login", json!({ "login": "rig1", "pass": "", "agent": "test" }));
+		assert_eq!(read_message(&mut r1)["result"], "ok");
+		request(&mut w1, 2, "getjobtemplate", json!({ "algorithm": "randomx" }));
+		assert_eq!(read_message(&mut r1)["error"]["code"], -32000);
+
+		// the pool's job goes to everybody
+		miner_tx
+			.send(types::MinerMessage::ReceivedSeed(vec![(0, 100, [1; 32])]))
+			.unwrap();
+		miner_tx
+			.send(types::MinerMessage::ReceivedJob(10, 3, 50, "00ff".to_owned()))
+			.unwrap();
+		for r in [&mut r1, &mut r2].iter_mut() {
+			assert_eq!(read_message(r)["method"], "seed");
+			let job = read_message(r);
+			assert_eq!(job["method"], "job");
+			assert_eq!(job["params"]["height"], 10);
+			assert_eq!(job["params"]["difficulty"][0][1], 50);
+			assert_eq!(job["params"]["epochs"][0][1], 100);
+		}
+
+		// shares go upstream and the answer back to the worker
+		let pow = AlgorithmParams::RandomX([0; 32]);
+		let submit = |height: u64, nonce: u64| {
+			json!({ "height": height, "job_id": 3, "nonce": nonce, "pow": pow })
+		};
+		request(&mut w1, 3, "submit", submit(10, 0x0900_1234_5678_9abc));
+		match upstream_rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+			types::ClientMessage::FoundSolution(height, s) => {
+				assert_eq!(height, 10);
+				assert_eq!(s.get_id(), 3);
+				assert_eq!(s.get_nonce(), 0x0900_1234_5678_9abc);
+			}
+			m => panic!("expected a solution, got {:?}", m),
+		}
+		miner_tx
+			.send(types::MinerMessage::SubmitResult(10, 0x0900_1234_5678_9abc, None))
+			.unwrap();
+		let answer = read_message(&mut r1);
+		assert_eq!(answer["id"], "3");
+		assert_eq!(answer["result"], "ok");
+
+		// the second worker's nonces start with its own byte
+		request(&mut w2, 1, "submit", submit(10, 0x0900_1234_5678_9abc));
+		assert_eq!(read_message(&mut r2)["error"]["code"], -32502);
+		request(&mut w2, 2, "submit", submit(9, 0x0901_1234_5678_9abc));
+		assert_eq!(read_message(&mut r2)["error"]["message"], "Solution submitted too late");
+		assert!(upstream_rx.try_recv().is_err());
+
+		let workers = stats.read().unwrap().workers.clone();
+		assert_eq!(workers.len(), 2);
+		let rig1 = workers.iter().find(|w| w.name == "rig1").unwrap();
+		assert_eq!(rig1.connections, 1);
+		assert_eq!(rig1.extranonce, "0900");
+		assert_eq!((rig1.shares_submitted, rig1.shares_accepted), (1, 1));
+		let other = workers.iter().find(|w| w.name != "rig1").unwrap();
+		assert_eq!(other.shares_submitted, 2);
+		assert_eq!((other.shares_rejected, other.shares_stale), (1, 1));
+
+		miner_tx.send(types::MinerMessage::Shutdown).unwrap();
+		proxy_thread.join().unwrap();
+	}
+}
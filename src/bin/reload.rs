@@ -0,0 +1,315 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reloads epic-miner.toml while mining, when the file changes, on SIGHUP
+//! or through the control API. Stratum and node settings and log levels are
+//! applied right away, anything touching the solvers only gets logged as
+//! needing a restart.
+//!
+//! `run_tui` is one of those too: the logger is set up once at startup, with
+//! stdout logging turned off while the TUI owns the terminal, so switching
+//! the TUI on or off would leave the two fighting over the screen.
+
+use config::{ConfigMembers, GlobalConfig};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use types;
+use util::{self, LOGGER};
+
+/// Where a reload requested through the API reports to
+pub type ReloadReply = mpsc::Sender<Result<Changes, String>>;
+
+/// Names of the settings that changed in a reload
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct Changes {
+	/// in effect already
+	pub applied: Vec<String>,
+	/// ignored until the miner is restarted
+	pub need_restart: Vec<String>,
+}
+
+/// Collects the names of the fields that differ between `$old` and `$new`
+macro_rules! changed_fields {
+	($out:expr, $old:expr, $new:expr, $($field:ident),+) => {
+		$(
+			if $old.$field != $new.$field {
+				$out.push(stringify!($field).to_owned());
+			}
+		)+
+	};
+}
+
+/// Sorts the differences between two configurations into what can be
+/// applied live and what needs a restart
+pub fn diff(old: &ConfigMembers, new: &ConfigMembers) -> Changes {
+	let mut changes = Changes::default();
+	let (o, n) = (&old.mining, &new.mining);
+	changed_fields!(
+		changes.applied,
+		o,
+		n,
+		stratum_server_addr,
+		stratum_server_login,
+		stratum_server_password,
+		stratum_server_tls_enabled,
+		stratum_tls,
+		stratum_protocol,
+		stratum_proxy,
+		stratum_backup_servers,
+		stratum_failover_retries,
+		stratum_stall_timeout,
+		stratum_primary_retry_interval,
+		stratum_request_timeout,
+		stratum_keepalive_interval,
+		stratum_idle_timeout,
+		stratum_retry_min,
+		stratum_retry_max,
+		stratum_share_queue_size,
+		stratum_capture_file,
+		node_api_secret,
+		node_poll_interval,
+		config_reload_interval
+	);
+	changed_fields!(
+		changes.need_restart,
+		o,
+		n,
+		node_api_url,
+		proxy_listen_addr,
+		algorithm,
+		algorithms,
+		follow_algorithm,
+		randomx_config,
+		run_tui,
+		api_listen_addr,
+		api_token,
+		miner_plugin_dir,
+		miner_plugin_config,
+		gpu_config
+	);
+
+	let default_logging = util::LoggingConfig::default();
+	let ol = old.logging.as_ref().unwrap_or(&default_logging);
+	let nl = new.logging.as_ref().unwrap_or(&default_logging);
+	changed_fields!(changes.applied, ol, nl, stdout_log_level, file_log_level);
+	changed_fields!(
+		changes.need_restart,
+		ol,
+		nl,
+		log_to_stdout,
+		log_to_file,
+		log_file_path,
+		log_file_append
+	);
+	changes
+}
+
+/// Set by the SIGHUP handler, picked up by the watcher thread
+static SIGHUP_RECEIVED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_sighup(_: ::libc::c_int) {
+	SIGHUP_RECEIVED.store(true, Ordering::Relaxed);
+}
+
+#[cfg(unix)]
+fn listen_for_sighup() {
+	unsafe {
+		::libc::signal(::libc::SIGHUP, on_sighup as ::libc::sighandler_t);
+	}
+}
+
+#[cfg(not(unix))]
+fn listen_for_sighup() {}
+
+pub struct Watcher {
+	path: PathBuf,
+	current: ConfigMembers,
+	modified: Option<SystemTime>,
+	rx: mpsc::Receiver<ReloadReply>,
+	tx: mpsc::Sender<ReloadReply>,
+}
+
+impl Watcher {
+	pub fn new(path: PathBuf, current: ConfigMembers) -> Watcher {
+		let (tx, rx) = mpsc::channel();
+		Watcher {
+			modified: modified(&path),
+			path: path,
+			current: current,
+			rx: rx,
+			tx: tx,
+		}
+	}
+
+	/// Requests sent here trigger a reload and get its outcome back
+	pub fn sender(&self) -> mpsc::Sender<ReloadReply> {
+		self.tx.clone()
+	}
+
+	/// Watches the config file in the background, stratum changes are
+	/// passed on to the clients through `client_txs`
+	pub fn start(mut self, client_txs: Vec<mpsc::Sender<types::ClientMessage>>) {
+		listen_for_sighup();
+		let _ = thread::Builder::new()
+			.name("config_watcher".to_string())
+			.spawn(move || {
+				let mut next_check = Instant::now();
+				loop {
+					let reply = match self.rx.recv_timeout(Duration::from_secs(1)) {
+						Ok(reply) => Some(reply),
+						Err(mpsc::RecvTimeoutError::Timeout) => None,
+						Err(mpsc::RecvTimeoutError::Disconnected) => return,
+					};
+					let interval = self.current.mining.config_reload_interval;
+					let file_changed = interval > 0 && Instant::now() >= next_check && {
+						next_check = Instant::now() + Duration::from_secs(interval);
+						modified(&self.path) != self.modified
+					};
+					let sighup = SIGHUP_RECEIVED.swap(false, Ordering::Relaxed);
+					if reply.is_none() && !file_changed && !sighup {
+						continue;
+					}
+					let result = self.reload(&client_txs);
+					if let Err(ref e) = result {
+						error!(LOGGER, "Can't reload {}: {}", self.path.display(), e);
+					}
+					if let Some(r) = reply {
+						let _ = r.send(result);
+					}
+				}
+			});
+	}
+
+	fn reload(
+		&mut self,
+		client_txs: &[mpsc::Sender<types::ClientMessage>],
+	) -> Result<Changes, String> {
+		// a half written file is picked up again with the next save
+		self.modified = modified(&self.path);
+		let path = self.path.to_string_lossy().into_owned();
+		let new = GlobalConfig::new(Some(&path))
+			.map_err(|e| format!("{}", e))?
+			.members
+			.ok_or_else(|| "no configuration in file".to_owned())?;
+		let changes = diff(&self.current, &new);
+		if changes.applied.is_empty() && changes.need_restart.is_empty() {
+			debug!(LOGGER, "Reloaded {}, nothing changed", path);
+			return Ok(changes);
+		}
+
+		if changes
+			.applied
+			.iter()
+			.any(|c| c.starts_with("stratum_") || c.starts_with("node_"))
+		{
+			// the running clients get it even when another one stopped
+			let stopped = client_txs
+				.iter()
+				.enumerate()
+				.filter(|&(_, tx)| {
+					tx.send(types::ClientMessage::Reconfigure(new.mining.clone()))
+						.is_err()
+				})
+				.map(|(i, _)| i.to_string())
+				.collect::<Vec<_>>();
+			if !stopped.is_empty() {
+				return Err(format!("stratum clients stopped: {}", stopped.join(", ")));
+			}
+		}
+		if let Some(ref l) = new.logging {
+			util::set_log_levels(&l.stdout_log_level, &l.file_log_level);
+		}
+		for c in &changes.applied {
+			info!(LOGGER, "Reloaded {}: applied new {}", path, c);
+		}
+		for c in &changes.need_restart {
+			warn!(
+				LOGGER,
+				"Reloaded {}: {} changed, restart the miner to apply it", path, c
+			);
+		}
+		self.current = new;
+		Ok(changes)
+	}
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+	fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use core::Algorithm;
+	use util::LogLevel;
+
+	#[test]
+	fn sort_changes() {
+		let old = ConfigMembers::default();
+		let mut new = ConfigMembers::default();
+		assert_eq!(diff(&old, &new), Changes::default());
+
+		new.mining.stratum_server_login = Some("worker2".to_owned());
+		new.mining.stratum_retry_max = 10;
+		new.mining.algorithm = Some(Algorithm::Cuckoo);
+		new.mining.randomx_config.threads = 8;
+		new.logging.as_mut().unwrap().stdout_log_level = LogLevel::Warning;
+		new.logging.as_mut().unwrap().log_file_path = "other.log".to_owned();
+		let changes = diff(&old, &new);
+		assert_eq!(
+			changes.applied,
+			vec![
+				"stratum_server_login",
+				"stratum_retry_max",
+				"stdout_log_level"
+			]
+		);
+		assert_eq!(
+			changes.need_restart,
+			vec!["algorithm", "randomx_config", "log_file_path"]
+		);
+	}
+
+	#[test]
+	fn reconfigure_every_client() {
+		let path =
+			std::env::temp_dir().join(format!("epic-miner-reload-{}.toml", std::process::id()));
+		let template = concat!(env!("CARGO_MANIFEST_DIR"), "/epic-miner.toml");
+		let current = GlobalConfig::new(Some(template)).unwrap().members.unwrap();
+		let edited = fs::read_to_string(template).unwrap().replace(
+			"#stratum_server_login = \"http://192.168.1.100:3415\"",
+			"stratum_server_login = \"worker2\"",
+		);
+		fs::write(&path, edited).unwrap();
+		let mut watcher = Watcher::new(path.clone(), current);
+
+		let (stopped_tx, _) = mpsc::channel();
+		let (tx, rx) = mpsc::channel();
+		let (other_stopped_tx, _) = mpsc::channel();
+		let result = watcher.reload(&[stopped_tx, tx, other_stopped_tx]);
+		fs::remove_file(&path).unwrap();
+		assert_eq!(result, Err("stratum clients stopped: 0, 2".to_owned()));
+		match rx.try_recv() {
+			Ok(types::ClientMessage::Reconfigure(c)) => {
+				assert_eq!(c.stratum_server_login, Some("worker2".to_owned()))
+			}
+			_ => panic!("running client not reconfigured"),
+		}
+	}
+}
@@ -0,0 +1,564 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Solo mining against an Epic node's HTTP API, used instead of the
+//! stratum client when `node_api_url` is set. Block templates are polled
+//! from the node and solved blocks submitted back, as JSON-RPC requests
+//! with the methods and params the node's stratum server takes.
+//!
+//! Released Epic nodes don't serve this yet: their HTTP APIs
+//! (`/v2/foreign`, `/v2/owner`) have no way to get a block template or
+//! submit a block, mining only goes through the node's stratum server.
+//! Solo mode needs the node to expose `getjobtemplate` and `submit` as a
+//! JSON-RPC endpoint (e.g. `/v2/mining`), a node-side change. Against a
+//! node without it the miner says so and keeps retrying.
+
+use client::{algorithm_name, job_algorithm, Error};
+use core::config::MinerConfig;
+use core::{Algorithm, Solution};
+use openssl::base64;
+use serde_json::{self, Value};
+use stats;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{mpsc, Arc, RwLock};
+use std::time::{Duration, Instant};
+use types;
+use util::LOGGER;
+
+/// User name the node API expects along with its secret
+const API_USER: &str = "epic";
+
+/// Responses larger than this are refused
+const MAX_RESPONSE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// JSON-RPC error code for a method the node doesn't know
+const METHOD_NOT_FOUND: i32 = -32601;
+
+/// What's logged when the node has no mining API
+const NO_MINING_API: &str = "the node doesn't serve block templates, solo mining needs \
+	an Epic node with a mining API (getjobtemplate and submit over JSON-RPC)";
+
+pub struct Controller {
+	algorithm: Algorithm,
+	url: String,
+	secret: Option<String>,
+	poll_interval: Duration,
+	request_timeout: Duration,
+	rx: mpsc::Receiver<types::ClientMessage>,
+	pub tx: mpsc::Sender<types::ClientMessage>,
+	miner_tx: mpsc::Sender<types::MinerMessage>,
+	/// height and header of the template being mined
+	current: Option<(u64, String)>,
+	last_request_id: u32,
+	stats: Arc<RwLock<stats::Stats>>,
+}
+
+impl Controller {
+	pub fn new(
+		algorithm: Algorithm,
+		config: &MinerConfig,
+		miner_tx: mpsc::Sender<types::MinerMessage>,
+		stats: Arc<RwLock<stats::Stats>>,
+	) -> Result<Controller, Error> {
+		let url = config
+			.node_api_url
+			.clone()
+			.ok_or_else(|| Error::GeneralError("no node_api_url configured".to_owned()))?;
+		let (tx, rx) = mpsc::channel::<types::ClientMessage>();
+		{
+			let mut stats = stats.write()?;
+			stats.client_stats.server_url = url.clone();
+			stats.client_stats.my_algorithm = algorithm_name(algorithm);
+		}
+		Ok(Controller {
+			algorithm: algorithm,
+			url: url,
+			secret: config.node_api_secret.clone(),
+			poll_interval: Duration::from_secs(config.node_poll_interval.max(1)),
+			request_timeout: Duration::from_secs(config.stratum_request_timeout),
+			rx: rx,
+			tx: tx,
+			miner_tx: miner_tx,
+			current: None,
+			last_request_id: 0,
+			stats: stats,
+		})
+	}
+
+	/// Sends a JSON-RPC request to the node. The outer error is about
+	/// reaching the node, the inner one is the node's answer.
+	fn request(
+		&mut self,
+		method: &str,
+		params: Value,
+	) -> Result<Result<Value, types::RpcError>, Error> {
+		self.last_request_id = self.last_request_id.wrapping_add(1);
+		let req = types::RpcRequest {
+			id: self.last_request_id.to_string(),
+			jsonrpc: "2.0".to_string(),
+			method: method.to_string(),
+			params: Some(params),
+		};
+		let sent_at = Instant::now();
+		let body = post(
+			&self.url,
+			self.secret.as_deref(),
+			&serde_json::to_string(&req)?,
+			self.request_timeout,
+		)?;
+		self.stats
+			.write()?
+			.client_stats
+			.add_rtt(sent_at.elapsed().as_millis() as f64);
+
+		// the node's ids don't have to be strings, only result and error
+		// matter
+		let res: Value = serde_json::from_str(&body)?;
+		if !res["error"].is_null() {
+			return Ok(Err(serde_json::from_value(res["error"].clone()).unwrap_or(
+				types::RpcError {
+					code: 0,
+					message: res["error"].to_string(),
+				},
+			)));
+		}
+		Ok(Ok(res["result"].clone()))
+	}
+
+	/// Asks the node for a block template, the miner gets it if it's a
+	/// new one
+	fn poll(&mut self) -> Result<(), Error> {
+		let params = serde_json::to_value(types::JobParams {
+			algorithm: job_algorithm(self.algorithm),
+		})?;
+		let job = match self.request("getjobtemplate", params)? {
+			Ok(result) => serde_json::from_value::<types::JobTemplate>(result)?,
+			Err(ref e) if e.code == METHOD_NOT_FOUND => {
+				return Err(Error::ResponseError(NO_MINING_API.to_owned()))
+			}
+			Err(e) => {
+				return Err(Error::ResponseError(format!(
+					"Failed to get a block template: {}",
+					e.message
+				)))
+			}
+		};
+		self.set_connected(
+			true,
+			format!("Solo mining against Epic node at {}", self.url),
+		);
+
+		if self.current.as_ref() == Some(&(job.height, job.pre_pow.clone())) {
+			return Ok(());
+		}
+		// solo, every solution is a block, so the target is the network
+		// difficulty
+		let mine = job_algorithm(self.algorithm);
+		let difficulty = job
+			.block_difficulty
+			.iter()
+			.find(|d| d.0 == mine)
+			.map(|d| d.1)
+			.unwrap_or(1);
+		{
+			let mut stats = self.stats.write()?;
+			stats.client_stats.last_message_received = format!(
+				"Last Message Received: Block template for height {} at difficulty {}",
+				job.height, difficulty
+			);
+			stats.client_stats.algorithm_needed = job.algorithm.clone();
+			stats.client_stats.current_network_difficulty = format!("{}", difficulty);
+		}
+		if job.algorithm != mine {
+			info!(
+				LOGGER,
+				"Node wants {} at height {}, waiting for a {} block",
+				job.algorithm,
+				job.height,
+				mine
+			);
+			self.current = Some((job.height, job.pre_pow));
+			self.miner_tx.send(types::MinerMessage::StopJob)?;
+			return Ok(());
+		}
+		info!(
+			LOGGER,
+			"New block template at height {}, difficulty {}", job.height, difficulty
+		);
+		self.miner_tx
+			.send(types::MinerMessage::ReceivedSeed(job.epochs.clone()))?;
+		self.miner_tx.send(types::MinerMessage::ReceivedJob(
+			job.height,
+			job.job_id,
+			difficulty,
+			job.pre_pow.clone(),
+		))?;
+		self.current = Some((job.height, job.pre_pow));
+		Ok(())
+	}
+
+	/// Hands a solved block to the node
+	fn submit(&mut self, height: u64, solution: Solution) -> Result<(), Error> {
+		let params = serde_json::to_value(types::SubmitParams {
+			height: height,
+			job_id: solution.get_id(),
+			nonce: solution.get_nonce(),
+			pow: solution.get_algorithm_params(),
+		})?;
+		self.stats.write()?.client_stats.last_message_sent = format!(
+			"Last Message Sent: Found block for height: {} - nonce: {}",
+			height,
+			solution.get_nonce()
+		);
+		let result = self.request("submit", params)?;
+		let mut stats = self.stats.write()?;
+		match result {
+			Ok(_) => {
+				info!(
+					LOGGER,
+					"Block Found!! height {}, nonce {}",
+					height,
+					solution.get_nonce()
+				);
+				stats.client_stats.last_message_received =
+					"Last Message Received: Block Found!!".to_owned();
+				stats.mining_stats.solution_stats.num_shares_accepted += 1;
+				stats.mining_stats.solution_stats.num_blocks_found += 1;
+			}
+			Err(e) => {
+				error!(
+					LOGGER,
+					"Node refused block for height {}, nonce {}: {}",
+					height,
+					solution.get_nonce(),
+					e.message
+				);
+				stats.client_stats.last_message_received = format!(
+					"Last Message Received: Failed to submit a block: {:?}",
+					e.message
+				);
+				if e.message.contains("too late") {
+					stats.mining_stats.solution_stats.num_staled += 1;
+				} else {
+					stats.mining_stats.solution_stats.num_rejected += 1;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn set_connected(&mut self, connected: bool, status: String) {
+		let mut stats = self.stats.write().unwrap();
+		stats.client_stats.connected = connected;
+		stats.client_stats.connection_status = format!("Connection Status: {}", status);
+	}
+
+	fn reconfigure(&mut self, config: &MinerConfig) {
+		if let Some(ref url) = config.node_api_url {
+			self.url = url.clone();
+		}
+		self.secret = config.node_api_secret.clone();
+		self.poll_interval = Duration::from_secs(config.node_poll_interval.max(1));
+		self.request_timeout = Duration::from_secs(config.stratum_request_timeout);
+	}
+
+	pub fn run(mut self) {
+		let mut next_poll = Instant::now();
+		let mut reachable = true;
+		loop {
+			if Instant::now() >= next_poll {
+				match self.poll() {
+					Ok(()) => reachable = true,
+					Err(e) => {
+						// a node that's down would log every poll
+						if reachable {
+							warn!(
+								LOGGER,
+								"Can't get a block template from {}: {:?}", self.url, e
+							);
+							// the template is likely outdated by the time
+							// the node is back
+							self.current = None;
+							let _ = self.miner_tx.send(types::MinerMessage::StopJob);
+						}
+						reachable = false;
+						let status = format!("Can't reach Epic node at {}", self.url);
+						self.set_connected(false, status);
+					}
+				}
+				next_poll = Instant::now() + self.poll_interval;
+			}
+
+			let wait = next_poll.saturating_duration_since(Instant::now());
+			match self.rx.recv_timeout(wait) {
+				Ok(types::ClientMessage::FoundSolution(height, solution)) => {
+					if let Err(e) = self.submit(height, solution) {
+						error!(LOGGER, "Can't submit block to {}: {:?}", self.url, e);
+					}
+					// after a block there's a new height to mine
+					next_poll = Instant::now();
+				}
+				Ok(types::ClientMessage::ChangeServer(_, reply)) => {
+					let _ = reply.send(Err(
+						"solo mining against a node, there's no stratum server to change"
+							.to_owned(),
+					));
+				}
+				Ok(types::ClientMessage::Reconfigure(config)) => {
+					info!(LOGGER, "Applying reloaded node settings");
+					self.reconfigure(&config);
+					next_poll = Instant::now();
+				}
+				Ok(types::ClientMessage::Shutdown) => {
+					debug!(LOGGER, "Shutting down solo client controller");
+					let _ = self.miner_tx.send(types::MinerMessage::Shutdown);
+					return;
+				}
+				Ok(types::ClientMessage::ReceivedLine(..))
+				| Ok(types::ClientMessage::ConnectionLost(..))
+				| Ok(types::ClientMessage::PrimaryProbed(..)) => {}
+				Err(mpsc::RecvTimeoutError::Timeout) => {}
+				Err(mpsc::RecvTimeoutError::Disconnected) => return,
+			}
+		}
+	}
+}
+
+/// Resolves the `host[:port]` of a node URL, port 80 when there's none
+fn node_addr(host: &str) -> Result<SocketAddr, Error> {
+	// an IPv6 address has colons of its own, in brackets
+	let has_port = host
+		.rfind(':')
+		.map_or(false, |i| i > host.rfind(']').unwrap_or(0));
+	let resolved = if has_port {
+		host.to_socket_addrs()
+	} else {
+		(host.trim_start_matches('[').trim_end_matches(']'), 80).to_socket_addrs()
+	};
+	resolved
+		.ok()
+		.and_then(|mut a| a.next())
+		.ok_or_else(|| Error::ConnectionError(format!("can't resolve {}", host)))
+}
+
+/// POSTs `body` to a plain http:// `url` and returns the response body.
+/// The request is HTTP/1.0 so the node answers with a plain body and closes
+/// the connection, there's no chunked encoding to undo.
+fn post(url: &str, secret: Option<&str>, body: &str, timeout: Duration) -> Result<String, Error> {
+	let rest = match url.strip_prefix("http://") {
+		Some(rest) => rest,
+		None => {
+			return Err(Error::ConnectionError(format!(
+				"only http:// node URLs are supported, not {}",
+				url
+			)))
+		}
+	};
+	let (host, path) = match rest.find('/') {
+		Some(i) => (&rest[..i], &rest[i..]),
+		None => (rest, "/"),
+	};
+	let addr = node_addr(host)?;
+	let connection_error = |e: ::std::io::Error| Error::ConnectionError(format!("{}: {}", url, e));
+	let mut stream = TcpStream::connect_timeout(&addr, timeout).map_err(connection_error)?;
+	stream
+		.set_read_timeout(Some(timeout))
+		.map_err(connection_error)?;
+	stream
+		.set_write_timeout(Some(timeout))
+		.map_err(connection_error)?;
+
+	let auth = match secret {
+		Some(s) => format!(
+			"Authorization: Basic {}\r\n",
+			base64::encode_block(format!("{}:{}", API_USER, s).as_bytes())
+		),
+		None => String::new(),
+	};
+	write!(
+		stream,
+		"POST {} HTTP/1.0\r\nHost: {}\r\n{}Content-Type: application/json\r\n\
+		 Content-Length: {}\r\n\r\n{}",
+		path,
+		host,
+		auth,
+		body.len(),
+		body
+	)
+	.map_err(connection_error)?;
+
+	let mut response = vec![];
+	stream
+		.take(MAX_RESPONSE_SIZE)
+		.read_to_end(&mut response)
+		.map_err(connection_error)?;
+	let response = String::from_utf8_lossy(&response);
+	let (head, body) = match response.find("\r\n\r\n") {
+		Some(i) => (&response[..i], &response[i + 4..]),
+		None => return Err(Error::ResponseError("incomplete HTTP response".to_owned())),
+	};
+	let status = head.lines().next().unwrap_or("");
+	match status.split_whitespace().nth(1) {
+		Some("200") => Ok(body.to_owned()),
+		Some("404") => Err(Error::ResponseError(NO_MINING_API.to_owned())),
+		_ => Err(Error::ResponseError(format!("node answered {}", status))),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use api::read_request;
+	use core::AlgorithmParams;
+	use std::io::BufReader;
+	use std::net::TcpListener;
+	use std::thread;
+
+	/// Stands in for an Epic node with the mining API solo mode needs,
+	/// answers template requests with a job for `height` and records the
+	/// blocks submitted to it
+	fn stand_in_node(height: u64) -> (String, mpsc::Receiver<types::SubmitParams>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/v2/mining", listener.local_addr().unwrap());
+		let (tx, rx) = mpsc::channel();
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				let mut stream = stream.unwrap();
+				let req = read_request(&mut BufReader::new(&stream)).unwrap();
+				let authorized = req.headers.get("authorization").map(|a| a.as_str())
+					== Some("Basic ZXBpYzpzZWNyZXQ=");
+				if !authorized {
+					let _ = stream.write_all(b"HTTP/1.0 401 Unauthorized\r\n\r\n");
+					continue;
+				}
+				// a released node has no mining API
+				if req.path != "/v2/mining" {
+					let _ = stream.write_all(b"HTTP/1.0 404 Not Found\r\n\r\n");
+					continue;
+				}
+				let rpc: types::RpcRequest = serde_json::from_slice(&req.body).unwrap();
+				let result = match rpc.method.as_str() {
+					"getjobtemplate" => json!({
+						"height": height,
+						"job_id": 7,
+						"difficulty": [["randomx", 1]],
+						"block_difficulty": [["randomx", 5000]],
+						"pre_pow": "00ff",
+						"epochs": [[0, 100, vec![1u8; 32]]],
+						"algorithm": rpc.params.unwrap()["algorithm"],
+					}),
+					"submit" => {
+						tx.send(serde_json::from_value(rpc.params.unwrap()).unwrap())
+							.unwrap();
+						json!("ok")
+					}
+					_ => Value::Null,
+				};
+				let body = json!({"id": 1, "jsonrpc": "2.0", "result": result}).to_string();
+				let _ = write!(
+					stream,
+					"HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n{}",
+					body
+				);
+			}
+		});
+		(url, rx)
+	}
+
+	#[test]
+	fn solo_mining() {
+		let (url, submitted) = stand_in_node(42);
+		let mut config = MinerConfig::default();
+		config.node_api_url = Some(url);
+		config.node_api_secret = Some("secret".to_owned());
+		let stats = Arc::new(RwLock::new(stats::Stats::default()));
+		let (miner_tx, miner_rx) = mpsc::channel();
+		let cc = Controller::new(Algorithm::RandomX, &config, miner_tx, stats.clone()).unwrap();
+		let client_tx = cc.tx.clone();
+		thread::spawn(move || cc.run());
+
+		let timeout = Duration::from_secs(5);
+		match miner_rx.recv_timeout(timeout).unwrap() {
+			types::MinerMessage::ReceivedSeed(epochs) => assert_eq!(epochs[0].1, 100),
+			m => panic!("expected the seed, got {:?}", m),
+		}
+		match miner_rx.recv_timeout(timeout).unwrap() {
+			types::MinerMessage::ReceivedJob(height, job_id, diff, pre_pow) => {
+				assert_eq!((height, job_id, diff), (42, 7, 5000));
+				assert_eq!(pre_pow, "00ff");
+			}
+			m => panic!("expected a job, got {:?}", m),
+		}
+		assert!(stats.read().unwrap().client_stats.connected);
+
+		let solution = Solution::new(7, 1234, AlgorithmParams::RandomX([2; 32]));
+		client_tx
+			.send(types::ClientMessage::FoundSolution(42, solution))
+			.unwrap();
+		let block = submitted.recv_timeout(timeout).unwrap();
+		assert_eq!((block.height, block.job_id, block.nonce), (42, 7, 1234));
+
+		client_tx.send(types::ClientMessage::Shutdown).unwrap();
+		while let Ok(m) = miner_rx.recv_timeout(timeout) {
+			if let types::MinerMessage::Shutdown = m {
+				break;
+			}
+		}
+		let solution_stats = stats.read().unwrap().mining_stats.solution_stats.clone();
+		assert_eq!(solution_stats.num_blocks_found, 1);
+	}
+
+	#[test]
+	fn node_without_mining_api() {
+		let (url, _submitted) = stand_in_node(42);
+		let mut config = MinerConfig::default();
+		config.node_api_url = Some(url.replace("/v2/mining", "/v2/foreign"));
+		config.node_api_secret = Some("secret".to_owned());
+		let stats = Arc::new(RwLock::new(stats::Stats::default()));
+		let (miner_tx, _miner_rx) = mpsc::channel();
+		let mut cc = Controller::new(Algorithm::RandomX, &config, miner_tx, stats).unwrap();
+		match cc.poll() {
+			Err(Error::ResponseError(msg)) => assert_eq!(msg, NO_MINING_API),
+			r => panic!("expected the missing mining API, got {:?}", r),
+		}
+	}
+
+	#[test]
+	fn node_url_without_port() {
+		assert_eq!(
+			node_addr("127.0.0.1").unwrap(),
+			"127.0.0.1:80".parse().unwrap()
+		);
+		assert_eq!(node_addr("[::1]").unwrap(), "[::1]:80".parse().unwrap());
+		assert_eq!(
+			node_addr("127.0.0.1:3415").unwrap(),
+			"127.0.0.1:3415".parse().unwrap()
+		);
+		assert_eq!(
+			node_addr("[::1]:3415").unwrap(),
+			"[::1]:3415".parse().unwrap()
+		);
+
+		let mut config = MinerConfig::default();
+		config.node_api_url = Some("http://127.0.0.1/v2/mining".to_owned());
+		let stats = Arc::new(RwLock::new(stats::Stats::default()));
+		let (miner_tx, _miner_rx) = mpsc::channel();
+		let mut cc = Controller::new(Algorithm::RandomX, &config, miner_tx, stats).unwrap();
+		// whether or not a node listens on port 80, the URL is usable
+		if let Err(Error::ConnectionError(msg)) = cc.poll() {
+			assert!(!msg.starts_with("can't resolve"), "{}", msg);
+		}
+	}
+}
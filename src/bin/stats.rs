@@ -15,12 +15,14 @@
 //! Miner stats collection types, to be used by tests, logging or GUI/TUI
 //! to collect information about mining status
 
+use core::DatasetStatus;
 use core::Stats as CrStats;
+use std::collections::BTreeMap;
 /// Struct to return relevant information about the mining process
 /// back to interested callers (such as the TUI)
 use plugin;
 
-#[derive(Clone)]
+#[derive(Clone, Serialize)]
 pub struct SolutionStats {
 	/// total solutions found
 	pub num_solutions_found: u32,
@@ -32,6 +34,8 @@ pub struct SolutionStats {
 	pub num_staled: u32,
 	/// total blocks found
 	pub num_blocks_found: u32,
+	/// solutions that failed verification and were never submitted
+	pub num_hw_errors: u32,
 }
 
 impl Default for SolutionStats {
@@ -42,6 +46,7 @@ impl Default for SolutionStats {
 			num_rejected: 0,
 			num_staled: 0,
 			num_blocks_found: 0,
+			num_hw_errors: 0,
 		}
 	}
 }
@@ -52,12 +57,18 @@ pub struct MiningStats {
 	combined_gps: Vec<f64>,
 	/// what block height we're mining at
 	pub block_height: u64,
+	/// id of the job we're mining on
+	pub job_id: u64,
+	/// whether mining was paused on request
+	pub paused: bool,
 	/// current target for share difficulty we're working on
 	pub target_difficulty: u64,
 	/// solution statistics
 	pub solution_stats: SolutionStats,
 	/// Individual device status from Cuckoo-Miner
 	pub device_stats: Vec<CrStats>,
+	/// RandomX dataset load state per epoch
+	pub datasets: Vec<DatasetStatus>,
 }
 
 impl Default for MiningStats {
@@ -65,9 +76,12 @@ impl Default for MiningStats {
 		MiningStats {
 			combined_gps: vec![],
 			block_height: 0,
+			job_id: 0,
+			paused: false,
 			target_difficulty: 0,
 			solution_stats: SolutionStats::default(),
 			device_stats: vec![],
+			datasets: vec![],
 		}
 	}
 }
@@ -86,12 +100,22 @@ impl MiningStats {
 			sum / (self.combined_gps.len() as f64)
 		}
 	}
+
+	/// recent combined graphs per second, newest first
+	pub fn combined_gps_history(&self) -> &[f64] {
+		&self.combined_gps
+	}
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize)]
 pub struct ClientStats {
 	/// Server we're connected to
 	pub server_url: String,
+	/// Index of the active server in the configured server list,
+	/// 0 being the primary server
+	pub active_server: usize,
+	/// Why the client last switched to another server
+	pub server_switch_reason: String,
 	/// whether we're connected
 	pub connected: bool,
 	/// Connection status
@@ -104,32 +128,103 @@ pub struct ClientStats {
 	pub my_algorithm: String,
 	/// Algorithm that the server needs to be mined
 	pub algorithm_needed: String,
+	/// Seconds spent mining each algorithm when following the algorithm
+	/// of the pool's jobs
+	pub algorithm_seconds: BTreeMap<String, f64>,
 	/// The current network difficulty
 	pub current_network_difficulty: String,
-	
+	/// Time from receiving the last job notification until it was handed
+	/// to the miner, in milliseconds
+	pub last_job_latency: f64,
+	/// Average job notification latency in milliseconds
+	pub avg_job_latency: f64,
+	/// Number of jobs the latency average is based on
+	pub num_jobs_received: u64,
+	/// Round trip time of the last answered request in milliseconds
+	pub last_rtt: f64,
+	/// Average request round trip time in milliseconds
+	pub avg_rtt: f64,
+	/// Number of answered requests the round trip average is based on
+	pub num_responses: u64,
+	/// Requests the server never answered
+	pub num_timed_out: u64,
+	/// Shares waiting for the connection to come back
+	pub queued_shares: usize,
+	/// Queued shares dropped because they went stale or didn't fit
+	pub num_dropped_shares: u64,
+}
+
+impl ClientStats {
+	/// Records the latency of a job notification
+	pub fn add_job_latency(&mut self, ms: f64) {
+		self.num_jobs_received += 1;
+		self.last_job_latency = ms;
+		self.avg_job_latency += (ms - self.avg_job_latency) / self.num_jobs_received as f64;
+	}
+
+	/// Records the round trip time of an answered request
+	pub fn add_rtt(&mut self, ms: f64) {
+		self.num_responses += 1;
+		self.last_rtt = ms;
+		self.avg_rtt += (ms - self.avg_rtt) / self.num_responses as f64;
+	}
 }
 
 impl Default for ClientStats {
 	fn default() -> ClientStats {
 		ClientStats {
 			server_url: "".to_string(),
+			active_server: 0,
+			server_switch_reason: "".to_string(),
 			connected: false,
 			connection_status: "Connection Status: Starting".to_string(),
 			last_message_sent: "Last Message Sent: None".to_string(),
 			last_message_received: "Last Message Received: None".to_string(),
 			my_algorithm: "".to_string(),
 			algorithm_needed: "".to_string(),
+			algorithm_seconds: BTreeMap::new(),
 			current_network_difficulty: "".to_string(),
+			last_job_latency: 0.0,
+			avg_job_latency: 0.0,
+			num_jobs_received: 0,
+			last_rtt: 0.0,
+			avg_rtt: 0.0,
+			num_responses: 0,
+			num_timed_out: 0,
+			queued_shares: 0,
+			num_dropped_shares: 0,
 		}
 	}
 }
 
+/// A miner connected to `epic-miner proxy`, connections with the same
+/// login count as one worker
+#[derive(Clone, Default, Serialize)]
+pub struct WorkerStats {
+	/// login of the worker, its address if it didn't log in
+	pub name: String,
+	/// address of the last connection
+	pub addr: String,
+	/// connections open right now
+	pub connections: u32,
+	/// leading nonce bytes of the last connection, hex
+	pub extranonce: String,
+	pub shares_submitted: u64,
+	pub shares_accepted: u64,
+	pub shares_rejected: u64,
+	pub shares_stale: u64,
+	/// unix time of the last share, 0 before the first one
+	pub last_share_time: i64,
+}
+
 #[derive(Clone)]
 pub struct Stats {
 	/// Client/networking stats
 	pub client_stats: ClientStats,
 	/// Mining stats
 	pub mining_stats: MiningStats,
+	/// Miners connected to the proxy, in proxy mode only
+	pub workers: Vec<WorkerStats>,
 }
 
 impl Default for Stats {
@@ -137,6 +232,7 @@ impl Default for Stats {
 		Stats {
 			client_stats: ClientStats::default(),
 			mining_stats: MiningStats::default(),
+			workers: vec![],
 		}
 	}
 }
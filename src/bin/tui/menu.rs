@@ -26,12 +26,16 @@ use cursive::Cursive;
 
 use tui::constants::*;
 
-/// Create menu
-pub fn create() -> Box<View> {
+/// Create menu, with a mining entry for each of the `sessions` views
+pub fn create(sessions: &[(String, String)]) -> Box<View> {
 	let mut main_menu = SelectView::new().h_align(HAlign::Left).with_id(MAIN_MENU);
-	main_menu.get_mut().add_item("Mining", VIEW_MINING);
-	main_menu.get_mut().add_item("Version Info", VIEW_VERSION);
-	let change_view = |s: &mut Cursive, v: &&str| {
+	for &(ref label, ref view) in sessions {
+		main_menu.get_mut().add_item(label.clone(), view.clone());
+	}
+	main_menu
+		.get_mut()
+		.add_item("Version Info", VIEW_VERSION.to_owned());
+	let change_view = |s: &mut Cursive, v: &String| {
 		if *v == "" {
 			return;
 		}
@@ -45,17 +49,17 @@ pub fn create() -> Box<View> {
 	main_menu.get_mut().set_on_select(change_view);
 	let main_menu = OnEventView::new(main_menu)
 		.on_pre_event('j', move |c| {
-			let mut s: ViewRef<SelectView<&str>> = c.find_id(MAIN_MENU).unwrap();
+			let mut s: ViewRef<SelectView<String>> = c.find_id(MAIN_MENU).unwrap();
 			s.select_down(1)(c);
 			Some(EventResult::Consumed(None));
 		})
 		.on_pre_event('k', move |c| {
-			let mut s: ViewRef<SelectView<&str>> = c.find_id(MAIN_MENU).unwrap();
+			let mut s: ViewRef<SelectView<String>> = c.find_id(MAIN_MENU).unwrap();
 			s.select_up(1)(c);
 			Some(EventResult::Consumed(None));
 		})
 		.on_pre_event(Key::Tab, move |c| {
-			let mut s: ViewRef<SelectView<&str>> = c.find_id(MAIN_MENU).unwrap();
+			let mut s: ViewRef<SelectView<String>> = c.find_id(MAIN_MENU).unwrap();
 			if s.selected_id().unwrap() == s.len() - 1 {
 				s.set_selection(0)(c);
 			} else {
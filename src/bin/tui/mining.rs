@@ -104,11 +104,18 @@ impl TableViewItem<MiningDeviceColumn> for Stats {
 	}
 }
 
+/// Id of a view of the given session, each session gets its own mining view
+pub fn id(name: &str, session: usize) -> String {
+	format!("{}_{}", name, session)
+}
+
 /// Mining status view
 pub struct TUIMiningView;
 
 impl TUIMiningView {
-	pub fn create_algorithm(algorithm: Algorithm) -> Box<View> {
+	/// Mining view of one stratum session, the devices of its first
+	/// algorithm decide the columns shown
+	pub fn create_session(session: usize, algorithms: &[Algorithm]) -> Box<View> {
 		let table_view = {
 			let table = TableView::<Stats, MiningDeviceColumn>::new()
 				.column(MiningDeviceColumn::Plugin, "Plugin", |c| {
@@ -125,8 +132,8 @@ impl TUIMiningView {
 					c.width_percent(8)
 				});
 
-			match algorithm {
-				Algorithm::Cuckoo => table
+			match algorithms.first() {
+				Some(Algorithm::Cuckoo) => table
 					.column(MiningDeviceColumn::LastGraphTime, "Graph Time", |c| {
 						c.width_percent(10)
 					})
@@ -142,139 +149,95 @@ impl TUIMiningView {
 		let status_view =
 			LinearLayout::new(Orientation::Vertical)
 				.child(LinearLayout::new(Orientation::Horizontal).child(
-					TextView::new("Connection Status: Starting...").with_id("mining_server_status"),
+					TextView::new("Connection Status: Starting...").with_id(id("mining_server_status", session)),
 				))
 				.child(
 					LinearLayout::new(Orientation::Horizontal)
-						.child(TextView::new("Current Network Difficulty:  ").with_id("current_network_diff")),
-				)
-				.child(
-					LinearLayout::new(Orientation::Horizontal)
-						.child(TextView::new("Network Needed Algorithm:  ").with_id("current_algo_needed")),
+						.child(TextView::new("Active Pool:  ").with_id(id("active_pool", session))),
 				)
 				.child(
 					LinearLayout::new(Orientation::Horizontal)
-						.child(TextView::new("Mining Status: ").with_id("mining_status")),
+						.child(TextView::new("Current Network Difficulty:  ").with_id(id("current_network_diff", session))),
 				)
 				.child(
 					LinearLayout::new(Orientation::Horizontal)
-						.child(TextView::new("  ").with_id("network_info")),
+						.child(TextView::new("Network Needed Algorithm:  ").with_id(id("current_algo_needed", session))),
 				)
 				.child(
 					LinearLayout::new(Orientation::Horizontal)
-						.child(TextView::new("  ").with_id("mining_statistics")),
+						.child(TextView::new("").with_id(id("algorithm_time", session))),
 				)
 				.child(
 					LinearLayout::new(Orientation::Horizontal)
-						.child(TextView::new("Last Message Sent:  ").with_id("last_message_sent")),
+						.child(TextView::new("Mining Status: ").with_id(id("mining_status", session))),
 				)
-				.child(LinearLayout::new(Orientation::Horizontal).child(
-					TextView::new("Last Message Received:  ").with_id("last_message_received"),
-				));
-
-		let mining_device_view = LinearLayout::new(Orientation::Vertical)
-			.child(status_view)
-			.child(BoxView::with_full_screen(
-				Dialog::around(table_view.with_id(TABLE_MINING_STATUS).min_size((50, 20)))
-					.title("Mining Devices"),
-			))
-			.with_id("mining_device_view");
-
-		let view_stack = StackView::new()
-			.layer(mining_device_view)
-			.with_id("mining_stack_view");
-
-		let mining_view = LinearLayout::new(Orientation::Vertical).child(view_stack);
-
-		Box::new(mining_view.with_id(VIEW_MINING))
-	}
-}
-
-impl TUIStatusListener for TUIMiningView {
-	/// Create the mining view
-	fn create() -> Box<View> {
-		let table_view = TableView::<Stats, MiningDeviceColumn>::new()
-			.column(MiningDeviceColumn::Plugin, "Plugin", |c| {
-				c.width_percent(25)
-			})
-			.column(MiningDeviceColumn::DeviceId, "Device ID", |c| {
-				c.width_percent(10)
-			})
-			.column(MiningDeviceColumn::DeviceName, "Device Name", |c| {
-				c.width_percent(20)
-			})
-			// .column(MiningDeviceColumn::EdgeBits, "Size", |c| c.width_percent(5))
-			.column(MiningDeviceColumn::ErrorStatus, "Status", |c| {
-				c.width_percent(8)
-			})
-			.column(MiningDeviceColumn::LastGraphTime, "Graph Time", |c| {
-				c.width_percent(10)
-			})
-			.column(MiningDeviceColumn::GraphsPerSecond, "GPS", |c| {
-				c.width_percent(10)
-			});
-
-		let status_view =
-			LinearLayout::new(Orientation::Vertical)
-				.child(LinearLayout::new(Orientation::Horizontal).child(
-					TextView::new("Connection Status: Starting...").with_id("mining_server_status"),
-				))
 				.child(
 					LinearLayout::new(Orientation::Horizontal)
-						.child(TextView::new("Mining Status: ").with_id("mining_status")),
+						.child(TextView::new("  ").with_id(id("network_info", session))),
 				)
 				.child(
 					LinearLayout::new(Orientation::Horizontal)
-						.child(TextView::new("Current Network Difficulty:  ").with_id("current_network_diff")),
+						.child(TextView::new("  ").with_id(id("mining_statistics", session))),
 				)
 				.child(
 					LinearLayout::new(Orientation::Horizontal)
-						.child(TextView::new("Network Needed Algorithm:  ").with_id("current_algo_needed")),
-				)
-				.child(
-					LinearLayout::new(Orientation::Horizontal)
-						.child(TextView::new("  ").with_id("network_info")),
-				)
-				.child(
-					LinearLayout::new(Orientation::Horizontal)
-						.child(TextView::new("  ").with_id("mining_statistics")),
-				)
-				.child(
-					LinearLayout::new(Orientation::Horizontal)
-						.child(TextView::new("Last Message Sent:  ").with_id("last_message_sent")),
+						.child(TextView::new("Last Message Sent:  ").with_id(id("last_message_sent", session))),
 				)
 				.child(LinearLayout::new(Orientation::Horizontal).child(
-					TextView::new("Last Message Received:  ").with_id("last_message_received"),
+					TextView::new("Last Message Received:  ").with_id(id("last_message_received", session)),
 				));
 
+		let algorithm_names: Vec<String> = algorithms.iter().map(|a| format!("{:?}", a)).collect();
 		let mining_device_view = LinearLayout::new(Orientation::Vertical)
 			.child(status_view)
 			.child(BoxView::with_full_screen(
-				Dialog::around(table_view.with_id(TABLE_MINING_STATUS).min_size((50, 20)))
-					.title("Mining Devices"),
+				Dialog::around(
+					table_view
+						.with_id(id(TABLE_MINING_STATUS, session))
+						.min_size((50, 20)),
+				)
+				.title(format!("Mining Devices: {}", algorithm_names.join(", "))),
 			))
-			.with_id("mining_device_view");
+			.with_id(id("mining_device_view", session));
 
 		let view_stack = StackView::new()
 			.layer(mining_device_view)
-			.with_id("mining_stack_view");
+			.with_id(id("mining_stack_view", session));
 
 		let mining_view = LinearLayout::new(Orientation::Vertical).child(view_stack);
 
-		Box::new(mining_view.with_id(VIEW_MINING))
+		Box::new(mining_view.with_id(id(VIEW_MINING, session)))
 	}
 
-	/// update
-	fn update(c: &mut Cursive, stats: Arc<RwLock<stats::Stats>>) {
+	fn update_session(c: &mut Cursive, session: usize, stats: &Arc<RwLock<stats::Stats>>) {
 		let (client_stats, mining_stats) = {
 			let stats = stats.read().unwrap();
 			(stats.client_stats.clone(), stats.mining_stats.clone())
 		};
 
-		c.call_on_id("mining_server_status", |t: &mut TextView| {
+		c.call_on_id(&id("mining_server_status", session), |t: &mut TextView| {
 			t.set_content(client_stats.connection_status.clone());
 		});
 
+		let active_pool = {
+			let role = if client_stats.active_server == 0 {
+				"primary".to_string()
+			} else {
+				format!("backup {}", client_stats.active_server)
+			};
+			if client_stats.server_switch_reason.is_empty() {
+				format!("Active Pool: {} ({})", client_stats.server_url, role)
+			} else {
+				format!(
+					"Active Pool: {} ({}, switched: {})",
+					client_stats.server_url, role, client_stats.server_switch_reason
+				)
+			}
+		};
+		c.call_on_id(&id("active_pool", session), |t: &mut TextView| {
+			t.set_content(active_pool);
+		});
+
 		let (basic_mining_status, basic_network_info) = {
 			if client_stats.connected {
 				if mining_stats.combined_gps() == 0.0 {
@@ -305,44 +268,75 @@ impl TUIStatusListener for TUIMiningView {
 		};
 
 		// device
-		c.call_on_id("mining_status", |t: &mut TextView| {
+		c.call_on_id(&id("mining_status", session), |t: &mut TextView| {
 			t.set_content(basic_mining_status);
 		});
-		c.call_on_id("network_info", |t: &mut TextView| {
+		c.call_on_id(&id("network_info", session), |t: &mut TextView| {
 			t.set_content(basic_network_info);
 		});
-		c.call_on_id("current_algo_needed", |t: &mut TextView| {
+		c.call_on_id(&id("current_algo_needed", session), |t: &mut TextView| {
 			t.set_content(format!("Network Needed Algorithm: {}", client_stats.algorithm_needed));
 		});
-		c.call_on_id("current_network_diff", |t: &mut TextView| {
+		// only filled in when following the pool's algorithm
+		if !client_stats.algorithm_seconds.is_empty() {
+			let times: Vec<String> = client_stats
+				.algorithm_seconds
+				.iter()
+				.map(|(algorithm, secs)| {
+					let secs = *secs as u64;
+					format!("{} {}h {:02}m {:02}s", algorithm, secs / 3600, secs / 60 % 60, secs % 60)
+				})
+				.collect();
+			c.call_on_id(&id("algorithm_time", session), |t: &mut TextView| {
+				t.set_content(format!("Time per Algorithm: {}", times.join(", ")));
+			});
+		}
+		c.call_on_id(&id("current_network_diff", session), |t: &mut TextView| {
 			t.set_content(format!("Current Network Difficulty: {}", client_stats.current_network_difficulty));
 		});
-		c.call_on_id("last_message_sent", |t: &mut TextView| {
+		c.call_on_id(&id("last_message_sent", session), |t: &mut TextView| {
 			t.set_content(client_stats.last_message_sent.clone());
 		});
-		c.call_on_id("last_message_received", |t: &mut TextView| {
+		c.call_on_id(&id("last_message_received", session), |t: &mut TextView| {
 			t.set_content(client_stats.last_message_received.clone());
 		});
 
-		if mining_stats.solution_stats.num_solutions_found > 0 {
+		if mining_stats.solution_stats.num_solutions_found > 0
+			|| mining_stats.solution_stats.num_hw_errors > 0
+		{
 			let sol_stat = format!(
-				"Solutions found: {}. Accepted: {}, Rejected: {}, Stale: {}, Blocks found: {}",
+				"Solutions found: {}. Accepted: {}, Rejected: {}, Stale: {}, HW errors: {}, Blocks found: {}",
 				mining_stats.solution_stats.num_solutions_found,
 				mining_stats.solution_stats.num_shares_accepted,
 				mining_stats.solution_stats.num_rejected,
 				mining_stats.solution_stats.num_staled,
+				mining_stats.solution_stats.num_hw_errors,
 				mining_stats.solution_stats.num_blocks_found,
 			);
-			c.call_on_id("mining_statistics", |t: &mut TextView| {
+			c.call_on_id(&id("mining_statistics", session), |t: &mut TextView| {
 				t.set_content(sol_stat);
 			});
 		}
 
 		let _ = c.call_on_id(
-			TABLE_MINING_STATUS,
+			&id(TABLE_MINING_STATUS, session),
 			|t: &mut TableView<Stats, MiningDeviceColumn>| {
 				t.set_items(mining_stats.device_stats);
 			},
 		);
 	}
 }
+
+impl TUIStatusListener for TUIMiningView {
+	/// Create the mining view
+	fn create() -> Box<View> {
+		TUIMiningView::create_session(0, &[Algorithm::Cuckoo])
+	}
+
+	/// update every session's view
+	fn update(c: &mut Cursive, stats: &[Arc<RwLock<stats::Stats>>]) {
+		for (session, s) in stats.iter().enumerate() {
+			TUIMiningView::update_session(c, session, s);
+		}
+	}
+}
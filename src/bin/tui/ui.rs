@@ -15,6 +15,7 @@
 //! Basic TUI to better output the overall system status and status
 //! of various subsystems
 
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{mpsc, Arc, RwLock};
 use std::{self, thread};
 use time;
@@ -60,8 +61,8 @@ fn modify_theme(theme: &mut Theme) {
 }
 
 impl UI {
-	/// Create a new UI
-	pub fn new(algorithm: Algorithm, controller_tx: mpsc::Sender<ControllerMessage>) -> UI {
+	/// Create a new UI, with a mining view for each session's algorithms
+	pub fn new(sessions: &[Vec<Algorithm>], controller_tx: mpsc::Sender<ControllerMessage>) -> UI {
 		let (ui_tx, ui_rx) = mpsc::channel::<UIMessage>();
 		let mut epic_ui = UI {
 			cursive: Cursive::default(),
@@ -71,15 +72,30 @@ impl UI {
 		};
 
 		// Create UI objects, etc
-		let mining_view = mining::TUIMiningView::create_algorithm(algorithm);
 		let version_view = version::TUIVersionView::create();
 
-		let main_menu = menu::create();
-
-		let root_stack = StackView::new()
-			.layer(version_view)
-			.layer(mining_view)
-			.with_id(ROOT_STACK);
+		let menu_items: Vec<(String, String)> = sessions
+			.iter()
+			.enumerate()
+			.map(|(i, algorithms)| {
+				let label = if sessions.len() == 1 {
+					"Mining".to_owned()
+				} else {
+					let names: Vec<String> =
+						algorithms.iter().map(|a| format!("{:?}", a)).collect();
+					format!("Mining {}", names.join("/"))
+				};
+				(label, mining::id(VIEW_MINING, i))
+			})
+			.collect();
+		let main_menu = menu::create(&menu_items);
+
+		// the first session's view on top
+		let mut root_stack = StackView::new().layer(version_view);
+		for (i, algorithms) in sessions.iter().enumerate().rev() {
+			root_stack.add_layer(mining::TUIMiningView::create_session(i, algorithms));
+		}
+		let root_stack = root_stack.with_id(ROOT_STACK);
 
 		let mut title_string = StyledString::new();
 		title_string.append(StyledString::styled(
@@ -123,8 +139,8 @@ impl UI {
 		while let Some(message) = self.ui_rx.try_iter().next() {
 			match message {
 				UIMessage::UpdateStatus(update) => {
-					mining::TUIMiningView::update(&mut self.cursive, update.clone());
-					version::TUIVersionView::update(&mut self.cursive, update.clone());
+					mining::TUIMiningView::update(&mut self.cursive, &update);
+					version::TUIVersionView::update(&mut self.cursive, &update);
 				}
 			}
 		}
@@ -154,19 +170,25 @@ pub enum ControllerMessage {
 }
 
 impl Controller {
-	/// Create a new controller
-	pub fn new(algo: Algorithm) -> Result<Controller, String> {
+	/// Create a new controller, for stratum sessions mining the given
+	/// algorithms
+	pub fn new(sessions: &[Vec<Algorithm>]) -> Result<Controller, String> {
 		let (tx, rx) = mpsc::channel::<ControllerMessage>();
 		Ok(Controller {
 			rx: rx,
-			ui: UI::new(algo, tx.clone()),
+			ui: UI::new(sessions, tx.clone()),
 		})
 	}
-	/// Run the controller
-	pub fn run(&mut self, stats: Arc<RwLock<stats::Stats>>) {
+	/// Run the controller until the UI is closed or `quit` is set, `stats`
+	/// holding each session's stats in the order they were created with
+	pub fn run(&mut self, stats: Vec<Arc<RwLock<stats::Stats>>>, quit: Arc<AtomicBool>) {
 		let stat_update_interval = 1;
 		let mut next_stat_update = time::get_time().sec + stat_update_interval;
 		while self.ui.step() {
+			if quit.load(Ordering::Relaxed) {
+				self.ui.stop();
+				return;
+			}
 			while let Some(message) = self.rx.try_iter().next() {
 				match message {
 					ControllerMessage::Shutdown => {
@@ -45,5 +45,5 @@ impl TUIStatusListener for TUIVersionView {
 	}
 
 	/// update
-	fn update(_c: &mut Cursive, _stats: Arc<RwLock<Stats>>) {}
+	fn update(_c: &mut Cursive, _stats: &[Arc<RwLock<Stats>>]) {}
 }
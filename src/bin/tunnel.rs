@@ -0,0 +1,461 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tunnels stratum connections through a SOCKS5 (RFC 1928, 1929) or HTTP
+//! CONNECT proxy. The tunnel is a plain TcpStream once set up, so TLS
+//! goes on top of it as on a direct connection.
+
+use core::config::{ProxyConfig, ProxyKind};
+use openssl::base64;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// How long the proxy gets to connect us
+const HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+/// Longest HTTP response header accepted from a proxy
+const MAX_HTTP_HEADER: usize = 8192;
+
+fn error(proxy: &ProxyConfig, message: String) -> io::Error {
+	io::Error::new(
+		io::ErrorKind::Other,
+		format!("{:?} proxy {}: {}", proxy.kind, proxy.addr, message),
+	)
+}
+
+/// Host and port of a `host:port` address, IPv6 brackets removed
+fn split_addr(addr: &str) -> Option<(String, u16)> {
+	let i = addr.rfind(':')?;
+	let port = addr[i + 1..].parse().ok()?;
+	let host = addr[..i].trim_start_matches('[').trim_end_matches(']');
+	if host.is_empty() {
+		return None;
+	}
+	Some((host.to_owned(), port))
+}
+
+/// Where to ask the proxy to connect to
+enum Target {
+	Ip(SocketAddr),
+	Name(String, u16),
+}
+
+impl Target {
+	fn new(proxy: &ProxyConfig, addr: &str) -> io::Result<Target> {
+		let (host, port) = split_addr(addr)
+			.ok_or_else(|| error(proxy, format!("invalid server address {}", addr)))?;
+		if let Ok(ip) = host.parse::<IpAddr>() {
+			return Ok(Target::Ip(SocketAddr::new(ip, port)));
+		}
+		if proxy.remote_dns {
+			return Ok(Target::Name(host, port));
+		}
+		(host.as_str(), port)
+			.to_socket_addrs()?
+			.next()
+			.map(Target::Ip)
+			.ok_or_else(|| error(proxy, format!("can't resolve {}", host)))
+	}
+
+	/// host:port as in an HTTP request
+	fn authority(&self) -> String {
+		match *self {
+			Target::Ip(addr) => addr.to_string(),
+			Target::Name(ref host, port) => format!("{}:{}", host, port),
+		}
+	}
+}
+
+/// Connects to `addr` through `proxy`
+pub fn connect(proxy: &ProxyConfig, addr: &str) -> io::Result<TcpStream> {
+	let target = Target::new(proxy, addr)?;
+	let timeout = Duration::from_secs(HANDSHAKE_TIMEOUT_SECS);
+	let mut last_error = error(proxy, "address doesn't resolve".to_owned());
+	let mut stream = None;
+	for proxy_addr in proxy.addr.to_socket_addrs()? {
+		match TcpStream::connect_timeout(&proxy_addr, timeout) {
+			Ok(s) => {
+				stream = Some(s);
+				break;
+			}
+			Err(e) => last_error = e,
+		}
+	}
+	let mut stream = stream.ok_or(last_error)?;
+	// a proxy that accepts the connection but never answers would
+	// otherwise hold up the client forever
+	stream.set_read_timeout(Some(timeout))?;
+	stream.set_write_timeout(Some(timeout))?;
+	match proxy.kind {
+		ProxyKind::Socks5 => socks5_handshake(proxy, &mut stream, &target)?,
+		ProxyKind::Http => http_connect(proxy, &mut stream, &target)?,
+	}
+	stream.set_read_timeout(None)?;
+	stream.set_write_timeout(None)?;
+	Ok(stream)
+}
+
+fn socks5_reply_message(code: u8) -> &'static str {
+	match code {
+		1 => "general failure",
+		2 => "connection not allowed by ruleset",
+		3 => "network unreachable",
+		4 => "host unreachable",
+		5 => "connection refused",
+		6 => "TTL expired",
+		7 => "command not supported",
+		8 => "address type not supported",
+		_ => "unknown error",
+	}
+}
+
+fn socks5_handshake(proxy: &ProxyConfig, stream: &mut TcpStream, target: &Target) -> io::Result<()> {
+	let credentials = proxy
+		.username
+		.as_ref()
+		.map(|u| (u.as_str(), proxy.password.as_ref().map_or("", |p| p.as_str())));
+	// offer username/password authentication only with credentials
+	let greeting: &[u8] = match credentials {
+		Some(_) => &[5, 2, 0, 2],
+		None => &[5, 1, 0],
+	};
+	stream.write_all(greeting)?;
+	let mut reply = [0u8; 2];
+	stream.read_exact(&mut reply)?;
+	if reply[0] != 5 {
+		return Err(error(proxy, "not a SOCKS5 proxy".to_owned()));
+	}
+	match (reply[1], credentials) {
+		(0, _) => {}
+		(2, Some((username, password))) => {
+			if username.len() > 255 || password.len() > 255 {
+				return Err(error(proxy, "username or password too long".to_owned()));
+			}
+			let mut auth = vec![1, username.len() as u8];
+			auth.extend_from_slice(username.as_bytes());
+			auth.push(password.len() as u8);
+			auth.extend_from_slice(password.as_bytes());
+			stream.write_all(&auth)?;
+			stream.read_exact(&mut reply)?;
+			if reply[1] != 0 {
+				return Err(error(proxy, "authentication failed".to_owned()));
+			}
+		}
+		_ => return Err(error(proxy, "no acceptable authentication method".to_owned())),
+	}
+
+	let mut request = vec![5, 1, 0];
+	let port = match *target {
+		Target::Ip(SocketAddr::V4(addr)) => {
+			request.push(1);
+			request.extend_from_slice(&addr.ip().octets());
+			addr.port()
+		}
+		Target::Ip(SocketAddr::V6(addr)) => {
+			request.push(4);
+			request.extend_from_slice(&addr.ip().octets());
+			addr.port()
+		}
+		Target::Name(ref host, port) => {
+			if host.len() > 255 {
+				return Err(error(proxy, format!("host name {} too long", host)));
+			}
+			request.push(3);
+			request.push(host.len() as u8);
+			request.extend_from_slice(host.as_bytes());
+			port
+		}
+	};
+	request.extend_from_slice(&port.to_be_bytes());
+	stream.write_all(&request)?;
+
+	let mut head = [0u8; 4];
+	stream.read_exact(&mut head)?;
+	if head[1] != 0 {
+		return Err(error(
+			proxy,
+			format!(
+				"can't connect to {}: {}",
+				target.authority(),
+				socks5_reply_message(head[1])
+			),
+		));
+	}
+	// the address the proxy connected from, not needed
+	let len = match head[3] {
+		1 => 4,
+		4 => 16,
+		3 => {
+			let mut len = [0u8; 1];
+			stream.read_exact(&mut len)?;
+			len[0] as usize
+		}
+		_ => return Err(error(proxy, "invalid reply".to_owned())),
+	};
+	let mut bound = vec![0u8; len + 2];
+	stream.read_exact(&mut bound)?;
+	Ok(())
+}
+
+fn http_connect(proxy: &ProxyConfig, stream: &mut TcpStream, target: &Target) -> io::Result<()> {
+	let authority = target.authority();
+	let mut request = format!(
+		"CONNECT {} HTTP/1.1\r\nHost: {}\r\n",
+		authority, authority
+	);
+	if let Some(ref username) = proxy.username {
+		let password = proxy.password.as_ref().map_or("", |p| p.as_str());
+		let credentials = base64::encode_block(format!("{}:{}", username, password).as_bytes());
+		request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+	}
+	request.push_str("\r\n");
+	stream.write_all(request.as_bytes())?;
+
+	// byte by byte, whatever follows the header belongs to the pool
+	let mut header = vec![];
+	let mut byte = [0u8; 1];
+	while !header.ends_with(b"\r\n\r\n") {
+		if header.len() >= MAX_HTTP_HEADER {
+			return Err(error(proxy, "response header too long".to_owned()));
+		}
+		if stream.read(&mut byte)? == 0 {
+			return Err(error(proxy, "connection closed".to_owned()));
+		}
+		header.push(byte[0]);
+	}
+	let header = String::from_utf8_lossy(&header);
+	let status = header.lines().next().unwrap_or("");
+	match status.split_whitespace().nth(1) {
+		Some(code) if code.starts_with('2') => Ok(()),
+		_ => Err(error(
+			proxy,
+			format!("can't connect to {}: {}", authority, status),
+		)),
+	}
+}
+
+#[cfg(test)]
+pub mod test {
+	use super::*;
+	use std::io::{BufRead, BufReader};
+	use std::net::{Shutdown, TcpListener};
+	use std::sync::mpsc;
+	use std::thread;
+
+	/// Relays between the client and `target` until either side closes
+	fn relay(client: TcpStream, target: &str) {
+		let server = TcpStream::connect(target).unwrap();
+		let (mut client_r, mut server_w) = (client.try_clone().unwrap(), server.try_clone().unwrap());
+		let t = thread::spawn(move || {
+			let _ = io::copy(&mut client_r, &mut server_w);
+			let _ = server_w.shutdown(Shutdown::Write);
+		});
+		let (mut server_r, mut client_w) = (server, client);
+		let _ = io::copy(&mut server_r, &mut client_w);
+		let _ = client_w.shutdown(Shutdown::Write);
+		let _ = t.join();
+	}
+
+	/// SOCKS5 proxy stand-in for one connection. Accepts `credentials` if
+	/// given, else no authentication, answers the connect request with
+	/// `reply` and relays to `relay_to`. Reports the requested target as
+	/// "host:port".
+	pub fn socks5_stand_in(
+		credentials: Option<(&'static str, &'static str)>,
+		reply: u8,
+		relay_to: String,
+	) -> (String, mpsc::Receiver<String>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap().to_string();
+		let (tx, rx) = mpsc::channel();
+		thread::spawn(move || {
+			let (mut s, _) = listener.accept().unwrap();
+			let mut head = [0u8; 2];
+			s.read_exact(&mut head).unwrap();
+			let mut methods = vec![0u8; head[1] as usize];
+			s.read_exact(&mut methods).unwrap();
+			match credentials {
+				Some((username, password)) => {
+					assert!(methods.contains(&2));
+					s.write_all(&[5, 2]).unwrap();
+					let mut buf = [0u8; 2];
+					s.read_exact(&mut buf).unwrap();
+					let mut user = vec![0u8; buf[1] as usize];
+					s.read_exact(&mut user).unwrap();
+					s.read_exact(&mut buf[..1]).unwrap();
+					let mut pass = vec![0u8; buf[0] as usize];
+					s.read_exact(&mut pass).unwrap();
+					let ok = user == username.as_bytes() && pass == password.as_bytes();
+					s.write_all(&[1, if ok { 0 } else { 1 }]).unwrap();
+					if !ok {
+						return;
+					}
+				}
+				None => s.write_all(&[5, 0]).unwrap(),
+			}
+			let mut req = [0u8; 4];
+			s.read_exact(&mut req).unwrap();
+			let host = match req[3] {
+				1 => {
+					let mut ip = [0u8; 4];
+					s.read_exact(&mut ip).unwrap();
+					IpAddr::from(ip).to_string()
+				}
+				3 => {
+					let mut len = [0u8; 1];
+					s.read_exact(&mut len).unwrap();
+					let mut name = vec![0u8; len[0] as usize];
+					s.read_exact(&mut name).unwrap();
+					String::from_utf8(name).unwrap()
+				}
+				_ => panic!("unexpected address type {}", req[3]),
+			};
+			let mut port = [0u8; 2];
+			s.read_exact(&mut port).unwrap();
+			let _ = tx.send(format!("{}:{}", host, u16::from_be_bytes(port)));
+			s.write_all(&[5, reply, 0, 1, 127, 0, 0, 1, 0, 0]).unwrap();
+			if reply == 0 {
+				relay(s, &relay_to);
+			}
+		});
+		(addr, rx)
+	}
+
+	/// HTTP CONNECT proxy stand-in for one connection, answers with
+	/// `status` and relays to `relay_to` on success. Reports the request
+	/// header.
+	fn http_stand_in(status: &'static str, relay_to: String) -> (String, mpsc::Receiver<String>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap().to_string();
+		let (tx, rx) = mpsc::channel();
+		thread::spawn(move || {
+			let (s, _) = listener.accept().unwrap();
+			let mut reader = BufReader::new(s.try_clone().unwrap());
+			let mut header = String::new();
+			loop {
+				let mut line = String::new();
+				reader.read_line(&mut line).unwrap();
+				header.push_str(&line);
+				if line == "\r\n" {
+					break;
+				}
+			}
+			let _ = tx.send(header);
+			let mut s = s;
+			write!(s, "HTTP/1.1 {}\r\nX-Stand-In: yes\r\n\r\n", status).unwrap();
+			if status.starts_with('2') {
+				relay(s, &relay_to);
+			}
+		});
+		(addr, rx)
+	}
+
+	/// Server answering every line with the line reversed
+	pub fn reversing_server() -> String {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap().to_string();
+		thread::spawn(move || {
+			for s in listener.incoming() {
+				let s = s.unwrap();
+				let mut writer = s.try_clone().unwrap();
+				for line in BufReader::new(s).lines() {
+					let line: String = line.unwrap().chars().rev().collect();
+					if writeln!(writer, "{}", line).is_err() {
+						break;
+					}
+				}
+			}
+		});
+		addr
+	}
+
+	fn proxy(kind: ProxyKind, addr: &str) -> ProxyConfig {
+		ProxyConfig {
+			kind: kind,
+			addr: addr.to_owned(),
+			username: None,
+			password: None,
+			remote_dns: true,
+		}
+	}
+
+	fn round_trip(stream: TcpStream) -> String {
+		let mut writer = stream.try_clone().unwrap();
+		writeln!(writer, "ping").unwrap();
+		let mut line = String::new();
+		BufReader::new(stream).read_line(&mut line).unwrap();
+		line
+	}
+
+	#[test]
+	fn socks5() {
+		let server = reversing_server();
+		let (addr, target) = socks5_stand_in(Some(("miner", "secret")), 0, server.clone());
+		let mut config = proxy(ProxyKind::Socks5, &addr);
+		config.username = Some("miner".to_owned());
+		config.password = Some("secret".to_owned());
+		let stream = connect(&config, "pool.example.com:3416").unwrap();
+		assert_eq!(target.recv().unwrap(), "pool.example.com:3416");
+		assert_eq!(round_trip(stream), "gnip\n");
+
+		// resolved locally without remote DNS
+		let (addr, target) = socks5_stand_in(None, 0, server.clone());
+		let mut config = proxy(ProxyKind::Socks5, &addr);
+		config.remote_dns = false;
+		connect(&config, "localhost:3416").unwrap();
+		assert!(target.recv().unwrap().ends_with(":3416"));
+
+		let (addr, _) = socks5_stand_in(Some(("miner", "secret")), 0, server.clone());
+		let mut config = proxy(ProxyKind::Socks5, &addr);
+		config.username = Some("miner".to_owned());
+		config.password = Some("wrong".to_owned());
+		let e = connect(&config, "pool.example.com:3416").unwrap_err();
+		assert!(e.to_string().contains("authentication failed"), "{}", e);
+
+		let (addr, _) = socks5_stand_in(None, 5, server);
+		let e = connect(&proxy(ProxyKind::Socks5, &addr), "10.0.0.1:3416").unwrap_err();
+		assert!(
+			e.to_string().contains("can't connect to 10.0.0.1:3416: connection refused"),
+			"{}",
+			e
+		);
+	}
+
+	#[test]
+	fn http_connect() {
+		let server = reversing_server();
+		let (addr, header) = http_stand_in("200 Connection established", server.clone());
+		let mut config = proxy(ProxyKind::Http, &addr);
+		config.username = Some("miner".to_owned());
+		config.password = Some("secret".to_owned());
+		let stream = connect(&config, "[::1]:3416").unwrap();
+		let header = header.recv().unwrap();
+		assert!(header.starts_with("CONNECT [::1]:3416 HTTP/1.1\r\n"), "{}", header);
+		assert!(header.contains("Proxy-Authorization: Basic bWluZXI6c2VjcmV0\r\n"));
+		assert_eq!(round_trip(stream), "gnip\n");
+
+		let (addr, _) = http_stand_in("407 Proxy Authentication Required", server);
+		let e = connect(&proxy(ProxyKind::Http, &addr), "pool.example.com:3416").unwrap_err();
+		assert!(e.to_string().contains("407 Proxy Authentication Required"), "{}", e);
+	}
+
+	#[test]
+	fn split_addresses() {
+		assert_eq!(split_addr("pool.example.com:3416"), Some(("pool.example.com".to_owned(), 3416)));
+		assert_eq!(split_addr("[::1]:80"), Some(("::1".to_owned(), 80)));
+		assert_eq!(split_addr("pool.example.com"), None);
+		assert_eq!(split_addr(":80"), None);
+	}
+}
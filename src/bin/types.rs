@@ -12,8 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use core::config::{MinerConfig, StratumProtocol};
 use core::{AlgorithmParams, Solution};
 use serde_json::Value;
+use std::sync::mpsc::Sender;
+use std::time::Instant;
 
 /// Types used for stratum
 
@@ -44,6 +47,7 @@ pub struct RpcRequest {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RpcResponse {
 	pub id: String,
+	#[serde(default)]
 	pub method: String,
 	pub jsonrpc: String,
 	pub result: Option<Value>,
@@ -86,6 +90,18 @@ pub struct WorkerStatus {
 	pub stale: u64,
 }
 
+/// Stratum server settings to change at runtime. With an address the
+/// server replaces the primary one, otherwise the settings apply to the
+/// server currently in use. Missing settings are kept.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ServerChange {
+	pub addr: Option<String>,
+	pub login: Option<String>,
+	pub password: Option<String>,
+	pub tls_enabled: Option<bool>,
+	pub protocol: Option<StratumProtocol>,
+}
+
 /// Types used for internal communication from stratum client to miner
 #[derive(Serialize, Deserialize, Debug)]
 pub enum MinerMessage {
@@ -93,13 +109,30 @@ pub enum MinerMessage {
 	ReceivedJob(u64, u64, u64, String),
 	// current_seed, next_seed
 	ReceivedSeed(Vec<(u64,u64, [u8; 32])>),
+	// leading nonce bytes assigned by the pool
+	SetExtranonce(Vec<u8>),
+	// height, nonce, why the pool refused the share if it did
+	SubmitResult(u64, u64, Option<String>),
+	// stop mining until resumed, jobs keep being tracked meanwhile
+	Pause,
+	Resume,
 	StopJob,
 	Shutdown,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Debug)]
 pub enum ClientMessage {
 	// height, job_id, edge_bits, nonce, pow
 	FoundSolution(u64, Solution),
+	// connection id, line, time of arrival
+	ReceivedLine(u64, String, Instant),
+	// connection id, reason
+	ConnectionLost(u64, String),
+	// new server settings, where to report the outcome
+	ChangeServer(ServerChange, Sender<Result<String, String>>),
+	// stratum settings from a reloaded config file
+	Reconfigure(MinerConfig),
+	// address of the probed primary server, whether it accepts connections
+	PrimaryProbed(String, bool),
 	Shutdown,
 }
@@ -0,0 +1,477 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stratum over WebSocket (RFC 6455), for pools reachable at ws:// and
+//! wss:// URLs. Every JSON-RPC line goes out as a text message, and every
+//! message received is handed on as a line, so the client reads and writes
+//! the same lines as on a plain connection.
+
+use openssl::base64;
+use openssl::sha::sha1;
+use rand::{self, Rng};
+use std::io::{self, ErrorKind, Read, Write};
+
+/// Appended to the key of the handshake before hashing, from the RFC
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Longest response header accepted in the handshake
+const MAX_HTTP_HEADER: usize = 8192;
+
+/// Larger messages are refused, stratum messages are far smaller
+const MAX_MESSAGE: usize = 1 << 20;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+/// A ws:// or wss:// stratum server address
+#[derive(Debug, PartialEq)]
+pub struct Url {
+	pub secure: bool,
+	pub host: String,
+	pub port: u16,
+	pub path: String,
+}
+
+impl Url {
+	/// The WebSocket URL in `addr`, none for plain host:port addresses
+	pub fn parse(addr: &str) -> Option<Url> {
+		let lower = addr.to_lowercase();
+		let (secure, rest) = if lower.starts_with("wss://") {
+			(true, &addr[6..])
+		} else if lower.starts_with("ws://") {
+			(false, &addr[5..])
+		} else {
+			return None;
+		};
+		let (authority, path) = match rest.find('/') {
+			Some(i) => (&rest[..i], &rest[i..]),
+			None => (rest, "/"),
+		};
+		let default_port = if secure { 443 } else { 80 };
+		let (host, port) = match authority.rfind(':') {
+			Some(i) if !authority[i..].contains(']') => {
+				(&authority[..i], authority[i + 1..].parse().ok()?)
+			}
+			_ => (authority, default_port),
+		};
+		if host.is_empty() {
+			return None;
+		}
+		Some(Url {
+			secure: secure,
+			host: host.to_owned(),
+			port: port,
+			path: path.to_owned(),
+		})
+	}
+
+	/// host:port to open the TCP connection to
+	pub fn authority(&self) -> String {
+		format!("{}:{}", self.host, self.port)
+	}
+}
+
+/// Expected Sec-WebSocket-Accept for the key we sent
+fn accept_key(key: &str) -> String {
+	base64::encode_block(&sha1(format!("{}{}", key, ACCEPT_GUID).as_bytes()))
+}
+
+fn invalid(message: String) -> io::Error {
+	io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Lines over a WebSocket connection. Writes are buffered and sent as one
+/// message per line on flush.
+pub struct WsStream<S> {
+	inner: S,
+	/// raw frames not parsed yet
+	incoming: Vec<u8>,
+	/// fragments of a message still missing its final frame
+	message: Vec<u8>,
+	/// complete lines ready to be read
+	received: Vec<u8>,
+	/// written but not flushed yet
+	outgoing: Vec<u8>,
+	closed: bool,
+}
+
+impl<S: Read + Write> WsStream<S> {
+	/// Upgrades `inner` to a WebSocket connection to `url`
+	pub fn connect(mut inner: S, url: &Url) -> io::Result<WsStream<S>> {
+		let mut rng = rand::thread_rng();
+		let nonce: Vec<u8> = (0..16).map(|_| rng.gen::<u8>()).collect();
+		let key = base64::encode_block(&nonce);
+		let default_port = if url.secure { 443 } else { 80 };
+		let host = if url.port == default_port {
+			url.host.clone()
+		} else {
+			url.authority()
+		};
+		let request = format!(
+			"GET {} HTTP/1.1\r\n\
+			 Host: {}\r\n\
+			 Upgrade: websocket\r\n\
+			 Connection: Upgrade\r\n\
+			 Sec-WebSocket-Key: {}\r\n\
+			 Sec-WebSocket-Version: 13\r\n\
+			 User-Agent: epic-miner\r\n\r\n",
+			url.path, host, key
+		);
+		inner.write_all(request.as_bytes())?;
+		inner.flush()?;
+
+		// byte by byte, the first frames may follow right after
+		let mut header = vec![];
+		let mut byte = [0u8; 1];
+		while !header.ends_with(b"\r\n\r\n") {
+			if header.len() >= MAX_HTTP_HEADER {
+				return Err(invalid("handshake response too long".to_owned()));
+			}
+			if inner.read(&mut byte)? == 0 {
+				return Err(invalid("connection closed during handshake".to_owned()));
+			}
+			header.push(byte[0]);
+		}
+		let header = String::from_utf8_lossy(&header);
+		let mut lines = header.lines();
+		let status = lines.next().unwrap_or("");
+		if status.split_whitespace().nth(1) != Some("101") {
+			return Err(invalid(format!("server refused the upgrade: {}", status)));
+		}
+		let accept = lines
+			.filter_map(|l| {
+				let mut parts = l.splitn(2, ':');
+				match (parts.next(), parts.next()) {
+					(Some(name), Some(value))
+						if name.trim().eq_ignore_ascii_case("sec-websocket-accept") =>
+					{
+						Some(value.trim().to_owned())
+					}
+					_ => None,
+				}
+			})
+			.next();
+		if accept != Some(accept_key(&key)) {
+			return Err(invalid("invalid Sec-WebSocket-Accept in handshake".to_owned()));
+		}
+		Ok(WsStream {
+			inner: inner,
+			incoming: vec![],
+			message: vec![],
+			received: vec![],
+			outgoing: vec![],
+			closed: false,
+		})
+	}
+
+	pub fn get_ref(&self) -> &S {
+		&self.inner
+	}
+
+	/// Sends a frame, masked as the RFC requires from clients
+	fn send_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+		let mut frame = vec![0x80 | opcode];
+		let len = payload.len();
+		if len < 126 {
+			frame.push(0x80 | len as u8);
+		} else if len <= 0xffff {
+			frame.push(0x80 | 126);
+			frame.extend_from_slice(&(len as u16).to_be_bytes());
+		} else {
+			frame.push(0x80 | 127);
+			frame.extend_from_slice(&(len as u64).to_be_bytes());
+		}
+		let mask = rand::thread_rng().gen::<u32>().to_be_bytes();
+		frame.extend_from_slice(&mask);
+		frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+		self.inner.write_all(&frame)
+	}
+
+	/// Handles the next complete frame in `incoming`, false if there's none
+	/// yet
+	fn next_frame(&mut self) -> io::Result<bool> {
+		if self.incoming.len() < 2 {
+			return Ok(false);
+		}
+		let fin = self.incoming[0] & 0x80 != 0;
+		let opcode = self.incoming[0] & 0x0f;
+		let masked = self.incoming[1] & 0x80 != 0;
+		let (len, mut pos) = match self.incoming[1] & 0x7f {
+			126 if self.incoming.len() >= 4 => {
+				(u16::from_be_bytes([self.incoming[2], self.incoming[3]]) as usize, 4)
+			}
+			127 if self.incoming.len() >= 10 => {
+				let mut len = [0u8; 8];
+				len.copy_from_slice(&self.incoming[2..10]);
+				(u64::from_be_bytes(len) as usize, 10)
+			}
+			126 | 127 => return Ok(false),
+			len => (len as usize, 2),
+		};
+		if len > MAX_MESSAGE {
+			return Err(invalid(format!("message of {} bytes is too large", len)));
+		}
+		let mask = if masked {
+			if self.incoming.len() < pos + 4 {
+				return Ok(false);
+			}
+			pos += 4;
+			Some([
+				self.incoming[pos - 4],
+				self.incoming[pos - 3],
+				self.incoming[pos - 2],
+				self.incoming[pos - 1],
+			])
+		} else {
+			None
+		};
+		if self.incoming.len() < pos + len {
+			return Ok(false);
+		}
+		let mut payload: Vec<u8> = self.incoming.drain(..pos + len).skip(pos).collect();
+		if let Some(mask) = mask {
+			for (i, b) in payload.iter_mut().enumerate() {
+				*b ^= mask[i % 4];
+			}
+		}
+
+		match opcode {
+			OP_TEXT | OP_BINARY | OP_CONTINUATION => {
+				if self.message.len() + payload.len() > MAX_MESSAGE {
+					return Err(invalid("fragmented message is too large".to_owned()));
+				}
+				self.message.extend_from_slice(&payload);
+				if fin {
+					let mut message = std::mem::take(&mut self.message);
+					if message.last() != Some(&b'\n') {
+						message.push(b'\n');
+					}
+					self.received.extend_from_slice(&message);
+				}
+			}
+			OP_PING => {
+				self.send_frame(OP_PONG, &payload)?;
+				self.inner.flush()?;
+			}
+			OP_PONG => {}
+			OP_CLOSE => {
+				// answer the close, the server hangs up after it
+				let _ = self.send_frame(OP_CLOSE, &payload);
+				let _ = self.inner.flush();
+				self.closed = true;
+			}
+			_ => return Err(invalid(format!("unknown opcode {}", opcode))),
+		}
+		Ok(true)
+	}
+}
+
+impl<S: Read + Write> Read for WsStream<S> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		loop {
+			if !self.received.is_empty() {
+				let n = buf.len().min(self.received.len());
+				buf[..n].copy_from_slice(&self.received[..n]);
+				self.received.drain(..n);
+				return Ok(n);
+			}
+			if self.closed {
+				return Ok(0);
+			}
+			if !self.next_frame()? {
+				// timeouts leave what arrived so far in `incoming`
+				let mut chunk = [0u8; 4096];
+				let n = self.inner.read(&mut chunk)?;
+				if n == 0 {
+					return Ok(0);
+				}
+				self.incoming.extend_from_slice(&chunk[..n]);
+			}
+		}
+	}
+}
+
+impl<S: Read + Write> Write for WsStream<S> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.outgoing.extend_from_slice(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		while let Some(pos) = self.outgoing.iter().position(|b| *b == b'\n') {
+			let line: Vec<u8> = self.outgoing.drain(..pos + 1).collect();
+			self.send_frame(OP_TEXT, &line[..pos])?;
+		}
+		self.inner.flush()
+	}
+}
+
+#[cfg(test)]
+pub mod test {
+	use super::*;
+	use std::io::{BufRead, BufReader};
+	use std::net::{TcpListener, TcpStream};
+	use std::thread;
+
+	/// A server frame, unmasked
+	pub fn frame(opcode: u8, fin: bool, payload: &[u8]) -> Vec<u8> {
+		let mut frame = vec![if fin { 0x80 | opcode } else { opcode }];
+		if payload.len() < 126 {
+			frame.push(payload.len() as u8);
+		} else {
+			frame.push(126);
+			frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+		}
+		frame.extend_from_slice(payload);
+		frame
+	}
+
+	/// Reads a client frame, which has to be masked
+	pub fn read_frame<S: Read>(s: &mut S) -> (u8, Vec<u8>) {
+		let mut head = [0u8; 2];
+		s.read_exact(&mut head).unwrap();
+		assert_eq!(head[1] & 0x80, 0x80, "client frames must be masked");
+		let len = match head[1] & 0x7f {
+			126 => {
+				let mut len = [0u8; 2];
+				s.read_exact(&mut len).unwrap();
+				u16::from_be_bytes(len) as usize
+			}
+			len => len as usize,
+		};
+		let mut mask = [0u8; 4];
+		s.read_exact(&mut mask).unwrap();
+		let mut payload = vec![0u8; len];
+		s.read_exact(&mut payload).unwrap();
+		for (i, b) in payload.iter_mut().enumerate() {
+			*b ^= mask[i % 4];
+		}
+		(head[0] & 0x0f, payload)
+	}
+
+	/// Answers the upgrade request of a client on `s`, returns the request
+	/// header
+	pub fn accept<S: Read + Write>(s: &mut S) -> String {
+		let mut header = vec![];
+		let mut byte = [0u8; 1];
+		while !header.ends_with(b"\r\n\r\n") {
+			s.read_exact(&mut byte).unwrap();
+			header.push(byte[0]);
+		}
+		let header = String::from_utf8(header).unwrap();
+		let key = header
+			.lines()
+			.find(|l| l.starts_with("Sec-WebSocket-Key: "))
+			.unwrap()["Sec-WebSocket-Key: ".len()..]
+			.to_owned();
+		write!(
+			s,
+			"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+			 Connection: Upgrade\r\nsec-websocket-accept: {}\r\n\r\n",
+			accept_key(&key)
+		)
+		.unwrap();
+		header
+	}
+
+	#[test]
+	fn parse_urls() {
+		assert_eq!(Url::parse("pool.example.com:3416"), None);
+		assert_eq!(
+			Url::parse("wss://pool.example.com/stratum"),
+			Some(Url {
+				secure: true,
+				host: "pool.example.com".to_owned(),
+				port: 443,
+				path: "/stratum".to_owned(),
+			})
+		);
+		let url = Url::parse("WS://[::1]:8080").unwrap();
+		assert_eq!((url.secure, url.port, url.path.as_str()), (false, 8080, "/"));
+		assert_eq!(url.authority(), "[::1]:8080");
+		assert_eq!(Url::parse("ws://:80/"), None);
+	}
+
+	#[test]
+	fn accept_key_from_rfc() {
+		assert_eq!(
+			accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+			"s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+		);
+	}
+
+	#[test]
+	fn lines_over_websocket() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let server = thread::spawn(move || {
+			let (mut s, _) = listener.accept().unwrap();
+			let header = accept(&mut s);
+			assert!(header.starts_with("GET /stratum HTTP/1.1\r\n"), "{}", header);
+			assert!(header.contains(&format!("Host: 127.0.0.1:{}\r\n", port)));
+
+			let (opcode, payload) = read_frame(&mut s);
+			assert_eq!((opcode, payload.as_slice()), (OP_TEXT, &b"{\"method\":\"login\"}"[..]));
+			let long = "x".repeat(300);
+			let (_, payload) = read_frame(&mut s);
+			assert_eq!(payload, long.as_bytes());
+
+			// a ping between the fragments of a message
+			s.write_all(&frame(OP_TEXT, false, b"{\"method\":")).unwrap();
+			s.write_all(&frame(OP_PING, true, b"hi")).unwrap();
+			s.write_all(&frame(OP_CONTINUATION, true, b"\"job\"}")).unwrap();
+			assert_eq!(read_frame(&mut s), (OP_PONG, b"hi".to_vec()));
+			s.write_all(&frame(OP_TEXT, true, long.as_bytes())).unwrap();
+			s.write_all(&frame(OP_CLOSE, true, &[0x03, 0xe8])).unwrap();
+			assert_eq!(read_frame(&mut s).0, OP_CLOSE);
+		});
+
+		let url = Url::parse(&format!("ws://127.0.0.1:{}/stratum", port)).unwrap();
+		let conn = TcpStream::connect(url.authority()).unwrap();
+		let mut ws = WsStream::connect(conn, &url).unwrap();
+		ws.write_all(b"{\"method\":\"login\"}\n").unwrap();
+		ws.write_all(format!("{}\n", "x".repeat(300)).as_bytes()).unwrap();
+		ws.flush().unwrap();
+
+		let mut reader = BufReader::new(ws);
+		let mut line = String::new();
+		reader.read_line(&mut line).unwrap();
+		assert_eq!(line, "{\"method\":\"job\"}\n");
+		line.clear();
+		reader.read_line(&mut line).unwrap();
+		assert_eq!(line.len(), 301);
+		line.clear();
+		assert_eq!(reader.read_line(&mut line).unwrap(), 0);
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn refused_upgrade() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = Url::parse(&format!("ws://{}", listener.local_addr().unwrap())).unwrap();
+		thread::spawn(move || {
+			let (mut s, _) = listener.accept().unwrap();
+			let mut buf = [0u8; 1024];
+			let _ = s.read(&mut buf);
+			s.write_all(b"HTTP/1.1 404 Not Found\r\n\r\n").unwrap();
+		});
+		let conn = TcpStream::connect(url.authority()).unwrap();
+		let e = WsStream::connect(conn, &url).err().unwrap();
+		assert!(e.to_string().contains("404 Not Found"), "{}", e);
+	}
+}
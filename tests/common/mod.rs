@@ -18,6 +18,8 @@
 extern crate cuckoo_miner as cuckoo;
 extern crate time;
 
+pub mod stratum;
+
 use self::cuckoo::PluginConfig;
 use core::config::MinerConfig;
 use core::Miner;
@@ -0,0 +1,298 @@
+// Copyright 2019 The Epic Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Scripted stand-in for a stratum server speaking the Epic protocol, so
+//! the stratum client can be tested without a node. Tests push jobs and
+//! seeds, choose how submits are answered, and can drop or stall the
+//! connection at any point.
+
+use serde_json::{self, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long tests wait for the client to do something
+pub const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How the server answers submits
+#[derive(Clone, Debug)]
+pub enum SubmitReply {
+	Accept,
+	/// accepted and good enough for a block
+	BlockFound,
+	/// error code and message
+	Reject(i32, String),
+}
+
+struct State {
+	/// the connection jobs and seeds go to, the latest one
+	conn: Option<TcpStream>,
+	connections: usize,
+	/// requests received on all connections, in order
+	requests: Vec<Value>,
+	/// handed out on `getjobtemplate`, none while the node "syncs"
+	job: Option<Value>,
+	epochs: Vec<(u64, u64, [u8; 32])>,
+	submit_reply: SubmitReply,
+	accepted: u64,
+	rejected: u64,
+	/// reads requests without ever answering
+	stalled: bool,
+}
+
+impl State {
+	fn reply(&mut self, req: &Value) -> Value {
+		let method = req["method"].as_str().unwrap_or("").to_owned();
+		let result = match method.as_str() {
+			"login" | "keepalive" => Ok(json!("ok")),
+			"getjobtemplate" => match self.job {
+				Some(ref job) => Ok(job.clone()),
+				None => Err((-32000, "Node is syncing - please wait".to_owned())),
+			},
+			"status" => Ok(json!({
+				"id": "0",
+				"height": self.job.as_ref().map_or(json!(0), |j| j["height"].clone()),
+				"difficulty": 1,
+				"accepted": self.accepted,
+				"rejected": self.rejected,
+				"stale": 0,
+			})),
+			"submit" => match self.submit_reply.clone() {
+				SubmitReply::Accept => Ok(json!("ok")),
+				SubmitReply::BlockFound => Ok(json!("blockfound - 0000000000000000")),
+				SubmitReply::Reject(code, message) => Err((code, message)),
+			},
+			_ => Err((-32601, "Method not found".to_owned())),
+		};
+		match result {
+			Ok(result) => {
+				if method == "submit" {
+					self.accepted += 1;
+				}
+				json!({
+					"id": req["id"],
+					"jsonrpc": "2.0",
+					"method": method,
+					"result": result,
+					"error": null,
+				})
+			}
+			Err((code, message)) => {
+				if method == "submit" {
+					self.rejected += 1;
+				}
+				json!({
+					"id": req["id"],
+					"jsonrpc": "2.0",
+					"method": method,
+					"result": null,
+					"error": { "code": code, "message": message },
+				})
+			}
+		}
+	}
+}
+
+pub struct MockServer {
+	pub addr: SocketAddr,
+	state: Arc<Mutex<State>>,
+}
+
+impl MockServer {
+	/// Listens on a free local port, accepting any number of connections
+	pub fn start() -> MockServer {
+		MockServer::start_on("127.0.0.1:0".parse().unwrap())
+	}
+
+	/// Same as `start` on the given address, e.g. one from `unused_addr`
+	pub fn start_on(addr: SocketAddr) -> MockServer {
+		let listener = TcpListener::bind(addr).unwrap();
+		let addr = listener.local_addr().unwrap();
+		let state = Arc::new(Mutex::new(State {
+			conn: None,
+			connections: 0,
+			requests: vec![],
+			job: None,
+			epochs: vec![(0, u64::MAX, [1; 32])],
+			submit_reply: SubmitReply::Accept,
+			accepted: 0,
+			rejected: 0,
+			stalled: false,
+		}));
+		let accept_state = state.clone();
+		thread::spawn(move || {
+			for conn in listener.incoming() {
+				let conn = match conn {
+					Ok(conn) => conn,
+					Err(_) => continue,
+				};
+				{
+					// a stall only lasts until the client gives up on it
+					let mut s = accept_state.lock().unwrap();
+					s.conn = conn.try_clone().ok();
+					s.connections += 1;
+					s.stalled = false;
+				}
+				let conn_state = accept_state.clone();
+				thread::spawn(move || serve(conn, conn_state));
+			}
+		});
+		MockServer {
+			addr: addr,
+			state: state,
+		}
+	}
+
+	/// Makes a job for `algorithm` the current one and pushes it to the
+	/// client if connected. The job id is the height.
+	pub fn send_job(&self, height: u64, algorithm: &str) {
+		let mut s = self.state.lock().unwrap();
+		let job = json!({
+			"height": height,
+			"job_id": height,
+			"difficulty": [[algorithm, 1]],
+			"block_difficulty": [[algorithm, 1000]],
+			"pre_pow": "00".repeat(64),
+			"epochs": s.epochs,
+			"algorithm": algorithm,
+		});
+		s.job = Some(job.clone());
+		send(
+			&mut s,
+			json!({
+				"id": "Stratum",
+				"jsonrpc": "2.0",
+				"method": "job",
+				"params": job,
+			}),
+		);
+	}
+
+	/// Pushes a seed message announcing a single epoch
+	pub fn send_seed(&self, start_height: u64, end_height: u64, seed: [u8; 32]) {
+		let mut s = self.state.lock().unwrap();
+		s.epochs = vec![(start_height, end_height, seed)];
+		let epochs = s.epochs.clone();
+		send(
+			&mut s,
+			json!({
+				"id": "Stratum",
+				"jsonrpc": "2.0",
+				"method": "seed",
+				"result": { "epochs": epochs },
+				"error": null,
+			}),
+		);
+	}
+
+	pub fn reply_to_submits(&self, reply: SubmitReply) {
+		self.state.lock().unwrap().submit_reply = reply;
+	}
+
+	/// Closes the current connection, the client has to reconnect
+	pub fn drop_connection(&self) {
+		if let Some(conn) = self.state.lock().unwrap().conn.take() {
+			let _ = conn.shutdown(Shutdown::Both);
+		}
+	}
+
+	/// Stops answering on the current connection while keeping it open
+	pub fn stall(&self) {
+		self.state.lock().unwrap().stalled = true;
+	}
+
+	/// Number of connections accepted so far
+	pub fn connections(&self) -> usize {
+		self.state.lock().unwrap().connections
+	}
+
+	/// Received requests of `method`, oldest first
+	pub fn requests(&self, method: &str) -> Vec<Value> {
+		self.state
+			.lock()
+			.unwrap()
+			.requests
+			.iter()
+			.filter(|r| r["method"] == method)
+			.cloned()
+			.collect()
+	}
+}
+
+/// Writes a line to the current connection, if any
+fn send(state: &mut State, message: Value) {
+	let failed = match state.conn {
+		Some(ref mut conn) => writeln!(conn, "{}", message).is_err(),
+		None => false,
+	};
+	if failed {
+		state.conn = None;
+	}
+}
+
+/// Answers the requests of one connection until it's closed
+fn serve(conn: TcpStream, state: Arc<Mutex<State>>) {
+	let mut writer = match conn.try_clone() {
+		Ok(writer) => writer,
+		Err(_) => return,
+	};
+	for line in BufReader::new(conn).lines() {
+		let line = match line {
+			Ok(line) => line,
+			Err(_) => return,
+		};
+		let req = match serde_json::from_str::<Value>(&line) {
+			Ok(req) => req,
+			Err(_) => continue,
+		};
+		let reply = {
+			let mut s = state.lock().unwrap();
+			s.requests.push(req.clone());
+			if s.stalled {
+				continue;
+			}
+			s.reply(&req)
+		};
+		if writeln!(writer, "{}", reply).is_err() {
+			return;
+		}
+	}
+}
+
+/// A local address nothing listens on, connecting to it fails until a
+/// server is started on it
+pub fn unused_addr() -> SocketAddr {
+	TcpListener::bind("127.0.0.1:0")
+		.unwrap()
+		.local_addr()
+		.unwrap()
+}
+
+/// Polls `done` until it holds or `WAIT_TIMEOUT` passes
+pub fn wait_until<F>(mut done: F) -> bool
+where
+	F: FnMut() -> bool,
+{
+	let deadline = Instant::now() + WAIT_TIMEOUT;
+	while !done() {
+		if Instant::now() >= deadline {
+			return false;
+		}
+		thread::sleep(Duration::from_millis(50));
+	}
+	true
+}
@@ -2,14 +2,54 @@
 extern crate cucumber_rust;
 extern crate cuckoo_miner as cuckoo;
 extern crate epic_miner_core as core;
+extern crate epic_miner_plugin as plugin;
+extern crate epic_miner_util as util;
+extern crate native_tls;
+extern crate openssl;
 extern crate progpow_miner as progpow;
 extern crate rand;
 extern crate randomx_miner as randomx;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate slog;
+extern crate time;
 mod common;
 
-use core::config::{EpicMinerPluginConfig, MinerConfig};
+// the stratum client as built into the miner binary
+#[allow(dead_code)]
+#[path = "../src/bin/capture.rs"]
+mod capture;
+#[allow(dead_code)]
+#[path = "../src/bin/client.rs"]
+mod client;
+#[allow(dead_code)]
+#[path = "../src/bin/protocol.rs"]
+mod protocol;
+#[allow(dead_code)]
+#[path = "../src/bin/stats.rs"]
+mod stats;
+#[allow(dead_code)]
+#[path = "../src/bin/tunnel.rs"]
+mod tunnel;
+#[allow(dead_code)]
+#[path = "../src/bin/types.rs"]
+mod types;
+#[allow(dead_code)]
+#[path = "../src/bin/websocket.rs"]
+mod websocket;
+
+use common::stratum::{unused_addr, wait_until, MockServer};
+use core::config::{EpicMinerPluginConfig, MinerConfig, StratumServerConfig};
 use core::types::Algorithm;
 use core::Miner;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, RwLock};
+use std::thread;
 
 pub enum TargetMiner {
 	Cuckoo(cuckoo::CuckooMiner),
@@ -23,6 +63,15 @@ pub struct MinerWorld {
 	pub time_in_seconds: Option<i64>,
 	pub algorithm: Algorithm,
 	pub miner: Option<TargetMiner>,
+	pub stratum: Option<MockServer>,
+	/// started on the primary server's address after the mock server was
+	/// made its backup
+	pub primary: Option<MockServer>,
+	/// settings the stratum client is started with
+	pub config: MinerConfig,
+	pub client: Option<StratumClient>,
+	/// the client's connection breaks while sending its first share
+	pub break_on_first_submit: bool,
 }
 
 impl cucumber_rust::World for MinerWorld {}
@@ -34,10 +83,109 @@ impl std::default::Default for MinerWorld {
 			time_in_seconds: None,
 			algorithm: Algorithm::Cuckoo,
 			miner: None,
+			stratum: None,
+			primary: None,
+			config: MinerConfig::default(),
+			client: None,
+			break_on_first_submit: false,
 		}
 	}
 }
 
+/// A stratum client running against the mock server, with the miner
+/// side of it played by the test
+pub struct StratumClient {
+	pub tx: mpsc::Sender<types::ClientMessage>,
+	miner_rx: mpsc::Receiver<types::MinerMessage>,
+	/// what the miner was told so far
+	pub received: Vec<types::MinerMessage>,
+	pub stats: Arc<RwLock<stats::Stats>>,
+}
+
+/// A plain connection to the mock server that breaks, once, as soon as a
+/// submit is written to it
+struct BreakingConnection {
+	stream: TcpStream,
+	broken: Arc<AtomicBool>,
+}
+
+impl Read for BreakingConnection {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.stream.read(buf)
+	}
+}
+
+impl Write for BreakingConnection {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let submit = String::from_utf8_lossy(buf).contains("\"submit\"");
+		if submit && !self.broken.swap(true, Ordering::SeqCst) {
+			let _ = self.stream.shutdown(Shutdown::Both);
+			return Err(io::Error::new(io::ErrorKind::BrokenPipe, "connection broke"));
+		}
+		self.stream.write(buf)
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		self.stream.flush()
+	}
+}
+
+impl client::Connection for BreakingConnection {
+	fn socket(&self) -> Option<&TcpStream> {
+		Some(&self.stream)
+	}
+}
+
+impl StratumClient {
+	pub fn start(algorithm: Algorithm, config: &MinerConfig, break_on_first_submit: bool) -> StratumClient {
+		let (miner_tx, miner_rx) = mpsc::channel();
+		let stats = Arc::new(RwLock::new(stats::Stats::default()));
+		let mut controller =
+			client::Controller::new(algorithm, config, miner_tx, stats.clone()).unwrap();
+		if break_on_first_submit {
+			let addr = config.stratum_server_addr.clone();
+			let broken = Arc::new(AtomicBool::new(false));
+			controller.set_connector(Box::new(move || {
+				let stream = TcpStream::connect(&addr)
+					.map_err(|e| client::Error::ConnectionError(e.to_string()))?;
+				Ok(Box::new(BreakingConnection {
+					stream: stream,
+					broken: broken.clone(),
+				}) as Box<dyn client::Connection>)
+			}));
+		}
+		let tx = controller.tx.clone();
+		thread::spawn(move || controller.run());
+		StratumClient {
+			tx: tx,
+			miner_rx: miner_rx,
+			received: vec![],
+			stats: stats,
+		}
+	}
+
+	/// Waits for the miner to be told something `matches` accepts, also
+	/// looking at what it was told before
+	pub fn wait_for_miner<F>(&mut self, matches: F) -> bool
+	where
+		F: Fn(&types::MinerMessage) -> bool,
+	{
+		let miner_rx = &self.miner_rx;
+		let received = &mut self.received;
+		wait_until(|| {
+			while let Ok(message) = miner_rx.try_recv() {
+				received.push(message);
+			}
+			received.iter().any(&matches)
+		})
+	}
+}
+
+impl Drop for StratumClient {
+	fn drop(&mut self) {
+		let _ = self.tx.send(types::ClientMessage::Shutdown);
+	}
+}
+
 mod miner_test {
 
 	use super::*;
@@ -96,6 +244,242 @@ mod miner_test {
     });
 }
 
+mod stratum_test {
+
+	use super::*;
+	use common::stratum::SubmitReply;
+	use core::{AlgorithmParams, Solution};
+	use types::MinerMessage;
+
+	fn algorithm(name: &str) -> Algorithm {
+		match name {
+			"cuckoo" => Algorithm::Cuckoo,
+			"randomx" => Algorithm::RandomX,
+			"progpow" => Algorithm::ProgPow,
+			_ => panic!("Algorithm not supported"),
+		}
+	}
+
+	fn solution(height: u64) -> Solution {
+		// the mock server's job id is the height
+		Solution::new(height, 42, AlgorithmParams::RandomX([0; 32]))
+	}
+
+	steps!(crate::MinerWorld => {
+		given regex r"a mock stratum server" |world, _matches, _step| {
+			let server = MockServer::start();
+			world.config.stratum_server_addr = server.addr.to_string();
+			// reconnect right away, tests don't want to wait for backoff
+			world.config.stratum_retry_min = 1;
+			world.config.stratum_retry_max = 1;
+			world.stratum = Some(server);
+		};
+
+		given regex r"the mock server is the backup of a primary server that is down" |world, _matches, _step| {
+			let backup = StratumServerConfig {
+				addr: world.config.stratum_server_addr.clone(),
+				login: None,
+				password: None,
+				tls_enabled: None,
+				tls: None,
+				protocol: None,
+				proxy: None,
+			};
+			world.config.stratum_server_addr = unused_addr().to_string();
+			world.config.stratum_backup_servers = vec![backup];
+		};
+
+		given regex r"the client switches servers after <(\d+)> failed connects" |world, matches, _step| {
+			world.config.stratum_failover_retries = matches[1].parse().unwrap();
+		};
+
+		given regex r"the client checks for the primary server every <(\d+)> seconds?" |world, matches, _step| {
+			world.config.stratum_primary_retry_interval = matches[1].parse().unwrap();
+		};
+
+		given regex r"the mock server has a <([a-z]+)> job at height <(\d+)>" |world, matches, _step| {
+			world.stratum.as_ref().unwrap().send_job(matches[2].parse().unwrap(), &matches[1]);
+		};
+
+		given regex r"the mock server rejects submits with <(.*)>" |world, matches, _step| {
+			let reply = SubmitReply::Reject(-32502, matches[1].clone());
+			world.stratum.as_ref().unwrap().reply_to_submits(reply);
+		};
+
+		given regex r"the mock server reports found blocks" |world, _matches, _step| {
+			world.stratum.as_ref().unwrap().reply_to_submits(SubmitReply::BlockFound);
+		};
+
+		given regex r"the client logs in as <(.*)>" |world, matches, _step| {
+			world.config.stratum_server_login = Some(matches[1].clone());
+		};
+
+		given regex r"the connection breaks while the first share is sent" |world, _matches, _step| {
+			world.break_on_first_submit = true;
+		};
+
+		given regex r"the client gives up on a silent server after <(\d+)> seconds" |world, matches, _step| {
+			world.config.stratum_idle_timeout = matches[1].parse().unwrap();
+		};
+
+		given regex r"the client gives up on a request after <(\d+)> seconds?" |world, matches, _step| {
+			world.config.stratum_request_timeout = matches[1].parse().unwrap();
+		};
+
+		when regex r"a <([a-z]+)> stratum client connects" |world, matches, _step| {
+			let algorithm = algorithm(&matches[1]);
+			world.client = Some(StratumClient::start(algorithm, &world.config, world.break_on_first_submit));
+		};
+
+		when regex r"the server sends a <([a-z]+)> job at height <(\d+)>" |world, matches, _step| {
+			world.stratum.as_ref().unwrap().send_job(matches[2].parse().unwrap(), &matches[1]);
+		};
+
+		when regex r"the server sends the seed <(\d+)> for heights <(\d+)> to <(\d+)>" |world, matches, _step| {
+			let seed: u8 = matches[1].parse().unwrap();
+			let server = world.stratum.as_ref().unwrap();
+			server.send_seed(matches[2].parse().unwrap(), matches[3].parse().unwrap(), [seed; 32]);
+		};
+
+		when regex r"the miner finds a share at height <(\d+)>" |world, matches, _step| {
+			let height: u64 = matches[1].parse().unwrap();
+			let client = world.client.as_ref().unwrap();
+			client.tx.send(types::ClientMessage::FoundSolution(height, solution(height))).unwrap();
+		};
+
+		when regex r"the server drops the connection" |world, _matches, _step| {
+			world.stratum.as_ref().unwrap().drop_connection();
+		};
+
+		when regex r"the server stalls" |world, _matches, _step| {
+			world.stratum.as_ref().unwrap().stall();
+		};
+
+		when regex r"the primary server comes back with a <([a-z]+)> job at height <(\d+)>" |world, matches, _step| {
+			let primary = MockServer::start_on(world.config.stratum_server_addr.parse().unwrap());
+			primary.send_job(matches[2].parse().unwrap(), &matches[1]);
+			world.primary = Some(primary);
+		};
+
+		then regex r"the server receives <(\d+)> <([a-z]+)> requests?" |world, matches, _step| {
+			let count: usize = matches[1].parse().unwrap();
+			let server = world.stratum.as_ref().unwrap();
+			assert!(
+				wait_until(|| server.requests(&matches[2]).len() >= count),
+				"got {:?}",
+				server.requests(&matches[2])
+			);
+		};
+
+		then regex r"the server receives exactly <(\d+)> shares? for height <(\d+)>" |world, matches, _step| {
+			let count: usize = matches[1].parse().unwrap();
+			let height: u64 = matches[2].parse().unwrap();
+			let server = world.stratum.as_ref().unwrap();
+			let shares = || {
+				server
+					.requests("submit")
+					.iter()
+					.filter(|r| r["params"]["height"] == height)
+					.count()
+			};
+			assert!(wait_until(|| shares() >= count), "got {}", shares());
+			// a share submitted twice would follow right after the first
+			thread::sleep(std::time::Duration::from_secs(1));
+			assert_eq!(shares(), count);
+		};
+
+		then regex r"the server sees the login <(.*)>" |world, matches, _step| {
+			let logins = world.stratum.as_ref().unwrap().requests("login");
+			assert!(logins.iter().any(|r| r["params"]["login"] == matches[1].as_str()));
+		};
+
+		then regex r"the server receives a share for height <(\d+)>" |world, matches, _step| {
+			let height: u64 = matches[1].parse().unwrap();
+			let server = world.stratum.as_ref().unwrap();
+			assert!(wait_until(|| {
+				server
+					.requests("submit")
+					.iter()
+					.any(|r| r["params"]["height"] == height)
+			}));
+		};
+
+		then regex r"the client mines on server <(\d+)>" |world, matches, _step| {
+			let index: usize = matches[1].parse().unwrap();
+			let client = world.client.as_ref().unwrap();
+			let active = || {
+				let stats = client.stats.read().unwrap();
+				(stats.client_stats.active_server, stats.client_stats.connected)
+			};
+			assert!(wait_until(|| active() == (index, true)), "got {:?}", active());
+		};
+
+		then regex r"the primary server sees <(\d+)> <([a-z]+)> requests?" |world, matches, _step| {
+			let count: usize = matches[1].parse().unwrap();
+			let server = world.primary.as_ref().unwrap();
+			assert!(
+				wait_until(|| server.requests(&matches[2]).len() >= count),
+				"got {:?}",
+				server.requests(&matches[2])
+			);
+		};
+
+		then regex r"the client has connected <(\d+)> times" |world, matches, _step| {
+			let count: usize = matches[1].parse().unwrap();
+			let server = world.stratum.as_ref().unwrap();
+			assert!(wait_until(|| server.connections() >= count));
+			assert_eq!(server.connections(), count);
+		};
+
+		then regex r"the miner receives a job at height <(\d+)>" |world, matches, _step| {
+			let height: u64 = matches[1].parse().unwrap();
+			let client = world.client.as_mut().unwrap();
+			assert!(client.wait_for_miner(|m| match *m {
+				MinerMessage::ReceivedJob(h, _, _, _) => h == height,
+				_ => false,
+			}), "got {:?}", client.received);
+		};
+
+		then regex r"the miner receives the seed <(\d+)> for heights <(\d+)> to <(\d+)>" |world, matches, _step| {
+			let seed: u8 = matches[1].parse().unwrap();
+			let epoch = (matches[2].parse().unwrap(), matches[3].parse().unwrap(), [seed; 32]);
+			let client = world.client.as_mut().unwrap();
+			assert!(client.wait_for_miner(|m| match *m {
+				MinerMessage::ReceivedSeed(ref epochs) => epochs.contains(&epoch),
+				_ => false,
+			}), "got {:?}", client.received);
+		};
+
+		then regex r"the miner is told to stop" |world, _matches, _step| {
+			let client = world.client.as_mut().unwrap();
+			assert!(
+				client.wait_for_miner(|m| matches!(*m, MinerMessage::StopJob)),
+				"got {:?}",
+				client.received
+			);
+		};
+
+		then regex r"the client counts <(\d+)> accepted, <(\d+)> rejected and <(\d+)> stale shares?" |world, matches, _step| {
+			let expected: Vec<u32> = matches[1..4].iter().map(|m| m.parse().unwrap()).collect();
+			let client = world.client.as_ref().unwrap();
+			let counts = || {
+				let stats = client.stats.read().unwrap();
+				let s = &stats.mining_stats.solution_stats;
+				vec![s.num_shares_accepted, s.num_rejected, s.num_staled]
+			};
+			assert!(wait_until(|| counts() == expected), "got {:?}", counts());
+		};
+
+		then regex r"the client counts <(\d+)> blocks? found" |world, matches, _step| {
+			let expected: u32 = matches[1].parse().unwrap();
+			let client = world.client.as_ref().unwrap();
+			assert!(wait_until(|| {
+				client.stats.read().unwrap().mining_stats.solution_stats.num_blocks_found == expected
+			}));
+		};
+	});
+}
+
 // Declares a before handler function named `a_before_fn`
 before!(a_before_fn => |_scenario| {
 
@@ -113,7 +497,8 @@ cucumber! {
 	features: "./features", // Path to our feature files
 	world: ::MinerWorld, // The world needs to be the same for steps and the main cucumber call
 	steps: &[
-		miner_test::steps // the `steps!` macro creates a `steps` function in a module
+		miner_test::steps, // the `steps!` macro creates a `steps` function in a module
+		stratum_test::steps
 	],
 	setup: setup, // Optional; called once before everything
 	before: &[
@@ -0,0 +1,45 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Number of hardware threads of the machine
+
+/// Hardware threads the OS reports, 1 where that can't be found out
+pub fn cpu_threads() -> u32 {
+	match os_cpu_threads() {
+		Some(n) if n > 0 => n,
+		_ => 1,
+	}
+}
+
+#[cfg(unix)]
+fn os_cpu_threads() -> Option<u32> {
+	let n = unsafe { ::libc::sysconf(::libc::_SC_NPROCESSORS_ONLN) };
+	if n > 0 {
+		Some(n as u32)
+	} else {
+		None
+	}
+}
+
+#[cfg(windows)]
+fn os_cpu_threads() -> Option<u32> {
+	::std::env::var("NUMBER_OF_PROCESSORS")
+		.ok()
+		.and_then(|n| n.parse().ok())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn os_cpu_threads() -> Option<u32> {
+	None
+}
@@ -23,6 +23,7 @@
 
 extern crate backtrace;
 extern crate byteorder;
+extern crate libc;
 extern crate rand;
 #[macro_use]
 extern crate slog;
@@ -38,7 +39,7 @@ extern crate serde_derive;
 
 // Logging related
 pub mod logger;
-pub use logger::{init_logger, init_test_logger, LOGGER};
+pub use logger::{init_logger, init_test_logger, set_log_levels, LOGGER};
 
 pub mod types;
 pub use types::{LogLevel, LoggingConfig};
@@ -49,3 +50,9 @@ use std::ops::Deref;
 
 mod hex;
 pub use hex::*;
+
+mod cpus;
+pub use cpus::cpu_threads;
+
+mod siphash;
+pub use siphash::{siphash24, siphash_block};
@@ -14,8 +14,9 @@
 //! Logging wrapper to be used throughout all crates in the workspace
 use std::fs::OpenOptions;
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::ops::Deref;
-use slog::{Discard, Drain, Duplicate, Level, LevelFilter, Logger};
+use slog::{Discard, Drain, Duplicate, Level, Logger, OwnedKVList, Record};
 use slog_term;
 use slog_async;
 
@@ -35,6 +36,30 @@ fn convert_log_level(in_level: &LogLevel) -> Level {
 	}
 }
 
+/// Levels the terminal and file drains filter on, can change at runtime
+static STDOUT_LEVEL: AtomicUsize = AtomicUsize::new(0);
+static FILE_LEVEL: AtomicUsize = AtomicUsize::new(0);
+
+/// Like slog's LevelFilter, but reads the level on every record
+struct RuntimeLevelFilter<D> {
+	drain: D,
+	level: &'static AtomicUsize,
+}
+
+impl<D: Drain> Drain for RuntimeLevelFilter<D> {
+	type Ok = Option<D::Ok>;
+	type Err = Option<D::Err>;
+
+	fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+		let level = Level::from_usize(self.level.load(Ordering::Relaxed)).unwrap_or(Level::Trace);
+		if record.level().is_at_least(level) {
+			self.drain.log(record, values).map(Some).map_err(Some)
+		} else {
+			Ok(None)
+		}
+	}
+}
+
 lazy_static! {
 	/// Flag to observe whether logging was explicitly initialised (don't output otherwise)
 	static ref WAS_INIT: Mutex<bool> = Mutex::new(false);
@@ -48,8 +73,7 @@ lazy_static! {
 	pub static ref LOGGER: Logger = {
 		let was_init = WAS_INIT.lock().unwrap().clone();
 		let config = LOGGING_CONFIG.lock().unwrap();
-		let slog_level_stdout = convert_log_level(&config.stdout_log_level);
-		let slog_level_file = convert_log_level(&config.file_log_level);
+		set_log_levels(&config.stdout_log_level, &config.file_log_level);
 		if config.tui_running.is_some() && config.tui_running.unwrap() {
 			let mut tui_running_ref = TUI_RUNNING.lock().unwrap();
 			*tui_running_ref = true;
@@ -58,7 +82,7 @@ lazy_static! {
 		//Terminal output drain
 		let terminal_decorator = slog_term::TermDecorator::new().build();
 		let terminal_drain = slog_term::FullFormat::new(terminal_decorator).build().fuse();
-		let terminal_drain = LevelFilter::new(terminal_drain, slog_level_stdout).fuse();
+		let terminal_drain = RuntimeLevelFilter { drain: terminal_drain, level: &STDOUT_LEVEL }.fuse();
 		let mut terminal_drain = slog_async::Async::new(terminal_drain).build().fuse();
 		if !config.log_to_stdout || !was_init {
 			terminal_drain = slog_async::Async::new(Discard{}).build().fuse();
@@ -77,7 +101,7 @@ lazy_static! {
 
 			let file_decorator = slog_term::PlainDecorator::new(file);
 			let file_drain = slog_term::FullFormat::new(file_decorator).build().fuse();
-			let file_drain = LevelFilter::new(file_drain, slog_level_file).fuse();
+			let file_drain = RuntimeLevelFilter { drain: file_drain, level: &FILE_LEVEL }.fuse();
 			file_drain_final = slog_async::Async::new(file_drain).build().fuse();
 		}
 
@@ -102,6 +126,12 @@ pub fn init_logger(config: Option<LoggingConfig>) {
 	send_panic_to_log();
 }
 
+/// Changes the levels logged to stdout and to the log file
+pub fn set_log_levels(stdout: &LogLevel, file: &LogLevel) {
+	STDOUT_LEVEL.store(convert_log_level(stdout).as_usize(), Ordering::Relaxed);
+	FILE_LEVEL.store(convert_log_level(file).as_usize(), Ordering::Relaxed);
+}
+
 /// Initializes the logger for unit and integration tests
 pub fn init_test_logger() {
 	let mut was_init_ref = WAS_INIT.lock().unwrap();
@@ -0,0 +1,114 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Simple implementation of the siphash 2-4 hashing function from
+//! Jean-Philippe Aumasson and Daniel J. Bernstein, as used by the
+//! Cuck(at)oo Cycle graphs.
+
+// Cuckaroo hashes edges in blocks of 64
+const EDGE_BLOCK_BITS: u8 = 6;
+const EDGE_BLOCK_SIZE: u64 = 1 << EDGE_BLOCK_BITS;
+const EDGE_BLOCK_MASK: u64 = EDGE_BLOCK_SIZE - 1;
+
+/// Implements siphash 2-4 specialized for a 4 u64 array key and a u64 nonce
+#[inline]
+pub fn siphash24(v: &[u64; 4], nonce: u64) -> u64 {
+	let mut siphash = SipHash24::new(v);
+	siphash.hash(nonce);
+	siphash.digest()
+}
+
+/// Builds a block of siphash values by repeatedly hashing from the nonce
+/// truncated to its closest block start, up to the end of the block. Returns
+/// the resulting hash at the nonce's position, xored with the last hash of
+/// the block.
+pub fn siphash_block(v: &[u64; 4], nonce: u64) -> u64 {
+	let nonce0 = nonce & !EDGE_BLOCK_MASK;
+	let mut nonce_hash = [0u64; EDGE_BLOCK_SIZE as usize];
+
+	let mut siphash = SipHash24::new(v);
+	for n in 0..EDGE_BLOCK_SIZE {
+		siphash.hash(nonce0 + n);
+		nonce_hash[n as usize] = siphash.digest();
+	}
+
+	let last = nonce_hash[EDGE_BLOCK_MASK as usize];
+	let pos = (nonce & EDGE_BLOCK_MASK) as usize;
+	if pos == EDGE_BLOCK_MASK as usize {
+		last
+	} else {
+		nonce_hash[pos] ^ last
+	}
+}
+
+/// Siphash state, kept across hashes when building cuckaroo blocks
+struct SipHash24(u64, u64, u64, u64);
+
+impl SipHash24 {
+	#[inline]
+	fn new(v: &[u64; 4]) -> SipHash24 {
+		SipHash24(v[0], v[1], v[2], v[3])
+	}
+
+	#[inline]
+	fn hash(&mut self, nonce: u64) {
+		self.3 ^= nonce;
+		self.round();
+		self.round();
+
+		self.0 ^= nonce;
+		self.2 ^= 0xff;
+
+		for _ in 0..4 {
+			self.round();
+		}
+	}
+
+	#[inline]
+	fn digest(&self) -> u64 {
+		(self.0 ^ self.1) ^ (self.2 ^ self.3)
+	}
+
+	#[inline]
+	fn round(&mut self) {
+		self.0 = self.0.wrapping_add(self.1);
+		self.2 = self.2.wrapping_add(self.3);
+		self.1 = self.1.rotate_left(13);
+		self.3 = self.3.rotate_left(16);
+		self.1 ^= self.0;
+		self.3 ^= self.2;
+		self.0 = self.0.rotate_left(32);
+		self.2 = self.2.wrapping_add(self.1);
+		self.0 = self.0.wrapping_add(self.3);
+		self.1 = self.1.rotate_left(17);
+		self.3 = self.3.rotate_left(21);
+		self.1 ^= self.2;
+		self.3 ^= self.0;
+		self.2 = self.2.rotate_left(32);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Test vectors from the Grin implementation
+	#[test]
+	fn hash_some() {
+		assert_eq!(siphash24(&[1, 2, 3, 4], 10), 928382149599306901);
+		assert_eq!(siphash24(&[1, 2, 3, 4], 111), 10524991083049122233);
+		assert_eq!(siphash24(&[9, 7, 6, 7], 12), 1305683875471634734);
+		assert_eq!(siphash24(&[9, 7, 6, 7], 10), 11589833042187638814);
+	}
+}
@@ -15,7 +15,7 @@
 //! Logging configuration types
 
 /// Log level types, as slog's don't implement serialize
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum LogLevel {
 	/// Critical
 	Critical,
@@ -32,7 +32,7 @@ pub enum LogLevel {
 }
 
 /// Logging config
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct LoggingConfig {
 	/// whether to log to stdout
 	pub log_to_stdout: bool,